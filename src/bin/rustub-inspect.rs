@@ -0,0 +1,101 @@
+//! Print the pages of a database file, decoded as a given kind of page. The database is opened
+//! read-only.
+//!
+//! Usage: rustub-inspect [--as KIND] [--json] [--key-file PATH] <database file> <page id>...
+
+use rustub::inspect::{Inspector, OutputFormat, PageKind};
+use rustub::{Result, RustubError};
+use std::path::PathBuf;
+use std::process;
+
+const USAGE: &str =
+    "usage: rustub-inspect [--as raw|header|table|overflow|fsm|tmp|index] [--json] \
+                     [--key-file PATH] <database file> <page id>...";
+
+struct Args {
+    db_file: String,
+    page_ids: Vec<i32>,
+    kind: PageKind,
+    format: OutputFormat,
+    key_file: Option<PathBuf>,
+}
+
+fn parse_args() -> Result<Args> {
+    let mut kind = PageKind::Raw;
+    let mut format = OutputFormat::Text;
+    let mut key_file = None;
+    let mut positional = Vec::new();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--as" => {
+                let value = args
+                    .next()
+                    .ok_or(RustubError::UntypedError("--as needs a kind"))?;
+                kind = value.parse()?;
+            }
+            "--json" => format = OutputFormat::Json,
+            "--key-file" => {
+                let value = args
+                    .next()
+                    .ok_or(RustubError::UntypedError("--key-file needs a path"))?;
+                key_file = Some(PathBuf::from(value));
+            }
+            _ if arg.starts_with("--") => return Err(RustubError::UntypedError("unknown option")),
+            _ => positional.push(arg),
+        }
+    }
+    if positional.len() < 2 {
+        return Err(RustubError::UntypedError(
+            "expected a database file and page ids",
+        ));
+    }
+    let db_file = positional.remove(0);
+    let page_ids = positional
+        .iter()
+        .map(|pid| pid.parse())
+        .collect::<std::result::Result<_, _>>()
+        .map_err(|_| RustubError::UntypedError("page ids must be integers"))?;
+    Ok(Args {
+        db_file,
+        page_ids,
+        kind,
+        format,
+        key_file,
+    })
+}
+
+fn run(args: Args) -> Result<()> {
+    let inspector = Inspector::open(&args.db_file, args.key_file.as_deref())?;
+    let mut pages = Vec::with_capacity(args.page_ids.len());
+    for pid in args.page_ids {
+        pages.push(inspector.inspect(pid, args.kind)?.render(args.format));
+    }
+    match args.format {
+        OutputFormat::Text => {
+            for (i, page) in pages.iter().enumerate() {
+                if i > 0 {
+                    println!();
+                }
+                print!("{}", page);
+            }
+        }
+        OutputFormat::Json if pages.len() == 1 => println!("{}", pages[0]),
+        OutputFormat::Json => println!("[{}]", pages.join(",")),
+    }
+    Ok(())
+}
+
+fn main() {
+    let args = match parse_args() {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{}\n{}", e, USAGE);
+            process::exit(2);
+        }
+    };
+    if let Err(e) = run(args) {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
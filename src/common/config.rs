@@ -1,5 +1,30 @@
 pub type PageId = i32;
+/// Identifies a frame of the buffer pool
+pub type FrameId = usize;
+/// Identifies a tablespace. It is stored in the high bits of the id of every page in its
+/// tablespace.
+pub type TablespaceId = u8;
+/// Log sequence number, which is the offset of the end of a log record in the log file
+pub type Lsn = u32;
 
-/// Size of data page in byte
+/// Size of data page in byte, unless another page size is chosen when a database is created
 pub const PAGE_SIZE: usize = 4096;
+/// Bounds of the page size of a database, which must also be a power of two
+pub const MIN_PAGE_SIZE: usize = 1024;
+pub const MAX_PAGE_SIZE: usize = 65536;
+/// Default size of a segment file in byte, unless another size is chosen when a database is created
+pub const SEGMENT_SIZE: usize = 1 << 30;
+/// Size of the trailer at the end of every page, which is reserved for the disk manager
+pub const PAGE_TRAILER_SIZE: usize = 8;
+/// Size of the end of every page which page formats keep clear of, so that it can hold the page
+/// trailer, and the nonce and tag of an encrypted page, see `DiskManager::reserved_size`
+pub const PAGE_RESERVED_SIZE: usize = 36;
+/// Size of the part of a page which page formats can use, i.e. the page without its reserved end
+pub const USABLE_PAGE_SIZE: usize = PAGE_SIZE - PAGE_RESERVED_SIZE;
 pub const INVALID_PAGE_ID: PageId = -1;
+/// The first page of a database, which is the header page
+pub const HEADER_PAGE_ID: PageId = 0;
+/// The tablespace which is stored next to the database file, and holds the header page
+pub const DEFAULT_TABLESPACE_ID: TablespaceId = 0;
+/// Number of frames of a buffer pool, unless another size is chosen
+pub const BUFFER_POOL_SIZE: usize = 64;
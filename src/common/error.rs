@@ -1,12 +1,46 @@
+use crate::common::config::PageId;
 use std::fmt::{write, Display, Formatter};
 use std::io::Error;
 
 pub type Result<T> = std::result::Result<T, RustubError>;
 
+/// Where an IO error happens
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IOContext {
+    /// Anything else, e.g. opening a file
+    Other(&'static str),
+    /// Reading or writing the given page of the database file
+    Page(PageId),
+    /// Reading or writing the log file at the given offset
+    Log(u64),
+}
+
+impl Display for IOContext {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IOContext::Other(m) => write!(f, "{}", m),
+            IOContext::Page(pid) => write!(f, "page {}", pid),
+            IOContext::Log(offset) => write!(f, "log offset {}", offset),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum RustubError {
     UntypedError(&'static str),
-    IOError(Error, &'static str),
+    IOError(Error, IOContext),
+    /// The page is accessed before it has been allocated, or after it has been deallocated
+    PageNotAllocated(PageId),
+    /// The page read from disk fails its integrity check, and the reason why
+    PageCorrupted(PageId, &'static str),
+    /// The database file can't be opened, e.g. it has another format or page size
+    IncompatibleDatabase(&'static str),
+    /// A tablespace can't be created or used, and the reason why
+    TablespaceError(&'static str),
+    /// The database can't be encrypted or decrypted, e.g. the key is wrong
+    EncryptionError(&'static str),
+    /// The buffer pool can't serve a request, e.g. every frame is pinned
+    BufferPoolError(&'static str),
     AstNodeVisitError(&'static str),
     UnimplementedError(&'static str),
 }
@@ -14,8 +48,26 @@ pub enum RustubError {
 impl Display for RustubError {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match self {
-            RustubError::IOError(e, m) => {
-                write!(f, "IO Error :: {} :: {}", e, m)
+            RustubError::IOError(e, c) => {
+                write!(f, "IO Error :: {} :: {}", e, c)
+            }
+            RustubError::PageNotAllocated(pid) => {
+                write!(f, "Page Not Allocated :: page {}", pid)
+            }
+            RustubError::PageCorrupted(pid, m) => {
+                write!(f, "Page Corrupted :: page {} :: {}", pid, m)
+            }
+            RustubError::IncompatibleDatabase(m) => {
+                write!(f, "Incompatible Database :: {}", m)
+            }
+            RustubError::TablespaceError(m) => {
+                write!(f, "Tablespace Error :: {}", m)
+            }
+            RustubError::EncryptionError(m) => {
+                write!(f, "Encryption Error :: {}", m)
+            }
+            RustubError::BufferPoolError(m) => {
+                write!(f, "Buffer Pool Error :: {}", m)
             }
             RustubError::UntypedError(m) => {
                 write!(f, "{}", m)
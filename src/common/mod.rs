@@ -1,6 +1,6 @@
 pub mod config;
 pub mod error;
-mod rid;
+pub mod rid;
 
 pub unsafe extern "C" fn memcpy(dest: *mut u8, src: *const u8, n: usize) -> *mut u8 {
     let mut i = 0;
@@ -1 +1,31 @@
-pub struct RecordId {}
+use crate::common::config::PageId;
+use std::fmt::{Display, Formatter};
+
+/// RecordId identifies a tuple of a table: the page it's stored in, and its slot in the page
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct RecordId {
+    page_id: PageId,
+    slot_num: u32,
+}
+
+impl RecordId {
+    pub fn new(page_id: PageId, slot_num: u32) -> Self {
+        RecordId { page_id, slot_num }
+    }
+
+    #[inline]
+    pub fn page_id(&self) -> PageId {
+        self.page_id
+    }
+
+    #[inline]
+    pub fn slot_num(&self) -> u32 {
+        self.slot_num
+    }
+}
+
+impl Display for RecordId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "({}, {})", self.page_id, self.slot_num)
+    }
+}
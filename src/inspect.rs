@@ -0,0 +1,446 @@
+//! Decoding of the pages of a database file, as done by the `rustub-inspect` tool. The database is
+//! opened read-only, so a database in use or a damaged one can be inspected. Corrupted pages are
+//! decoded as they are stored.
+
+use crate::common::config::{PageId, PAGE_SIZE};
+use crate::common::error::Result;
+use crate::storage::disk::PageReader;
+use crate::storage::page::{
+    BasePage, FreeSpaceMapPage, HeaderPage, OverflowPage, Page, TablePage, TmpTuplePage,
+};
+use crate::storage::table::{OverflowPointer, Tuple};
+use crate::RustubError;
+use std::fmt::Write;
+use std::path::Path;
+use std::str::FromStr;
+
+/// What a page is decoded as
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PageKind {
+    /// The bytes of the page, undecoded
+    Raw,
+    Header,
+    Table,
+    Overflow,
+    FreeSpaceMap,
+    Tmp,
+    Index,
+}
+
+impl FromStr for PageKind {
+    type Err = RustubError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "raw" => Ok(PageKind::Raw),
+            "header" => Ok(PageKind::Header),
+            "table" => Ok(PageKind::Table),
+            "overflow" => Ok(PageKind::Overflow),
+            "fsm" => Ok(PageKind::FreeSpaceMap),
+            "tmp" => Ok(PageKind::Tmp),
+            "index" => Ok(PageKind::Index),
+            _ => Err(RustubError::UntypedError(
+                "unknown page kind, expected raw, header, table, overflow, fsm, tmp or index",
+            )),
+        }
+    }
+}
+
+/// How a decoded page is printed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    Text,
+    Json,
+}
+
+/// A field of a decoded page
+#[derive(Debug, Clone, PartialEq)]
+pub enum Field {
+    Int(i64),
+    Bool(bool),
+    Str(String),
+    Bytes(Vec<u8>),
+    List(Vec<PageDump>),
+}
+
+/// A decoded page, or a part of it, as a list of named fields in the order of the page format
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PageDump {
+    fields: Vec<(&'static str, Field)>,
+}
+
+impl PageDump {
+    /// Decode the given page as the given kind of page. Decoding a page as the wrong kind gives
+    /// garbage, but never fails.
+    pub fn decode(pid: PageId, data: &[u8], kind: PageKind) -> Result<Self> {
+        let mut dump = PageDump::default();
+        dump.int("page_id", pid);
+        if kind == PageKind::Raw {
+            dump.push("data", Field::Bytes(data.to_vec()));
+            return Ok(dump);
+        }
+        if kind == PageKind::Index {
+            return Err(RustubError::UnimplementedError(
+                "index pages don't have an on-disk format yet",
+            ));
+        }
+        if data.len() != PAGE_SIZE {
+            return Err(RustubError::UnimplementedError(
+                "only pages of the default page size can be decoded, use the raw kind",
+            ));
+        }
+        let mut base = BasePage::new();
+        base.data_mut().copy_from_slice(data);
+        dump.int("lsn", base.lsn());
+        match kind {
+            PageKind::Header => dump.header(HeaderPage::from_page(base)),
+            PageKind::Table => dump.table(TablePage::from_page(base)),
+            PageKind::Overflow => dump.overflow(OverflowPage::from_page(base)),
+            PageKind::FreeSpaceMap => dump.free_space_map(FreeSpaceMapPage::from_page(base)),
+            PageKind::Tmp => dump.tmp(TmpTuplePage::from_page(base)),
+            PageKind::Raw | PageKind::Index => unreachable!(),
+        }
+        Ok(dump)
+    }
+
+    /// Returns the value of the given field
+    pub fn get(&self, name: &str) -> Option<&Field> {
+        self.fields
+            .iter()
+            .find(|(field, _)| *field == name)
+            .map(|(_, value)| value)
+    }
+
+    /// Print the page in the given format
+    pub fn render(&self, format: OutputFormat) -> String {
+        let mut out = String::new();
+        match format {
+            OutputFormat::Text => self.write_text(&mut out, 0),
+            OutputFormat::Json => self.write_json(&mut out),
+        }
+        out
+    }
+
+    fn header(&mut self, page: HeaderPage) {
+        self.int("next_page_id", page.next_page_id());
+        self.int("record_count", page.record_count());
+        let records = page
+            .records()
+            .into_iter()
+            .map(|(name, root_id)| {
+                let mut record = PageDump::default();
+                record.push("name", Field::Str(name));
+                record.int("root_id", root_id);
+                record
+            })
+            .collect();
+        self.push("records", Field::List(records));
+    }
+
+    fn table(&mut self, page: TablePage) {
+        self.int("prev_page_id", page.prev_page_id());
+        self.int("next_page_id", page.next_page_id());
+        self.int("tuple_count", page.tuple_count());
+        self.int("free_space", page.free_space_remaining() as i64);
+        let mut slots = Vec::new();
+        let mut slot = 0;
+        while let Some(entry) = page.slot(slot) {
+            let mut dump = PageDump::default();
+            dump.int("slot", slot);
+            dump.int("offset", entry.offset as i64);
+            dump.int("size", entry.size as i64);
+            dump.push("deleted", Field::Bool(entry.deleted));
+            dump.push("overflow", Field::Bool(entry.overflow));
+            match page.data().get(entry.offset..entry.offset + entry.size) {
+                Some(data) => {
+                    if entry.overflow {
+                        if let Some(pointer) =
+                            OverflowPointer::from_tuple(&Tuple::new(data.to_vec()))
+                        {
+                            dump.int("overflow_page_id", pointer.first_page_id());
+                            dump.int("overflow_size", pointer.size() as i64);
+                        }
+                    }
+                    dump.push("data", Field::Bytes(data.to_vec()));
+                }
+                None => dump.push("error", Field::Str("tuple out of the page".to_string())),
+            }
+            slots.push(dump);
+            slot += 1;
+        }
+        self.push("slots", Field::List(slots));
+    }
+
+    fn overflow(&mut self, page: OverflowPage) {
+        self.int("next_page_id", page.next_page_id());
+        self.int("size", page.payload().len() as i64);
+        self.push("data", Field::Bytes(page.payload().to_vec()));
+    }
+
+    fn free_space_map(&mut self, page: FreeSpaceMapPage) {
+        self.int("next_page_id", page.next_page_id());
+        self.int("last_page_id", page.last_page_id());
+        self.int("entry_count", page.entry_count() as i64);
+        let entries = page
+            .entries()
+            .into_iter()
+            .map(|(pid, free_space)| {
+                let mut entry = PageDump::default();
+                entry.int("page_id", pid);
+                entry.int("free_space", free_space as i64);
+                entry
+            })
+            .collect();
+        self.push("entries", Field::List(entries));
+    }
+
+    fn tmp(&mut self, page: TmpTuplePage) {
+        self.int("tuple_count", page.tuple_count());
+        self.int("free_space", page.free_space_remaining() as i64);
+        let tuples = page
+            .tuples()
+            .into_iter()
+            .map(|tmp_tuple| {
+                let mut dump = PageDump::default();
+                dump.int("offset", tmp_tuple.offset() as i64);
+                match page.get_tuple(tmp_tuple) {
+                    Some(tuple) => dump.push("data", Field::Bytes(tuple.data().to_vec())),
+                    None => dump.push("error", Field::Str("tuple out of the page".to_string())),
+                }
+                dump
+            })
+            .collect();
+        self.push("tuples", Field::List(tuples));
+    }
+
+    fn int(&mut self, name: &'static str, value: impl Into<i64>) {
+        self.push(name, Field::Int(value.into()));
+    }
+
+    fn push(&mut self, name: &'static str, value: Field) {
+        self.fields.push((name, value));
+    }
+
+    fn write_text(&self, out: &mut String, indent: usize) {
+        for (name, value) in &self.fields {
+            let _ = write!(out, "{:indent$}{}:", "", name, indent = indent);
+            match value {
+                Field::Int(v) => {
+                    let _ = writeln!(out, " {}", v);
+                }
+                Field::Bool(v) => {
+                    let _ = writeln!(out, " {}", v);
+                }
+                Field::Str(v) => {
+                    let _ = writeln!(out, " {:?}", v);
+                }
+                Field::Bytes(v) => {
+                    let _ = writeln!(out, " {} bytes", v.len());
+                    write_hexdump(out, v, indent + 2);
+                }
+                Field::List(items) => {
+                    let _ = writeln!(out, " {} items", items.len());
+                    for (i, item) in items.iter().enumerate() {
+                        let _ = writeln!(out, "{:indent$}[{}]", "", i, indent = indent + 2);
+                        item.write_text(out, indent + 4);
+                    }
+                }
+            }
+        }
+    }
+
+    fn write_json(&self, out: &mut String) {
+        out.push('{');
+        for (i, (name, value)) in self.fields.iter().enumerate() {
+            if i > 0 {
+                out.push(',');
+            }
+            let _ = write!(out, "\"{}\":", name);
+            match value {
+                Field::Int(v) => {
+                    let _ = write!(out, "{}", v);
+                }
+                Field::Bool(v) => {
+                    let _ = write!(out, "{}", v);
+                }
+                Field::Str(v) => write_json_string(out, v),
+                Field::Bytes(v) => {
+                    out.push('"');
+                    for byte in v {
+                        let _ = write!(out, "{:02x}", byte);
+                    }
+                    out.push('"');
+                }
+                Field::List(items) => {
+                    out.push('[');
+                    for (i, item) in items.iter().enumerate() {
+                        if i > 0 {
+                            out.push(',');
+                        }
+                        item.write_json(out);
+                    }
+                    out.push(']');
+                }
+            }
+        }
+        out.push('}');
+    }
+}
+
+/// Write the given bytes 16 per line, with their offset, hex value and printable characters
+fn write_hexdump(out: &mut String, data: &[u8], indent: usize) {
+    for (line, chunk) in data.chunks(16).enumerate() {
+        let _ = write!(out, "{:indent$}{:04x} ", "", line * 16, indent = indent);
+        for i in 0..16 {
+            match chunk.get(i) {
+                Some(byte) => {
+                    let _ = write!(out, " {:02x}", byte);
+                }
+                None => out.push_str("   "),
+            }
+        }
+        out.push_str("  |");
+        for byte in chunk {
+            let c = *byte as char;
+            out.push(if c.is_ascii_graphic() || c == ' ' {
+                c
+            } else {
+                '.'
+            });
+        }
+        out.push_str("|\n");
+    }
+}
+
+fn write_json_string(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+/// Inspector reads and decodes the pages of a database without modifying any of its files
+pub struct Inspector {
+    reader: PageReader,
+}
+
+impl Inspector {
+    /// Open the given database read-only. An encrypted database can only be read with its key file.
+    pub fn open(db_file: &str, key_file: Option<&Path>) -> Result<Self> {
+        Ok(Inspector {
+            reader: PageReader::open(db_file, key_file)?,
+        })
+    }
+
+    /// Returns the size of the pages of the database
+    pub fn page_size(&self) -> usize {
+        self.reader.page_size()
+    }
+
+    /// Read the given page and decode it as the given kind of page. A corrupted page is decoded as
+    /// it's stored, and its checksum field tells why it's corrupted, or is "ok" for a sound page.
+    pub fn inspect(&self, pid: PageId, kind: PageKind) -> Result<PageDump> {
+        let mut data = vec![0; self.page_size()];
+        let corruption = self.reader.read_page_unverified(pid, &mut data)?;
+        let mut dump = PageDump::decode(pid, &data, kind)?;
+        let checksum = Field::Str(corruption.unwrap_or("ok").to_string());
+        // right after the page id
+        dump.fields.insert(1, ("checksum", checksum));
+        Ok(dump)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::common::config::PAGE_SIZE;
+    use crate::inspect::{Field, Inspector, OutputFormat, PageKind};
+    use crate::storage::disk::test::run_test;
+    use crate::storage::disk::{DiskManager, FileBasedDiskManager};
+    use crate::storage::page::{BasePage, Page, TablePage};
+    use crate::storage::table::Tuple;
+    use std::fs;
+    use std::os::unix::fs::FileExt;
+
+    #[test]
+    fn inspect_table_page() {
+        run_test("inspect_table_page", |db_file| {
+            let pid = {
+                let disk = FileBasedDiskManager::new(db_file.clone()).unwrap();
+                let pid = disk.allocate_page().unwrap();
+                let mut page = TablePage::from_page(BasePage::new());
+                page.init(pid, -1);
+                page.insert_tuple(&Tuple::new(b"alpha".to_vec())).unwrap();
+                let rid = page.insert_tuple(&Tuple::new(b"bravo".to_vec())).unwrap();
+                page.mark_delete(rid);
+                disk.write_page(pid, page.data()).unwrap();
+                pid
+            };
+            let before = fs::read(&db_file).unwrap();
+
+            let inspector = Inspector::open(&db_file, None).unwrap();
+            let dump = inspector.inspect(pid, PageKind::Table).unwrap();
+            assert_eq!(dump.get("checksum"), Some(&Field::Str("ok".to_string())));
+            assert_eq!(dump.get("tuple_count"), Some(&Field::Int(2)));
+            let slots = match dump.get("slots") {
+                Some(Field::List(slots)) => slots,
+                _ => panic!("no slots"),
+            };
+            assert_eq!(slots[0].get("data"), Some(&Field::Bytes(b"alpha".to_vec())));
+            assert_eq!(slots[1].get("deleted"), Some(&Field::Bool(true)));
+
+            let text = dump.render(OutputFormat::Text);
+            assert!(text.contains("tuple_count: 2"));
+            assert!(text.contains("|alpha|"));
+            let json = dump.render(OutputFormat::Json);
+            assert!(json.starts_with(&format!("{{\"page_id\":{},\"checksum\":\"ok\",", pid)));
+            assert!(json.contains("\"data\":\"616c706861\""));
+            assert!(inspector.inspect(pid, PageKind::Index).is_err());
+
+            drop(inspector);
+            assert_eq!(fs::read(&db_file).unwrap(), before);
+        })
+    }
+
+    #[test]
+    fn inspect_corrupted_page() {
+        run_test("inspect_corrupted_page", |db_file| {
+            let disk = FileBasedDiskManager::new(db_file.clone()).unwrap();
+            let pid = disk.allocate_page().unwrap();
+            let mut page = TablePage::from_page(BasePage::new());
+            page.init(pid, -1);
+            page.insert_tuple(&Tuple::new(b"alpha".to_vec())).unwrap();
+            disk.write_page(pid, page.data()).unwrap();
+            drop(disk);
+
+            // flip a bit of the page id the page starts with, after the header of the database file
+            let file = fs::File::options().write(true).open(&db_file).unwrap();
+            let offset = (pid as u64 + 1) * PAGE_SIZE as u64;
+            file.write_all_at(&[(pid as u8) ^ 0x10], offset + 3)
+                .unwrap();
+
+            let inspector = Inspector::open(&db_file, None).unwrap();
+            let dump = inspector.inspect(pid, PageKind::Raw).unwrap();
+            let checksum = Field::Str("checksum mismatch".to_string());
+            assert_eq!(dump.get("checksum"), Some(&checksum));
+            match dump.get("data") {
+                Some(Field::Bytes(data)) => assert_eq!(data[3], (pid as u8) ^ 0x10),
+                _ => panic!("no data"),
+            }
+            // the rest of the page still decodes
+            let dump = inspector.inspect(pid, PageKind::Table).unwrap();
+            assert_eq!(dump.get("checksum"), Some(&checksum));
+            assert_eq!(dump.get("tuple_count"), Some(&Field::Int(1)));
+        })
+    }
+}
@@ -3,6 +3,7 @@ mod common;
 mod concurrency;
 mod deprecated;
 mod execution;
+pub mod inspect;
 mod recovery;
 mod storage;
 #[cfg(test)]
@@ -10,7 +11,7 @@ mod tests;
 mod tiny_planner;
 mod types;
 
-use common::error::*;
+pub use common::error::{IOContext, Result, RustubError};
 
 #[macro_use]
 extern crate log;
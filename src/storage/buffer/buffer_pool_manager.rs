@@ -0,0 +1,780 @@
+use crate::common::config::{
+    FrameId, PageId, BUFFER_POOL_SIZE, INVALID_PAGE_ID, PAGE_RESERVED_SIZE, PAGE_SIZE,
+};
+use crate::common::error::Result;
+use crate::storage::buffer::page_guard::{ReadPageGuard, WritePageGuard};
+use crate::storage::buffer::replacer::{Replacer, ReplacerPolicy, ReplacerStats};
+use crate::storage::buffer::ring::BufferRing;
+use crate::storage::buffer::snapshot::{BufferPoolSnapshot, FrameSnapshot, PageType};
+use crate::storage::disk::DiskManager;
+use crate::storage::page::{BasePage, Page};
+use crate::RustubError;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex, MutexGuard, RwLock};
+use std::time::Instant;
+
+/// Options for creating a `BufferPoolManager`
+#[derive(Debug, Clone)]
+pub struct BufferPoolOptions {
+    /// Number of frames
+    pub pool_size: usize,
+    /// How the page to evict is picked when every frame is used
+    pub replacer: ReplacerPolicy,
+}
+
+impl Default for BufferPoolOptions {
+    fn default() -> Self {
+        BufferPoolOptions {
+            pool_size: BUFFER_POOL_SIZE,
+            replacer: ReplacerPolicy::default(),
+        }
+    }
+}
+
+/// Book-keeping of a frame, which is only touched under the latch of the buffer pool
+#[derive(Debug, Clone, Copy)]
+struct FrameMeta {
+    /// The page held by the frame, or INVALID_PAGE_ID if the frame is free
+    page_id: PageId,
+    pin_count: usize,
+    /// True if the page has been modified since it has been read from or written to disk
+    is_dirty: bool,
+    /// When the page has become dirty, on the dirty clock of the pool
+    dirtied_at: u64,
+    /// When the page has last been pinned, or None if the frame is free
+    last_access: Option<Instant>,
+    /// The type the page has last been viewed as, see `PageType`
+    page_type: PageType,
+}
+
+struct PoolState {
+    meta: Vec<FrameMeta>,
+    /// The frame of every page in the pool
+    page_table: HashMap<PageId, FrameId>,
+    /// Frames which don't hold any page
+    free_list: VecDeque<FrameId>,
+    /// Picks the page to evict when a frame is needed and there is no free frame
+    replacer: Box<dyn Replacer>,
+    /// Ticks every time a clean page becomes dirty
+    dirty_clock: u64,
+}
+
+/// BufferPoolManager caches pages of a disk manager in a fixed number of frames. A page is pinned
+/// by `new_page` and `fetch_page`, and stays in its frame until it's unpinned as many times as it
+/// has been pinned. Once unpinned, it can be evicted to make room for another page, and is written
+/// back first if it's dirty. Which unpinned page is evicted is up to the replacement policy, see
+/// `ReplacerPolicy`.
+///
+/// The content of a page is protected by the latch of its frame, i.e. the RwLock returned when the
+/// page is pinned. A page must only be latched while it's pinned. The pin counts and dirty flags
+/// are kept by the pool under its own latch rather than in the pages, so pinning a page never waits
+/// for another thread which holds the latch of the page.
+///
+/// THREAD SAFETY: YES
+pub struct BufferPoolManager {
+    disk: Arc<dyn DiskManager>,
+    frames: Vec<RwLock<BasePage>>,
+    state: Mutex<PoolState>,
+}
+
+impl BufferPoolManager {
+    /// Create a buffer pool of `pool_size` frames over the given disk manager, whose pages must
+    /// have the default page size.
+    pub fn new(pool_size: usize, disk: Arc<dyn DiskManager>) -> Result<Self> {
+        let options = BufferPoolOptions {
+            pool_size,
+            ..BufferPoolOptions::default()
+        };
+        BufferPoolManager::with_options(disk, options)
+    }
+
+    /// Create a buffer pool over the given disk manager with the given options. Fails if the pages
+    /// of the disk manager don't have the default page size, which is the only one the page
+    /// formats support, or if it reserves more than `PAGE_RESERVED_SIZE` bytes of every page.
+    pub fn with_options(disk: Arc<dyn DiskManager>, options: BufferPoolOptions) -> Result<Self> {
+        let pool_size = options.pool_size;
+        assert!(pool_size > 0);
+        if disk.page_size() != PAGE_SIZE {
+            return Err(RustubError::IncompatibleDatabase(
+                "the buffer pool only supports the default page size",
+            ));
+        }
+        if disk.reserved_size() > PAGE_RESERVED_SIZE {
+            return Err(RustubError::IncompatibleDatabase(
+                "the disk manager reserves more of every page than pages keep clear of",
+            ));
+        }
+        let meta = FrameMeta {
+            page_id: INVALID_PAGE_ID,
+            pin_count: 0,
+            is_dirty: false,
+            dirtied_at: 0,
+            last_access: None,
+            page_type: PageType::Unknown,
+        };
+        Ok(BufferPoolManager {
+            disk,
+            frames: (0..pool_size)
+                .map(|_| RwLock::new(BasePage::new()))
+                .collect(),
+            state: Mutex::new(PoolState {
+                meta: vec![meta; pool_size],
+                page_table: HashMap::new(),
+                free_list: (0..pool_size).collect(),
+                replacer: options.replacer.create(pool_size),
+                dirty_clock: 0,
+            }),
+        })
+    }
+
+    /// Returns the number of frames
+    #[inline]
+    pub fn pool_size(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Returns the disk manager the pages are read from and written to
+    #[inline]
+    pub fn disk(&self) -> &Arc<dyn DiskManager> {
+        &self.disk
+    }
+
+    /// Allocate a new page on disk and pin it in a frame, filled with zeros. Fails if every frame
+    /// is pinned.
+    ///
+    /// THREAD SAFETY: YES
+    pub fn new_page(&self) -> Result<(PageId, &RwLock<BasePage>)> {
+        let mut state = self.state.lock().unwrap();
+        let frame_id = self.take_frame(&mut state)?;
+        let pid = match self.disk.allocate_page() {
+            Ok(pid) => pid,
+            Err(e) => {
+                state.free_list.push_front(frame_id);
+                return Err(e);
+            }
+        };
+        Ok((pid, self.init_page(&mut state, frame_id, pid)))
+    }
+
+    /// Pin a page which has just been allocated on disk in a frame, filled with zeros. Fails if
+    /// every frame is pinned, in which case the caller still owns the page.
+    ///
+    /// THREAD SAFETY: YES
+    pub(crate) fn new_page_with_id(&self, pid: PageId) -> Result<&RwLock<BasePage>> {
+        let mut state = self.state.lock().unwrap();
+        let frame_id = self.take_frame(&mut state)?;
+        Ok(self.init_page(&mut state, frame_id, pid))
+    }
+
+    /// Pin the given page, reading it from disk unless it's already in the pool. Fails if the page
+    /// can't be read, or if it's not in the pool and every frame is pinned.
+    ///
+    /// THREAD SAFETY: YES
+    pub fn fetch_page(&self, pid: PageId) -> Result<&RwLock<BasePage>> {
+        let mut state = self.state.lock().unwrap();
+        if let Some(&frame_id) = state.page_table.get(&pid) {
+            BufferPoolManager::pin(&mut state, frame_id);
+            return Ok(&self.frames[frame_id]);
+        }
+        let frame_id = self.take_frame(&mut state)?;
+        self.load_page(&mut state, frame_id, pid)?;
+        Ok(&self.frames[frame_id])
+    }
+
+    /// Like `fetch_page`, except that a page which isn't in the pool is read into a frame of the
+    /// given ring, reusing the frame of the oldest page of the ring if it's not pinned. A scan
+    /// going through the ring uses at most as many frames as the ring holds, instead of evicting
+    /// the working set of the pool.
+    ///
+    /// THREAD SAFETY: YES
+    pub fn fetch_page_in_ring(
+        &self,
+        pid: PageId,
+        ring: &mut BufferRing,
+    ) -> Result<&RwLock<BasePage>> {
+        let mut state = self.state.lock().unwrap();
+        if let Some(&frame_id) = state.page_table.get(&pid) {
+            BufferPoolManager::pin(&mut state, frame_id);
+            return Ok(&self.frames[frame_id]);
+        }
+        let frame_id = match ring.oldest() {
+            Some((frame_id, old_pid))
+                if state.meta[frame_id].page_id == old_pid
+                    && state.meta[frame_id].pin_count == 0 =>
+            {
+                state.replacer.remove(frame_id);
+                self.evict_frame(&mut state, frame_id)?;
+                frame_id
+            }
+            _ => self.take_frame(&mut state)?,
+        };
+        self.load_page(&mut state, frame_id, pid)?;
+        ring.push(frame_id, pid);
+        Ok(&self.frames[frame_id])
+    }
+
+    /// Like `new_page`, and returns the new page latched for writing. The page is unpinned when
+    /// the guard is dropped.
+    ///
+    /// THREAD SAFETY: YES
+    pub fn new_page_guarded(&self) -> Result<WritePageGuard<'_>> {
+        let (pid, page) = self.new_page()?;
+        Ok(WritePageGuard::new(self, pid, page.write().unwrap()))
+    }
+
+    /// Like `fetch_page`, and returns the page latched for reading. The page is unpinned when the
+    /// guard is dropped.
+    ///
+    /// THREAD SAFETY: YES
+    pub fn fetch_page_read(&self, pid: PageId) -> Result<ReadPageGuard<'_>> {
+        let page = self.fetch_page(pid)?;
+        Ok(ReadPageGuard::new(self, pid, page.read().unwrap()))
+    }
+
+    /// Like `fetch_page_in_ring`, and returns the page latched for reading
+    ///
+    /// THREAD SAFETY: YES
+    pub fn fetch_page_read_in_ring(
+        &self,
+        pid: PageId,
+        ring: &mut BufferRing,
+    ) -> Result<ReadPageGuard<'_>> {
+        let page = self.fetch_page_in_ring(pid, ring)?;
+        Ok(ReadPageGuard::new(self, pid, page.read().unwrap()))
+    }
+
+    /// Like `fetch_page`, and returns the page latched for writing. The page is unpinned, and
+    /// marked dirty if it has been modified, when the guard is dropped.
+    ///
+    /// THREAD SAFETY: YES
+    pub fn fetch_page_write(&self, pid: PageId) -> Result<WritePageGuard<'_>> {
+        let page = self.fetch_page(pid)?;
+        Ok(WritePageGuard::new(self, pid, page.write().unwrap()))
+    }
+
+    /// Unpin the given page, which has been modified if `is_dirty` is set. Returns false if the
+    /// page isn't in the pool or isn't pinned.
+    ///
+    /// THREAD SAFETY: YES
+    pub fn unpin_page(&self, pid: PageId, is_dirty: bool) -> bool {
+        self.unpin_page_as(pid, is_dirty, None)
+    }
+
+    /// Like `unpin_page`, and records the type the page has been viewed as while it was pinned,
+    /// if any
+    pub(crate) fn unpin_page_as(
+        &self,
+        pid: PageId,
+        is_dirty: bool,
+        page_type: Option<PageType>,
+    ) -> bool {
+        let mut state = self.state.lock().unwrap();
+        let frame_id = match state.page_table.get(&pid) {
+            Some(&frame_id) => frame_id,
+            None => return false,
+        };
+        if state.meta[frame_id].pin_count == 0 {
+            return false;
+        }
+        if let Some(page_type) = page_type {
+            state.meta[frame_id].page_type = page_type;
+        }
+        if is_dirty {
+            BufferPoolManager::mark_dirty(&mut state, frame_id);
+        }
+        BufferPoolManager::unpin(&mut state, frame_id);
+        true
+    }
+
+    /// Write the given page to disk, whether it's dirty or not, once the log is durable up to the
+    /// LSN of the page. Returns false if the page isn't in the pool. The caller must not hold the
+    /// latch of the page for writing.
+    ///
+    /// THREAD SAFETY: YES
+    pub fn flush_page(&self, pid: PageId) -> Result<bool> {
+        self.write_back(pid, false)
+    }
+
+    /// Write up to `budget` dirty pages to disk, the ones which have been dirty for the longest
+    /// time first. Pages which are latched for writing, or whose LSN is beyond the durable end of
+    /// the log, are skipped. Returns the number of pages written.
+    ///
+    /// THREAD SAFETY: YES
+    pub fn flush_oldest(&self, budget: usize) -> Result<usize> {
+        let mut dirty: Vec<(u64, PageId)> = {
+            let state = self.state.lock().unwrap();
+            state
+                .meta
+                .iter()
+                .filter(|meta| meta.is_dirty)
+                .map(|meta| (meta.dirtied_at, meta.page_id))
+                .collect()
+        };
+        dirty.sort_unstable();
+        let mut written = 0;
+        for (_, pid) in dirty {
+            if written == budget {
+                break;
+            }
+            if self.write_back(pid, true)? {
+                written += 1;
+            }
+        }
+        Ok(written)
+    }
+
+    /// Write every dirty page to disk, each once the log is durable up to its LSN, and sync the
+    /// disk manager. The caller must not hold the latch of any page for writing.
+    ///
+    /// THREAD SAFETY: YES
+    pub fn flush_all(&self) -> Result<()> {
+        self.flush_dirty_pages()?;
+        self.disk.sync()
+    }
+
+    /// Write every dirty page to disk, without syncing the disk manager
+    ///
+    /// THREAD SAFETY: YES
+    pub(crate) fn flush_dirty_pages(&self) -> Result<()> {
+        let dirty: Vec<PageId> = {
+            let state = self.state.lock().unwrap();
+            state
+                .meta
+                .iter()
+                .filter(|meta| meta.is_dirty)
+                .map(|meta| meta.page_id)
+                .collect()
+        };
+        for pid in dirty {
+            self.flush_page(pid)?;
+        }
+        Ok(())
+    }
+
+    /// Drop the given page from the pool without writing it, and deallocate it on disk. Returns
+    /// false if the page is pinned, in which case nothing is done.
+    ///
+    /// THREAD SAFETY: YES
+    pub fn delete_page(&self, pid: PageId) -> Result<bool> {
+        let mut state = self.state.lock().unwrap();
+        if let Some(&frame_id) = state.page_table.get(&pid) {
+            if state.meta[frame_id].pin_count > 0 {
+                return Ok(false);
+            }
+            state.page_table.remove(&pid);
+            state.replacer.remove(frame_id);
+            state.meta[frame_id].page_id = INVALID_PAGE_ID;
+            state.meta[frame_id].is_dirty = false;
+            state.meta[frame_id].last_access = None;
+            state.meta[frame_id].page_type = PageType::Unknown;
+            self.frames[frame_id]
+                .write()
+                .unwrap()
+                .set_page_id(INVALID_PAGE_ID);
+            state.free_list.push_back(frame_id);
+        }
+        self.disk.deallocate_page(pid)?;
+        Ok(true)
+    }
+
+    /// Returns the pin count of the given page, or None if it isn't in the pool
+    pub fn pin_count(&self, pid: PageId) -> Option<usize> {
+        let state = self.state.lock().unwrap();
+        let frame_id = *state.page_table.get(&pid)?;
+        Some(state.meta[frame_id].pin_count)
+    }
+
+    /// Returns true if the given page is in the pool and has been modified since it has been
+    /// written to disk
+    pub fn is_dirty(&self, pid: PageId) -> bool {
+        let state = self.state.lock().unwrap();
+        match state.page_table.get(&pid) {
+            Some(&frame_id) => state.meta[frame_id].is_dirty,
+            None => false,
+        }
+    }
+
+    /// Returns the number of dirty pages in the pool
+    pub fn num_dirty(&self) -> usize {
+        let state = self.state.lock().unwrap();
+        state.meta.iter().filter(|meta| meta.is_dirty).count()
+    }
+
+    /// Take a snapshot of every frame of the pool, together with the hits, misses and evictions so
+    /// far. The type of a page is the one it was viewed as by the last page guard released.
+    ///
+    /// THREAD SAFETY: YES
+    pub fn snapshot(&self) -> BufferPoolSnapshot {
+        let (meta, stats) = {
+            let state = self.state.lock().unwrap();
+            (state.meta.clone(), state.replacer.stats())
+        };
+        let frames = meta
+            .iter()
+            .enumerate()
+            .map(|(frame_id, meta)| FrameSnapshot {
+                frame_id,
+                page_id: meta.page_id,
+                page_type: (meta.page_id != INVALID_PAGE_ID).then_some(meta.page_type),
+                pin_count: meta.pin_count,
+                is_dirty: meta.is_dirty,
+                last_access: meta.last_access,
+            })
+            .collect();
+        BufferPoolSnapshot {
+            taken_at: Instant::now(),
+            frames,
+            stats,
+        }
+    }
+
+    /// Returns the hits, misses and evictions of the pool so far, as seen by its replacer
+    pub fn replacer_stats(&self) -> ReplacerStats {
+        self.state.lock().unwrap().replacer.stats()
+    }
+
+    /// Returns a frame which doesn't hold any page: a free one, or else the one of the page picked
+    /// by the replacer, which is written back first if it's dirty.
+    fn take_frame(&self, state: &mut MutexGuard<PoolState>) -> Result<FrameId> {
+        if let Some(frame_id) = state.free_list.pop_front() {
+            return Ok(frame_id);
+        }
+        let frame_id = state
+            .replacer
+            .evict()
+            .ok_or(RustubError::BufferPoolError("every frame is pinned"))?;
+        self.evict_frame(state, frame_id)?;
+        Ok(frame_id)
+    }
+
+    /// Drop the page of the given unpinned frame from the pool, writing it back first if it's
+    /// dirty, once the log is durable up to the LSN of the page. The replacer must not track the
+    /// frame anymore.
+    fn evict_frame(&self, state: &mut PoolState, frame_id: FrameId) -> Result<()> {
+        let meta = state.meta[frame_id];
+        if meta.is_dirty {
+            // nobody holds the latch of an unpinned page
+            let page = self.frames[frame_id].read().unwrap();
+            let result = self
+                .disk
+                .flush_log(page.lsn())
+                .and_then(|_| self.disk.write_page(meta.page_id, page.data()));
+            if let Err(e) = result {
+                // the page stays in the pool
+                state.replacer.record_access(frame_id, meta.page_id);
+                state.replacer.set_evictable(frame_id, true);
+                return Err(e);
+            }
+        }
+        state.page_table.remove(&meta.page_id);
+        state.meta[frame_id] = FrameMeta {
+            page_id: INVALID_PAGE_ID,
+            pin_count: 0,
+            is_dirty: false,
+            dirtied_at: 0,
+            last_access: None,
+            page_type: PageType::Unknown,
+        };
+        Ok(())
+    }
+
+    /// Read the given page from disk into the given free frame, and pin it. The frame is freed if
+    /// the page can't be read.
+    fn load_page(&self, state: &mut PoolState, frame_id: FrameId, pid: PageId) -> Result<()> {
+        let mut page = self.frames[frame_id].write().unwrap();
+        if let Err(e) = self.disk.read_page(pid, page.data_mut()) {
+            page.set_page_id(INVALID_PAGE_ID);
+            state.free_list.push_front(frame_id);
+            return Err(e);
+        }
+        page.set_page_id(pid);
+        drop(page);
+        BufferPoolManager::install(state, frame_id, pid, false);
+        Ok(())
+    }
+
+    /// Write the given page to disk. A write in the background is skipped, and false returned, if
+    /// the page isn't dirty, is latched for writing or has a LSN beyond the durable end of the log.
+    /// Otherwise, the log is flushed up to the LSN of the page first.
+    fn write_back(&self, pid: PageId, background: bool) -> Result<bool> {
+        let mut state = self.state.lock().unwrap();
+        let frame_id = match state.page_table.get(&pid) {
+            Some(&frame_id) => frame_id,
+            None => return Ok(false),
+        };
+        if background && !state.meta[frame_id].is_dirty {
+            return Ok(false);
+        }
+        // pinned, the page stays in its frame while it's written without the latch of the pool.
+        // It doesn't count as an access to the page.
+        state.meta[frame_id].pin_count += 1;
+        state.replacer.set_evictable(frame_id, false);
+        drop(state);
+
+        let result = self.write_latched(frame_id, pid, background);
+        let mut state = self.state.lock().unwrap();
+        BufferPoolManager::unpin(&mut state, frame_id);
+        result
+    }
+
+    /// Latch the page of the given pinned frame for reading and write it to disk, see `write_back`
+    fn write_latched(&self, frame_id: FrameId, pid: PageId, background: bool) -> Result<bool> {
+        let page = match background {
+            true => match self.frames[frame_id].try_read() {
+                Ok(page) => page,
+                Err(_) => return Ok(false),
+            },
+            false => self.frames[frame_id].read().unwrap(),
+        };
+        if background && page.lsn() > self.disk.flushed_lsn() {
+            return Ok(false);
+        }
+        // write-ahead logging: the page must not reach the disk before its log records
+        self.disk.flush_log(page.lsn())?;
+        // nobody modifies the page while it's latched, so it's clean once written
+        let was_dirty = {
+            let mut state = self.state.lock().unwrap();
+            std::mem::replace(&mut state.meta[frame_id].is_dirty, false)
+        };
+        let result = self.disk.write_page(pid, page.data());
+        if result.is_err() {
+            self.state.lock().unwrap().meta[frame_id].is_dirty |= was_dirty;
+        }
+        result.map(|_| true)
+    }
+
+    /// Make the given free frame hold the given new page, filled with zeros
+    fn init_page(
+        &self,
+        state: &mut PoolState,
+        frame_id: FrameId,
+        pid: PageId,
+    ) -> &RwLock<BasePage> {
+        let mut page = self.frames[frame_id].write().unwrap();
+        page.data_mut().fill(0u8);
+        page.set_page_id(pid);
+        drop(page);
+        // the page on disk may still hold the content of a deallocated page
+        BufferPoolManager::install(state, frame_id, pid, true);
+        &self.frames[frame_id]
+    }
+
+    /// Make the given frame hold the given page, pinned once
+    fn install(state: &mut PoolState, frame_id: FrameId, pid: PageId, is_dirty: bool) {
+        state.meta[frame_id] = FrameMeta {
+            page_id: pid,
+            pin_count: 1,
+            is_dirty: false,
+            dirtied_at: 0,
+            last_access: Some(Instant::now()),
+            page_type: PageType::of(pid),
+        };
+        if is_dirty {
+            BufferPoolManager::mark_dirty(state, frame_id);
+        }
+        state.page_table.insert(pid, frame_id);
+        state.replacer.record_access(frame_id, pid);
+        state.replacer.set_evictable(frame_id, false);
+    }
+
+    fn mark_dirty(state: &mut PoolState, frame_id: FrameId) {
+        if !state.meta[frame_id].is_dirty {
+            state.dirty_clock += 1;
+            state.meta[frame_id].is_dirty = true;
+            state.meta[frame_id].dirtied_at = state.dirty_clock;
+        }
+    }
+
+    /// Pin the page of the given frame once more. It counts as an access to the page.
+    fn pin(state: &mut PoolState, frame_id: FrameId) {
+        let pid = state.meta[frame_id].page_id;
+        state.meta[frame_id].pin_count += 1;
+        state.meta[frame_id].last_access = Some(Instant::now());
+        state.replacer.record_access(frame_id, pid);
+        state.replacer.set_evictable(frame_id, false);
+    }
+
+    fn unpin(state: &mut PoolState, frame_id: FrameId) {
+        state.meta[frame_id].pin_count -= 1;
+        if state.meta[frame_id].pin_count == 0 {
+            state.replacer.set_evictable(frame_id, true);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::common::config::PAGE_SIZE;
+    use crate::storage::buffer::{BufferPoolManager, BufferPoolOptions, ReplacerPolicy};
+    use crate::storage::disk::{DiskManager, InMemDiskManager};
+    use crate::storage::page::Page;
+    use crate::RustubError;
+    use std::sync::Arc;
+
+    #[test]
+    fn reject_other_page_size() {
+        let disk = Arc::new(InMemDiskManager::with_page_size(2 * PAGE_SIZE));
+        assert!(matches!(
+            BufferPoolManager::new(3, disk),
+            Err(RustubError::IncompatibleDatabase(_))
+        ));
+    }
+
+    #[test]
+    fn evict_and_write_back() {
+        let disk = Arc::new(InMemDiskManager::new());
+        let bpm = BufferPoolManager::new(3, disk.clone()).unwrap();
+        let mut pids = Vec::new();
+        for i in 0..3 {
+            let (pid, page) = bpm.new_page().unwrap();
+            assert_eq!(page.read().unwrap().page_id(), pid);
+            assert!(page.read().unwrap().data().iter().all(|b| *b == 0));
+            page.write().unwrap().data_mut()[..4].copy_from_slice(&[i as u8 + 1; 4]);
+            pids.push(pid);
+        }
+        // every frame is pinned
+        assert!(matches!(
+            bpm.new_page(),
+            Err(RustubError::BufferPoolError(_))
+        ));
+        assert!(matches!(
+            bpm.fetch_page(pids[0] + 100),
+            Err(RustubError::BufferPoolError(_))
+        ));
+
+        assert!(bpm.unpin_page(pids[0], true));
+        assert!(!bpm.unpin_page(pids[0], false));
+        let (fourth, _) = bpm.new_page().unwrap();
+        // the first page has been written back to make room
+        assert_eq!(bpm.pin_count(pids[0]), None);
+        let mut buf = [0u8; PAGE_SIZE];
+        disk.read_page(pids[0], &mut buf[..]).unwrap();
+        assert_eq!(buf[..4], [1u8; 4]);
+
+        assert!(bpm.unpin_page(fourth, false));
+        let page = bpm.fetch_page(pids[0]).unwrap();
+        assert_eq!(page.read().unwrap().data()[..4], [1u8; 4]);
+        assert_eq!(bpm.pin_count(fourth), None);
+        for pid in &pids {
+            assert!(bpm.unpin_page(*pid, true));
+        }
+    }
+
+    #[test]
+    fn fetch_pinned_page_once() {
+        let disk = Arc::new(InMemDiskManager::new());
+        let bpm = BufferPoolManager::new(2, disk.clone()).unwrap();
+        let (pid, page) = bpm.new_page().unwrap();
+        page.write().unwrap().data_mut()[..5].copy_from_slice(b"hello");
+        assert!(bpm.unpin_page(pid, true));
+        assert!(bpm.is_dirty(pid));
+        assert!(bpm.flush_page(pid).unwrap());
+        assert!(!bpm.is_dirty(pid));
+        assert!(!bpm.flush_page(pid + 100).unwrap());
+
+        // the page is still in the pool, so it isn't read again
+        let reads = disk.metrics().page_reads.count;
+        let page = bpm.fetch_page(pid).unwrap();
+        let again = bpm.fetch_page(pid).unwrap();
+        assert!(std::ptr::eq(page, again));
+        assert_eq!(bpm.pin_count(pid), Some(2));
+        assert_eq!(disk.metrics().page_reads.count, reads);
+        assert_eq!(&page.read().unwrap().data()[..5], b"hello");
+
+        // a pinned page can't be deleted
+        assert!(!bpm.delete_page(pid).unwrap());
+        assert!(bpm.unpin_page(pid, false));
+        assert!(bpm.unpin_page(pid, false));
+        assert!(bpm.delete_page(pid).unwrap());
+        assert_eq!(bpm.pin_count(pid), None);
+        assert!(matches!(
+            bpm.delete_page(pid),
+            Err(RustubError::PageNotAllocated(_))
+        ));
+    }
+
+    #[test]
+    fn flush_all_pages() {
+        let disk = Arc::new(InMemDiskManager::new());
+        let bpm = BufferPoolManager::new(4, disk.clone()).unwrap();
+        let mut pids = Vec::new();
+        for i in 0..4 {
+            let (pid, page) = bpm.new_page().unwrap();
+            page.write().unwrap().data_mut()[0] = i + 1;
+            assert!(bpm.unpin_page(pid, true));
+            pids.push(pid);
+        }
+        bpm.flush_all().unwrap();
+        let mut buf = [0u8; PAGE_SIZE];
+        for (i, pid) in pids.iter().enumerate() {
+            assert!(!bpm.is_dirty(*pid));
+            disk.read_page(*pid, &mut buf[..]).unwrap();
+            assert_eq!(buf[0], i as u8 + 1);
+        }
+        // clean pages aren't written again
+        let writes = disk.metrics().page_writes.count;
+        for pid in &pids {
+            bpm.fetch_page(*pid).unwrap();
+            bpm.flush_all().unwrap();
+            assert!(bpm.unpin_page(*pid, false));
+        }
+        assert_eq!(disk.metrics().page_writes.count, writes);
+    }
+
+    #[test]
+    fn write_ahead_of_log() {
+        let disk = Arc::new(InMemDiskManager::new());
+        let bpm = BufferPoolManager::new(1, disk.clone()).unwrap();
+        let (pid, page) = bpm.new_page().unwrap();
+        let mut guard = page.write().unwrap();
+        guard.data_mut()[..5].copy_from_slice(b"hello");
+        guard.set_lsn(10);
+        drop(guard);
+        assert!(bpm.unpin_page(pid, true));
+
+        // the log records of the page aren't in the log, so the page can't be written
+        let writes = disk.metrics().page_writes.count;
+        assert!(matches!(
+            bpm.flush_page(pid),
+            Err(RustubError::IOError(_, _))
+        ));
+        assert!(bpm.flush_all().is_err());
+        assert!(matches!(bpm.new_page(), Err(RustubError::IOError(_, _))));
+        assert_eq!(bpm.pin_count(pid), Some(0));
+        assert!(bpm.is_dirty(pid));
+        assert_eq!(disk.metrics().page_writes.count, writes);
+
+        // once they are, the page is evicted
+        disk.write_log(&[0u8; 10][..]).unwrap();
+        let (other, _) = bpm.new_page().unwrap();
+        assert_eq!(bpm.pin_count(pid), None);
+        let mut buf = [0u8; PAGE_SIZE];
+        disk.read_page(pid, &mut buf[..]).unwrap();
+        assert_eq!(&buf[..5], b"hello");
+        assert!(bpm.unpin_page(other, false));
+    }
+
+    #[test]
+    fn evict_with_lru_k() {
+        let disk = Arc::new(InMemDiskManager::new());
+        let options = BufferPoolOptions {
+            pool_size: 2,
+            replacer: ReplacerPolicy::LruK(2),
+        };
+        let bpm = BufferPoolManager::with_options(disk, options).unwrap();
+        let (hot, _) = bpm.new_page().unwrap();
+        assert!(bpm.unpin_page(hot, false));
+        bpm.fetch_page(hot).unwrap();
+        assert!(bpm.unpin_page(hot, false));
+        let (scanned, _) = bpm.new_page().unwrap();
+        assert!(bpm.unpin_page(scanned, false));
+
+        // LRU would evict the hot page, which has been used less recently
+        let (third, _) = bpm.new_page().unwrap();
+        assert_eq!(bpm.pin_count(scanned), None);
+        assert_eq!(bpm.pin_count(hot), Some(0));
+        assert!(bpm.unpin_page(third, false));
+        let stats = bpm.replacer_stats();
+        assert_eq!((stats.hits, stats.misses, stats.evictions), (1, 3, 1));
+    }
+}
@@ -0,0 +1,187 @@
+use crate::common::error::{IOContext, Result};
+use crate::storage::buffer::BufferPoolManager;
+use crate::RustubError;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// Options of a `BackgroundFlusher`
+#[derive(Debug, Clone, Copy)]
+pub struct FlusherOptions {
+    /// Time between two rounds of writes
+    pub interval: Duration,
+    /// The I/O budget of a round, i.e. the maximum number of pages written in a round
+    pub pages_per_round: usize,
+}
+
+impl Default for FlusherOptions {
+    fn default() -> Self {
+        FlusherOptions {
+            interval: Duration::from_millis(100),
+            pages_per_round: 16,
+        }
+    }
+}
+
+struct FlusherShared {
+    shutdown: Mutex<bool>,
+    /// Signaled on shutdown
+    wakeup: Condvar,
+    pages_written: AtomicU64,
+}
+
+/// BackgroundFlusher trickles the dirty pages of a buffer pool to disk from a background thread,
+/// so that evictions rarely have to write a page before reusing its frame. Every round, it writes
+/// the pages which have been dirty for the longest time first, up to its I/O budget. A page whose
+/// LSN is beyond the durable end of the log is left alone until the log catches up.
+///
+/// The flusher is stopped when it's dropped.
+///
+/// THREAD SAFETY: YES
+pub struct BackgroundFlusher {
+    shared: Arc<FlusherShared>,
+    flusher: Option<thread::JoinHandle<()>>,
+}
+
+impl BackgroundFlusher {
+    /// Start flushing the dirty pages of the given buffer pool
+    pub fn start(bpm: Arc<BufferPoolManager>, options: FlusherOptions) -> Result<Self> {
+        let shared = Arc::new(FlusherShared {
+            shutdown: Mutex::new(false),
+            wakeup: Condvar::new(),
+            pages_written: AtomicU64::new(0),
+        });
+        let flusher = {
+            let shared = shared.clone();
+            thread::Builder::new()
+                .name("page-flusher".to_string())
+                .spawn(move || BackgroundFlusher::flush_loop(shared, bpm, options))
+                .map_err(|e| {
+                    RustubError::IOError(e, IOContext::Other("can't start page flusher"))
+                })?
+        };
+        Ok(BackgroundFlusher {
+            shared,
+            flusher: Some(flusher),
+        })
+    }
+
+    /// Returns the number of pages written by the flusher so far
+    pub fn pages_written(&self) -> u64 {
+        self.shared.pages_written.load(Ordering::Relaxed)
+    }
+
+    fn flush_loop(
+        shared: Arc<FlusherShared>,
+        bpm: Arc<BufferPoolManager>,
+        options: FlusherOptions,
+    ) {
+        loop {
+            {
+                let shutdown = shared.shutdown.lock().unwrap();
+                let (shutdown, _) = shared
+                    .wakeup
+                    .wait_timeout_while(shutdown, options.interval, |shutdown| !*shutdown)
+                    .unwrap();
+                if *shutdown {
+                    return;
+                }
+            }
+            match bpm.flush_oldest(options.pages_per_round) {
+                Ok(written) => {
+                    shared
+                        .pages_written
+                        .fetch_add(written as u64, Ordering::Relaxed);
+                }
+                // the page stays dirty, and is tried again in the next round
+                Err(e) => error!("IO error while flushing pages: {}", e),
+            }
+        }
+    }
+}
+
+impl Drop for BackgroundFlusher {
+    /// Stop the flusher, leaving the remaining dirty pages in the pool
+    fn drop(&mut self) {
+        *self.shared.shutdown.lock().unwrap() = true;
+        self.shared.wakeup.notify_one();
+        if let Some(flusher) = self.flusher.take() {
+            let _ = flusher.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::storage::buffer::{BackgroundFlusher, BufferPoolManager, FlusherOptions};
+    use crate::storage::disk::{DiskManager, InMemDiskManager};
+    use crate::storage::page::Page;
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn flush_oldest_durable_pages() {
+        let disk = Arc::new(InMemDiskManager::new());
+        let bpm = BufferPoolManager::new(4, disk.clone()).unwrap();
+        let pids: Vec<_> = (0..4)
+            .map(|_| bpm.new_page_guarded().unwrap().page_id())
+            .collect();
+        bpm.flush_all().unwrap();
+        assert_eq!(bpm.num_dirty(), 0);
+
+        // pages are modified in reverse order, and the log isn't durable up to the last change of
+        // the second one
+        for (i, pid) in pids.iter().enumerate().rev() {
+            let mut guard = bpm.fetch_page_write(*pid).unwrap();
+            guard.data_mut()[0] = i as u8 + 1;
+            if i == 2 {
+                guard.set_lsn(10);
+            }
+        }
+        assert_eq!(bpm.flush_oldest(2).unwrap(), 2);
+        assert!(bpm.is_dirty(pids[0]));
+        assert!(!bpm.is_dirty(pids[1]));
+        assert!(bpm.is_dirty(pids[2]));
+        assert!(!bpm.is_dirty(pids[3]));
+
+        assert_eq!(bpm.flush_oldest(8).unwrap(), 1);
+        assert!(bpm.is_dirty(pids[2]));
+        disk.append_log(&[0u8; 10]).unwrap().wait().unwrap();
+        assert_eq!(bpm.flush_oldest(8).unwrap(), 1);
+        assert_eq!(bpm.num_dirty(), 0);
+        // writing doesn't pin the pages for good, nor count as an access
+        assert!(pids.iter().all(|pid| bpm.pin_count(*pid) == Some(0)));
+        assert_eq!(bpm.replacer_stats().hits, 4);
+    }
+
+    #[test]
+    fn flush_in_background() {
+        let disk = Arc::new(InMemDiskManager::new());
+        let bpm = Arc::new(BufferPoolManager::new(8, disk.clone()).unwrap());
+        let options = FlusherOptions {
+            interval: Duration::from_millis(5),
+            pages_per_round: 2,
+        };
+        let flusher = BackgroundFlusher::start(bpm.clone(), options).unwrap();
+        let pids: Vec<_> = (0..8)
+            .map(|i| {
+                let mut guard = bpm.new_page_guarded().unwrap();
+                guard.data_mut()[0] = i + 1;
+                guard.page_id()
+            })
+            .collect();
+        let start = Instant::now();
+        while bpm.num_dirty() > 0 {
+            assert!(start.elapsed() < Duration::from_secs(10));
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        assert!(flusher.pages_written() >= 8);
+        drop(flusher);
+        let mut buf = vec![0u8; disk.page_size()];
+        for (i, pid) in pids.iter().enumerate() {
+            disk.read_page(*pid, &mut buf).unwrap();
+            assert_eq!(buf[0], i as u8 + 1);
+        }
+    }
+}
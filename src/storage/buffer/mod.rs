@@ -0,0 +1,18 @@
+mod buffer_pool_manager;
+mod flusher;
+mod page_guard;
+mod parallel_buffer_pool_manager;
+mod replacer;
+mod ring;
+mod snapshot;
+
+pub use buffer_pool_manager::{BufferPoolManager, BufferPoolOptions};
+pub use flusher::{BackgroundFlusher, FlusherOptions};
+pub use page_guard::{ReadPageGuard, WritePageGuard};
+pub use parallel_buffer_pool_manager::ParallelBufferPoolManager;
+pub use replacer::{
+    replay_trace, ArcReplacer, ClockReplacer, LruKReplacer, LruReplacer, Replacer, ReplacerPolicy,
+    ReplacerStats,
+};
+pub use ring::BufferRing;
+pub use snapshot::{BufferPoolSnapshot, FrameSnapshot, PageType};
@@ -0,0 +1,272 @@
+use crate::common::config::PageId;
+use crate::storage::buffer::{BufferPoolManager, PageType};
+use crate::storage::page::{
+    BasePage, FreeSpaceMapPage, HeaderPage, OverflowPage, TablePage, TmpTuplePage,
+};
+use std::cell::Cell;
+use std::ops::{Deref, DerefMut};
+use std::sync::{RwLockReadGuard, RwLockWriteGuard};
+
+/// ReadPageGuard holds a pinned page and its shared latch. The latch is released and the page is
+/// unpinned when the guard is dropped, and the pool records the type the page has been viewed as.
+pub struct ReadPageGuard<'a> {
+    bpm: &'a BufferPoolManager,
+    page_id: PageId,
+    /// Only taken on drop, to release the latch before unpinning the page
+    page: Option<RwLockReadGuard<'a, BasePage>>,
+    /// The type the page has last been viewed as through this guard
+    page_type: Cell<Option<PageType>>,
+}
+
+impl<'a> ReadPageGuard<'a> {
+    pub(crate) fn new(
+        bpm: &'a BufferPoolManager,
+        page_id: PageId,
+        page: RwLockReadGuard<'a, BasePage>,
+    ) -> Self {
+        ReadPageGuard {
+            bpm,
+            page_id,
+            page: Some(page),
+            page_type: Cell::new(None),
+        }
+    }
+
+    /// Returns the id of the guarded page
+    pub fn page_id(&self) -> PageId {
+        self.page_id
+    }
+
+    /// View the guarded page as a table page
+    pub fn as_table_page(&self) -> TablePage<&BasePage> {
+        self.page_type.set(Some(PageType::Table));
+        TablePage::from_page(self.deref())
+    }
+
+    /// View the guarded page as a header page
+    pub fn as_header_page(&self) -> HeaderPage<&BasePage> {
+        self.page_type.set(Some(PageType::Header));
+        HeaderPage::from_page(self.deref())
+    }
+
+    /// View the guarded page as a temporary page
+    pub fn as_tmp_tuple_page(&self) -> TmpTuplePage<&BasePage> {
+        self.page_type.set(Some(PageType::Temporary));
+        TmpTuplePage::from_page(self.deref())
+    }
+
+    /// View the guarded page as an overflow page
+    pub fn as_overflow_page(&self) -> OverflowPage<&BasePage> {
+        self.page_type.set(Some(PageType::Overflow));
+        OverflowPage::from_page(self.deref())
+    }
+
+    /// View the guarded page as a free space map page
+    pub fn as_free_space_map_page(&self) -> FreeSpaceMapPage<&BasePage> {
+        self.page_type.set(Some(PageType::FreeSpaceMap));
+        FreeSpaceMapPage::from_page(self.deref())
+    }
+}
+
+impl Deref for ReadPageGuard<'_> {
+    type Target = BasePage;
+
+    fn deref(&self) -> &BasePage {
+        self.page.as_ref().unwrap()
+    }
+}
+
+impl Drop for ReadPageGuard<'_> {
+    fn drop(&mut self) {
+        drop(self.page.take());
+        self.bpm
+            .unpin_page_as(self.page_id, false, self.page_type.get());
+    }
+}
+
+/// WritePageGuard holds a pinned page and its exclusive latch. The page is marked dirty as soon as
+/// its content is borrowed mutably. The latch is released and the page is unpinned when the guard
+/// is dropped, and the pool records the type the page has been viewed as.
+pub struct WritePageGuard<'a> {
+    bpm: &'a BufferPoolManager,
+    page_id: PageId,
+    /// Only taken on drop, to release the latch before unpinning the page
+    page: Option<RwLockWriteGuard<'a, BasePage>>,
+    is_dirty: bool,
+    /// The type the page has last been viewed as through this guard
+    page_type: Cell<Option<PageType>>,
+}
+
+impl<'a> WritePageGuard<'a> {
+    pub(crate) fn new(
+        bpm: &'a BufferPoolManager,
+        page_id: PageId,
+        page: RwLockWriteGuard<'a, BasePage>,
+    ) -> Self {
+        WritePageGuard {
+            bpm,
+            page_id,
+            page: Some(page),
+            is_dirty: false,
+            page_type: Cell::new(None),
+        }
+    }
+
+    /// Returns the id of the guarded page
+    pub fn page_id(&self) -> PageId {
+        self.page_id
+    }
+
+    /// Returns true if the content of the page has been borrowed mutably through this guard
+    pub fn is_dirty(&self) -> bool {
+        self.is_dirty
+    }
+
+    /// View the guarded page as a table page
+    pub fn as_table_page(&self) -> TablePage<&BasePage> {
+        self.page_type.set(Some(PageType::Table));
+        TablePage::from_page(self.deref())
+    }
+
+    /// View the guarded page as a header page
+    pub fn as_header_page(&self) -> HeaderPage<&BasePage> {
+        self.page_type.set(Some(PageType::Header));
+        HeaderPage::from_page(self.deref())
+    }
+
+    /// View the guarded page as a temporary page
+    pub fn as_tmp_tuple_page(&self) -> TmpTuplePage<&BasePage> {
+        self.page_type.set(Some(PageType::Temporary));
+        TmpTuplePage::from_page(self.deref())
+    }
+
+    /// View the guarded page as an overflow page
+    pub fn as_overflow_page(&self) -> OverflowPage<&BasePage> {
+        self.page_type.set(Some(PageType::Overflow));
+        OverflowPage::from_page(self.deref())
+    }
+
+    /// View the guarded page as a free space map page
+    pub fn as_free_space_map_page(&self) -> FreeSpaceMapPage<&BasePage> {
+        self.page_type.set(Some(PageType::FreeSpaceMap));
+        FreeSpaceMapPage::from_page(self.deref())
+    }
+
+    /// View the guarded page as a table page which can be modified
+    pub fn as_table_page_mut(&mut self) -> TablePage<&mut BasePage> {
+        self.page_type.set(Some(PageType::Table));
+        TablePage::from_page(self.deref_mut())
+    }
+
+    /// View the guarded page as a header page which can be modified
+    pub fn as_header_page_mut(&mut self) -> HeaderPage<&mut BasePage> {
+        self.page_type.set(Some(PageType::Header));
+        HeaderPage::from_page(self.deref_mut())
+    }
+
+    /// View the guarded page as a temporary page which can be modified
+    pub fn as_tmp_tuple_page_mut(&mut self) -> TmpTuplePage<&mut BasePage> {
+        self.page_type.set(Some(PageType::Temporary));
+        TmpTuplePage::from_page(self.deref_mut())
+    }
+
+    /// View the guarded page as an overflow page which can be modified
+    pub fn as_overflow_page_mut(&mut self) -> OverflowPage<&mut BasePage> {
+        self.page_type.set(Some(PageType::Overflow));
+        OverflowPage::from_page(self.deref_mut())
+    }
+
+    /// View the guarded page as a free space map page which can be modified
+    pub fn as_free_space_map_page_mut(&mut self) -> FreeSpaceMapPage<&mut BasePage> {
+        self.page_type.set(Some(PageType::FreeSpaceMap));
+        FreeSpaceMapPage::from_page(self.deref_mut())
+    }
+}
+
+impl Deref for WritePageGuard<'_> {
+    type Target = BasePage;
+
+    fn deref(&self) -> &BasePage {
+        self.page.as_ref().unwrap()
+    }
+}
+
+impl DerefMut for WritePageGuard<'_> {
+    fn deref_mut(&mut self) -> &mut BasePage {
+        self.is_dirty = true;
+        self.page.as_mut().unwrap()
+    }
+}
+
+impl Drop for WritePageGuard<'_> {
+    fn drop(&mut self) {
+        drop(self.page.take());
+        self.bpm
+            .unpin_page_as(self.page_id, self.is_dirty, self.page_type.get());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::common::config::INVALID_PAGE_ID;
+    use crate::storage::buffer::BufferPoolManager;
+    use crate::storage::disk::InMemDiskManager;
+    use crate::storage::page::Page;
+    use std::sync::Arc;
+
+    #[test]
+    fn unpin_on_drop() {
+        let disk = Arc::new(InMemDiskManager::new());
+        let bpm = BufferPoolManager::new(2, disk).unwrap();
+        let pid = bpm.new_page_guarded().unwrap().page_id();
+        assert_eq!(bpm.pin_count(pid), Some(0));
+        bpm.flush_page(pid).unwrap();
+
+        // reading doesn't dirty the page
+        let guard = bpm.fetch_page_write(pid).unwrap();
+        assert_eq!(guard.data()[0], 0);
+        assert!(!guard.is_dirty());
+        drop(guard);
+        assert!(!bpm.is_dirty(pid));
+
+        let mut guard = bpm.fetch_page_write(pid).unwrap();
+        guard.data_mut()[0] = 42;
+        assert!(guard.is_dirty());
+        drop(guard);
+        assert!(bpm.is_dirty(pid));
+        assert_eq!(bpm.pin_count(pid), Some(0));
+
+        // readers share the latch
+        let first = bpm.fetch_page_read(pid).unwrap();
+        let second = bpm.fetch_page_read(pid).unwrap();
+        assert_eq!(bpm.pin_count(pid), Some(2));
+        assert_eq!(first.data()[0], second.data()[0]);
+        drop(first);
+        drop(second);
+        assert_eq!(bpm.pin_count(pid), Some(0));
+    }
+
+    #[test]
+    fn typed_views() {
+        let disk = Arc::new(InMemDiskManager::new());
+        let bpm = BufferPoolManager::new(2, disk).unwrap();
+        let mut guard = bpm.new_page_guarded().unwrap();
+        let pid = guard.page_id();
+        guard.as_header_page_mut().init();
+        assert!(guard.as_header_page_mut().insert_record("rustub", 7));
+        drop(guard);
+        let guard = bpm.fetch_page_read(pid).unwrap();
+        assert_eq!(guard.as_header_page().record_count(), 1);
+        assert_eq!(guard.as_header_page().root_id("rustub"), 7);
+        drop(guard);
+
+        let mut guard = bpm.fetch_page_write(pid).unwrap();
+        let mut table = guard.as_table_page_mut();
+        table.set_prev_page_id(INVALID_PAGE_ID);
+        table.set_next_page_id(pid + 1);
+        drop(guard);
+        let guard = bpm.fetch_page_read(pid).unwrap();
+        assert_eq!(guard.as_table_page().prev_page_id(), INVALID_PAGE_ID);
+        assert_eq!(guard.as_table_page().next_page_id(), pid + 1);
+    }
+}
@@ -0,0 +1,362 @@
+use crate::common::config::PageId;
+use crate::common::error::Result;
+use crate::storage::buffer::{
+    BufferPoolManager, BufferPoolOptions, ReadPageGuard, ReplacerStats, WritePageGuard,
+};
+use crate::storage::disk::DiskManager;
+use crate::storage::page::BasePage;
+use crate::RustubError;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+
+/// ParallelBufferPoolManager spreads the pages over several independent buffer pool instances, each
+/// with its own latch and replacer, so that threads working on different pages rarely wait for
+/// each other.
+///
+/// A page always lives in the instance its id hashes to, i.e. the instance whose index is the page
+/// id modulo the number of instances. New pages are spread round-robin across the instances: each
+/// one is given a page id of the next instance in turn, or of the following one whenever that
+/// instance has no frame to spare.
+pub struct ParallelBufferPoolManager {
+    disk: Arc<dyn DiskManager>,
+    instances: Vec<BufferPoolManager>,
+    /// The instance the next new page is tried in first
+    next_instance: AtomicUsize,
+}
+
+impl ParallelBufferPoolManager {
+    /// Create `num_instances` buffer pool instances over the given disk manager, each of them
+    /// created with the given options, see `BufferPoolManager::with_options`
+    pub fn new(
+        num_instances: usize,
+        disk: Arc<dyn DiskManager>,
+        options: BufferPoolOptions,
+    ) -> Result<Self> {
+        assert!(num_instances > 0);
+        let instances = (0..num_instances)
+            .map(|_| BufferPoolManager::with_options(disk.clone(), options.clone()))
+            .collect::<Result<_>>()?;
+        Ok(ParallelBufferPoolManager {
+            disk,
+            instances,
+            next_instance: AtomicUsize::new(0),
+        })
+    }
+
+    /// Returns the number of instances
+    #[inline]
+    pub fn num_instances(&self) -> usize {
+        self.instances.len()
+    }
+
+    /// Returns the number of frames of all instances
+    pub fn pool_size(&self) -> usize {
+        self.instances.iter().map(|bpm| bpm.pool_size()).sum()
+    }
+
+    /// Returns the instance responsible for the given page
+    pub fn instance(&self, pid: PageId) -> &BufferPoolManager {
+        &self.instances[self.instance_index(pid)]
+    }
+
+    #[inline]
+    fn instance_index(&self, pid: PageId) -> usize {
+        pid as u32 as usize % self.instances.len()
+    }
+
+    /// Allocate a new page on disk and pin it, filled with zeros, in the next instance in turn.
+    /// Page ids are allocated until one belongs to that instance, and the others are held back.
+    /// When every frame of the instance is pinned, the page goes to the following instance, and so
+    /// on. Fails once every instance has been tried, or as soon as an instance fails otherwise,
+    /// e.g. to write back the page it evicts. The page ids held back are deallocated in any case.
+    ///
+    /// THREAD SAFETY: YES
+    pub fn new_page(&self) -> Result<(PageId, &RwLock<BasePage>)> {
+        let num_instances = self.instances.len();
+        let start = self.next_instance.fetch_add(1, Ordering::Relaxed);
+        let mut held = Vec::new();
+        let mut result = Err(RustubError::BufferPoolError("every frame is pinned"));
+        'instances: for index in (start..start + num_instances).map(|i| i % num_instances) {
+            // a page id held back while looking for the previous instances may belong to this one
+            let pid = match held
+                .iter()
+                .position(|pid| self.instance_index(*pid) == index)
+            {
+                Some(i) => held.swap_remove(i),
+                None => loop {
+                    match self.disk.allocate_page() {
+                        Ok(pid) if self.instance_index(pid) == index => break pid,
+                        Ok(pid) => held.push(pid),
+                        Err(e) => {
+                            result = Err(e);
+                            break 'instances;
+                        }
+                    }
+                },
+            };
+            match self.instances[index].new_page_with_id(pid) {
+                Ok(page) => {
+                    result = Ok((pid, page));
+                    break;
+                }
+                Err(e) => {
+                    held.push(pid);
+                    let full = matches!(e, RustubError::BufferPoolError(_));
+                    result = Err(e);
+                    if !full {
+                        break;
+                    }
+                }
+            }
+        }
+        for pid in held {
+            // the new page, if any, is pinned already, so a page id which can't be given back is
+            // only leaked
+            if let Err(e) = self.disk.deallocate_page(pid) {
+                error!("Can't deallocate page {} held back: {}", pid, e);
+            }
+        }
+        result
+    }
+
+    /// See `BufferPoolManager::fetch_page`
+    ///
+    /// THREAD SAFETY: YES
+    pub fn fetch_page(&self, pid: PageId) -> Result<&RwLock<BasePage>> {
+        self.instance(pid).fetch_page(pid)
+    }
+
+    /// Like `new_page`, and returns the new page latched for writing
+    ///
+    /// THREAD SAFETY: YES
+    pub fn new_page_guarded(&self) -> Result<WritePageGuard<'_>> {
+        let (pid, page) = self.new_page()?;
+        Ok(WritePageGuard::new(
+            self.instance(pid),
+            pid,
+            page.write().unwrap(),
+        ))
+    }
+
+    /// See `BufferPoolManager::fetch_page_read`
+    ///
+    /// THREAD SAFETY: YES
+    pub fn fetch_page_read(&self, pid: PageId) -> Result<ReadPageGuard<'_>> {
+        self.instance(pid).fetch_page_read(pid)
+    }
+
+    /// See `BufferPoolManager::fetch_page_write`
+    ///
+    /// THREAD SAFETY: YES
+    pub fn fetch_page_write(&self, pid: PageId) -> Result<WritePageGuard<'_>> {
+        self.instance(pid).fetch_page_write(pid)
+    }
+
+    /// See `BufferPoolManager::unpin_page`
+    ///
+    /// THREAD SAFETY: YES
+    pub fn unpin_page(&self, pid: PageId, is_dirty: bool) -> bool {
+        self.instance(pid).unpin_page(pid, is_dirty)
+    }
+
+    /// See `BufferPoolManager::flush_page`
+    ///
+    /// THREAD SAFETY: YES
+    pub fn flush_page(&self, pid: PageId) -> Result<bool> {
+        self.instance(pid).flush_page(pid)
+    }
+
+    /// Write the dirty pages of every instance to disk, and sync the disk manager once
+    ///
+    /// THREAD SAFETY: YES
+    pub fn flush_all(&self) -> Result<()> {
+        for bpm in &self.instances {
+            bpm.flush_dirty_pages()?;
+        }
+        self.disk.sync()
+    }
+
+    /// See `BufferPoolManager::delete_page`
+    ///
+    /// THREAD SAFETY: YES
+    pub fn delete_page(&self, pid: PageId) -> Result<bool> {
+        self.instance(pid).delete_page(pid)
+    }
+
+    /// Returns the pin count of the given page, or None if it isn't in the pool
+    pub fn pin_count(&self, pid: PageId) -> Option<usize> {
+        self.instance(pid).pin_count(pid)
+    }
+
+    /// Returns true if the given page is in the pool and has been modified since it has been
+    /// written to disk
+    pub fn is_dirty(&self, pid: PageId) -> bool {
+        self.instance(pid).is_dirty(pid)
+    }
+
+    /// Returns the hits, misses and evictions of all instances so far
+    pub fn replacer_stats(&self) -> ReplacerStats {
+        let mut stats = ReplacerStats::default();
+        for bpm in &self.instances {
+            stats += bpm.replacer_stats();
+        }
+        stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::common::config::PAGE_SIZE;
+    use crate::storage::buffer::{BufferPoolOptions, ParallelBufferPoolManager};
+    use crate::storage::disk::{DiskManager, InMemDiskManager};
+    use crate::storage::page::Page;
+    use crate::RustubError;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn spread_pages_over_instances() {
+        let disk = Arc::new(InMemDiskManager::new());
+        let options = BufferPoolOptions {
+            pool_size: 2,
+            ..BufferPoolOptions::default()
+        };
+        let bpm = ParallelBufferPoolManager::new(3, disk.clone(), options).unwrap();
+        assert_eq!(bpm.pool_size(), 6);
+        let mut pids = Vec::new();
+        for i in 0..6 {
+            let (pid, page) = bpm.new_page().unwrap();
+            page.write().unwrap().data_mut()[0] = i + 1;
+            pids.push(pid);
+        }
+        // consecutive pages land on different instances, which are all full now
+        for pid in &pids {
+            assert_eq!(bpm.instance(*pid).pin_count(*pid), Some(1));
+        }
+        assert!(matches!(
+            bpm.new_page(),
+            Err(RustubError::BufferPoolError(_))
+        ));
+        // the page which couldn't be pinned has been given back
+        let pid = disk.allocate_page().unwrap();
+        disk.deallocate_page(pid).unwrap();
+        assert!(!pids.contains(&pid));
+
+        for pid in &pids {
+            assert!(bpm.unpin_page(*pid, true));
+        }
+        bpm.flush_all().unwrap();
+        let mut buf = [0u8; PAGE_SIZE];
+        for (i, pid) in pids.iter().enumerate() {
+            assert!(!bpm.is_dirty(*pid));
+            disk.read_page(*pid, &mut buf[..]).unwrap();
+            assert_eq!(buf[0], i as u8 + 1);
+        }
+        let stats = bpm.replacer_stats();
+        assert_eq!((stats.misses, stats.evictions), (6, 0));
+    }
+
+    #[test]
+    fn skip_full_instance() {
+        let disk = Arc::new(InMemDiskManager::new());
+        let options = BufferPoolOptions {
+            pool_size: 1,
+            ..BufferPoolOptions::default()
+        };
+        let bpm = ParallelBufferPoolManager::new(2, disk.clone(), options).unwrap();
+        let mut pids = Vec::new();
+        for _ in 0..3 {
+            let (pid, _) = bpm.new_page().unwrap();
+            assert!(bpm.unpin_page(pid, false));
+            pids.push(pid);
+        }
+        // the instances are taken in turn, so the first and the third page share one
+        assert_eq!(bpm.instance_index(pids[0]), 0);
+        assert_eq!(bpm.instance_index(pids[1]), 1);
+        assert_eq!(bpm.instance_index(pids[2]), 0);
+
+        // the next page goes to the second instance, unless it's full
+        assert_eq!(
+            bpm.fetch_page(pids[1]).unwrap().read().unwrap().page_id(),
+            pids[1]
+        );
+        assert!(bpm.delete_page(pids[0]).unwrap());
+        assert!(bpm.delete_page(pids[2]).unwrap());
+        let (pid, _) = bpm.new_page().unwrap();
+        assert_eq!(pid, pids[0]);
+        assert_eq!(bpm.pin_count(pid), Some(1));
+        // the page ids which have been skipped have been given back
+        assert_eq!(disk.allocate_page().unwrap(), pids[2]);
+    }
+
+    #[test]
+    fn rotate_over_instances() {
+        let disk = Arc::new(InMemDiskManager::new());
+        let bpm = ParallelBufferPoolManager::new(2, disk, BufferPoolOptions::default()).unwrap();
+        // the page id given back is the next one handed out by the disk manager, but it belongs to
+        // the instance which just got a page
+        let (first, _) = bpm.new_page().unwrap();
+        assert!(bpm.unpin_page(first, false));
+        assert!(bpm.delete_page(first).unwrap());
+        let (second, _) = bpm.new_page().unwrap();
+        assert_ne!(bpm.instance_index(first), bpm.instance_index(second));
+        assert!(bpm.unpin_page(second, false));
+    }
+
+    #[test]
+    fn fail_on_eviction_error() {
+        let disk = Arc::new(InMemDiskManager::new());
+        let options = BufferPoolOptions {
+            pool_size: 1,
+            ..BufferPoolOptions::default()
+        };
+        let bpm = ParallelBufferPoolManager::new(2, disk.clone(), options).unwrap();
+        let mut pids = Vec::new();
+        for _ in 0..2 {
+            let mut guard = bpm.new_page_guarded().unwrap();
+            // the log records of the page are missing, so it can't be evicted
+            guard.set_lsn(10);
+            pids.push(guard.page_id());
+        }
+
+        // the first instance fails to evict its page, and the second one isn't tried
+        assert!(matches!(bpm.new_page(), Err(RustubError::IOError(_, _))));
+        assert!(bpm.is_dirty(pids[0]));
+        let pid = disk.allocate_page().unwrap();
+        assert!(!pids.contains(&pid));
+        disk.deallocate_page(pid).unwrap();
+
+        // the next page goes to the second instance
+        disk.write_log(&[0u8; 10][..]).unwrap();
+        let (pid, _) = bpm.new_page().unwrap();
+        assert_eq!(bpm.pin_count(pids[1]), None);
+        assert!(bpm.unpin_page(pid, false));
+    }
+
+    #[test]
+    fn concurrent_access() {
+        let disk = Arc::new(InMemDiskManager::new());
+        let bpm = ParallelBufferPoolManager::new(4, disk, BufferPoolOptions::default()).unwrap();
+        let pids: Vec<_> = (0..16)
+            .map(|_| bpm.new_page_guarded().unwrap().page_id())
+            .collect();
+        thread::scope(|s| {
+            for t in 0..4u8 {
+                let (bpm, pids) = (&bpm, &pids);
+                s.spawn(move || {
+                    for _ in 0..100 {
+                        for pid in pids {
+                            let mut guard = bpm.fetch_page_write(*pid).unwrap();
+                            guard.data_mut()[t as usize] += 1;
+                        }
+                    }
+                });
+            }
+        });
+        for pid in &pids {
+            let guard = bpm.fetch_page_read(*pid).unwrap();
+            assert_eq!(guard.data()[..4], [100u8; 4]);
+        }
+        assert!(pids.iter().all(|pid| bpm.pin_count(*pid) == Some(0)));
+    }
+}
@@ -0,0 +1,243 @@
+use crate::common::config::{FrameId, PageId};
+use crate::storage::buffer::replacer::{Replacer, ReplacerStats};
+use std::collections::{BTreeMap, HashMap};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ArcList {
+    /// Pages which have been accessed once since they have been loaded
+    Recent,
+    /// Pages which have been accessed more than once, or which had been evicted recently
+    Frequent,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct ArcEntry {
+    page_id: PageId,
+    list: ArcList,
+    /// Position in its list
+    seq: u64,
+    evictable: bool,
+}
+
+/// Pages which have been evicted recently, least recently evicted first
+#[derive(Default)]
+struct GhostList {
+    pages: BTreeMap<u64, PageId>,
+    index: HashMap<PageId, u64>,
+}
+
+impl GhostList {
+    fn len(&self) -> usize {
+        self.pages.len()
+    }
+
+    fn push(&mut self, seq: u64, page_id: PageId) {
+        self.pages.insert(seq, page_id);
+        self.index.insert(page_id, seq);
+    }
+
+    fn remove(&mut self, page_id: PageId) -> bool {
+        match self.index.remove(&page_id) {
+            Some(seq) => self.pages.remove(&seq).is_some(),
+            None => false,
+        }
+    }
+
+    fn pop_oldest(&mut self) {
+        if let Some((_, page_id)) = self.pages.pop_first() {
+            self.index.remove(&page_id);
+        }
+    }
+}
+
+/// ArcReplacer implements the Adaptive Replacement Cache of Megiddo and Modha. Resident pages are
+/// split between a recency list (T1), of pages accessed once, and a frequency list (T2), of pages
+/// accessed again. Evicted pages are remembered in ghost lists (B1 and B2). A miss on a page of B1
+/// means T1 is too small and grows its target size `p`, a miss on B2 shrinks it. Eviction takes
+/// the least recently used evictable page of T1 if it's larger than its target, of T2 otherwise.
+///
+/// THREAD SAFETY: NO
+pub struct ArcReplacer {
+    capacity: usize,
+    /// Target size of T1
+    p: usize,
+    frames: Vec<Option<ArcEntry>>,
+    /// T1 and T2, least recently used first
+    recent: BTreeMap<u64, FrameId>,
+    frequent: BTreeMap<u64, FrameId>,
+    /// B1 and B2
+    recent_ghosts: GhostList,
+    frequent_ghosts: GhostList,
+    num_evictable: usize,
+    /// Logical clock, ticking on every access and eviction
+    now: u64,
+    stats: ReplacerStats,
+}
+
+impl ArcReplacer {
+    pub fn new(num_frames: usize) -> Self {
+        ArcReplacer {
+            capacity: num_frames,
+            p: 0,
+            frames: vec![None; num_frames],
+            recent: BTreeMap::new(),
+            frequent: BTreeMap::new(),
+            recent_ghosts: GhostList::default(),
+            frequent_ghosts: GhostList::default(),
+            num_evictable: 0,
+            now: 0,
+            stats: ReplacerStats::default(),
+        }
+    }
+
+    fn list_mut(&mut self, list: ArcList) -> &mut BTreeMap<u64, FrameId> {
+        match list {
+            ArcList::Recent => &mut self.recent,
+            ArcList::Frequent => &mut self.frequent,
+        }
+    }
+
+    /// Make the given frame the most recently used one of the given list
+    fn push(&mut self, frame_id: FrameId, page_id: PageId, list: ArcList, evictable: bool) {
+        self.now += 1;
+        let seq = self.now;
+        self.list_mut(list).insert(seq, frame_id);
+        self.frames[frame_id] = Some(ArcEntry {
+            page_id,
+            list,
+            seq,
+            evictable,
+        });
+    }
+
+    /// Returns the least recently used evictable frame of the given list
+    fn victim_of(&self, list: ArcList) -> Option<FrameId> {
+        let list = match list {
+            ArcList::Recent => &self.recent,
+            ArcList::Frequent => &self.frequent,
+        };
+        list.values()
+            .copied()
+            .find(|frame_id| self.frames[*frame_id].is_some_and(|entry| entry.evictable))
+    }
+}
+
+impl Replacer for ArcReplacer {
+    fn record_access(&mut self, frame_id: FrameId, page_id: PageId) {
+        if let Some(entry) = self.frames[frame_id] {
+            self.stats.hits += 1;
+            self.list_mut(entry.list).remove(&entry.seq);
+            self.push(frame_id, page_id, ArcList::Frequent, entry.evictable);
+            return;
+        }
+        self.stats.misses += 1;
+        let (b1, b2) = (self.recent_ghosts.len(), self.frequent_ghosts.len());
+        if self.recent_ghosts.remove(page_id) {
+            // T1 has been too small to keep the page
+            self.p = (self.p + (b2 / b1).max(1)).min(self.capacity);
+            self.push(frame_id, page_id, ArcList::Frequent, false);
+        } else if self.frequent_ghosts.remove(page_id) {
+            // T2 has been too small to keep the page
+            self.p = self.p.saturating_sub((b1 / b2).max(1));
+            self.push(frame_id, page_id, ArcList::Frequent, false);
+        } else {
+            self.push(frame_id, page_id, ArcList::Recent, false);
+        }
+    }
+
+    fn set_evictable(&mut self, frame_id: FrameId, evictable: bool) {
+        if let Some(entry) = &mut self.frames[frame_id] {
+            if entry.evictable != evictable {
+                entry.evictable = evictable;
+                match evictable {
+                    true => self.num_evictable += 1,
+                    false => self.num_evictable -= 1,
+                }
+            }
+        }
+    }
+
+    fn evict(&mut self) -> Option<FrameId> {
+        let prefer_recent = !self.recent.is_empty() && self.recent.len() > self.p;
+        let (first, second) = match prefer_recent {
+            true => (ArcList::Recent, ArcList::Frequent),
+            false => (ArcList::Frequent, ArcList::Recent),
+        };
+        let frame_id = self.victim_of(first).or_else(|| self.victim_of(second))?;
+        let entry = self.frames[frame_id].take().unwrap();
+        self.list_mut(entry.list).remove(&entry.seq);
+        self.num_evictable -= 1;
+        self.stats.evictions += 1;
+
+        // remember the page, keeping |T1| + |B1| <= c and |T1| + |T2| + |B1| + |B2| <= 2c
+        self.now += 1;
+        match entry.list {
+            ArcList::Recent => self.recent_ghosts.push(self.now, entry.page_id),
+            ArcList::Frequent => self.frequent_ghosts.push(self.now, entry.page_id),
+        }
+        while self.recent.len() + self.recent_ghosts.len() > self.capacity {
+            self.recent_ghosts.pop_oldest();
+        }
+        while self.recent.len()
+            + self.frequent.len()
+            + self.recent_ghosts.len()
+            + self.frequent_ghosts.len()
+            > 2 * self.capacity
+        {
+            self.frequent_ghosts.pop_oldest();
+        }
+        Some(frame_id)
+    }
+
+    fn remove(&mut self, frame_id: FrameId) {
+        if let Some(entry) = self.frames[frame_id].take() {
+            self.list_mut(entry.list).remove(&entry.seq);
+            if entry.evictable {
+                self.num_evictable -= 1;
+            }
+        }
+    }
+
+    fn size(&self) -> usize {
+        self.num_evictable
+    }
+
+    fn stats(&self) -> ReplacerStats {
+        self.stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::storage::buffer::replacer::{ArcReplacer, Replacer};
+
+    #[test]
+    fn adapt_to_ghost_hits() {
+        let mut replacer = ArcReplacer::new(2);
+        replacer.record_access(0, 10);
+        replacer.record_access(1, 11);
+        // page 10 is used again, so it's frequent
+        replacer.record_access(0, 10);
+        replacer.set_evictable(0, true);
+        replacer.set_evictable(1, true);
+        // T1 is larger than its target, so its page goes first
+        assert_eq!(replacer.evict(), Some(1));
+        assert_eq!(replacer.p, 0);
+
+        // page 11 comes back right after its eviction: T1 should have been larger
+        replacer.record_access(1, 11);
+        assert_eq!(replacer.p, 1);
+        replacer.set_evictable(1, true);
+        // both pages are frequent now, the least recently used one goes
+        assert_eq!(replacer.evict(), Some(0));
+        replacer.record_access(0, 12);
+        replacer.set_evictable(0, true);
+        // T1 holds a single page, which isn't more than its target
+        assert_eq!(replacer.evict(), Some(1));
+        replacer.remove(0);
+        assert_eq!(replacer.size(), 0);
+        assert_eq!(replacer.evict(), None);
+        let stats = replacer.stats();
+        assert_eq!((stats.hits, stats.misses, stats.evictions), (1, 4, 3));
+    }
+}
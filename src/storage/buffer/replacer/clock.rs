@@ -0,0 +1,118 @@
+use crate::common::config::{FrameId, PageId};
+use crate::storage::buffer::replacer::{Replacer, ReplacerStats};
+
+#[derive(Debug, Clone, Copy, Default)]
+struct ClockEntry {
+    tracked: bool,
+    evictable: bool,
+    /// Set on every access, and cleared when the hand passes by
+    referenced: bool,
+}
+
+/// ClockReplacer sweeps the frames with a clock hand. A page which has been accessed since the
+/// hand last passed by gets a second chance, the first evictable one which hasn't is evicted.
+///
+/// THREAD SAFETY: NO
+pub struct ClockReplacer {
+    frames: Vec<ClockEntry>,
+    hand: FrameId,
+    num_evictable: usize,
+    stats: ReplacerStats,
+}
+
+impl ClockReplacer {
+    pub fn new(num_frames: usize) -> Self {
+        ClockReplacer {
+            frames: vec![ClockEntry::default(); num_frames],
+            hand: 0,
+            num_evictable: 0,
+            stats: ReplacerStats::default(),
+        }
+    }
+}
+
+impl Replacer for ClockReplacer {
+    fn record_access(&mut self, frame_id: FrameId, _page_id: PageId) {
+        let entry = &mut self.frames[frame_id];
+        if entry.tracked {
+            self.stats.hits += 1;
+        } else {
+            self.stats.misses += 1;
+            entry.tracked = true;
+        }
+        entry.referenced = true;
+    }
+
+    fn set_evictable(&mut self, frame_id: FrameId, evictable: bool) {
+        let entry = &mut self.frames[frame_id];
+        if entry.tracked && entry.evictable != evictable {
+            entry.evictable = evictable;
+            match evictable {
+                true => self.num_evictable += 1,
+                false => self.num_evictable -= 1,
+            }
+        }
+    }
+
+    fn evict(&mut self) -> Option<FrameId> {
+        if self.num_evictable == 0 {
+            return None;
+        }
+        // the hand goes around twice at most, clearing every reference bit the first time
+        loop {
+            let frame_id = self.hand;
+            self.hand = (self.hand + 1) % self.frames.len();
+            let entry = &mut self.frames[frame_id];
+            if !entry.tracked || !entry.evictable {
+                continue;
+            }
+            if entry.referenced {
+                entry.referenced = false;
+                continue;
+            }
+            *entry = ClockEntry::default();
+            self.num_evictable -= 1;
+            self.stats.evictions += 1;
+            return Some(frame_id);
+        }
+    }
+
+    fn remove(&mut self, frame_id: FrameId) {
+        let entry = &mut self.frames[frame_id];
+        if entry.tracked && entry.evictable {
+            self.num_evictable -= 1;
+        }
+        *entry = ClockEntry::default();
+    }
+
+    fn size(&self) -> usize {
+        self.num_evictable
+    }
+
+    fn stats(&self) -> ReplacerStats {
+        self.stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::storage::buffer::replacer::{ClockReplacer, Replacer};
+
+    #[test]
+    fn second_chance() {
+        let mut replacer = ClockReplacer::new(3);
+        for frame_id in 0..3 {
+            replacer.record_access(frame_id, frame_id as i32);
+            replacer.set_evictable(frame_id, true);
+        }
+        // every page has been referenced, so the hand clears all bits and comes back to 0
+        assert_eq!(replacer.evict(), Some(0));
+        replacer.record_access(1, 1);
+        assert_eq!(replacer.evict(), Some(2));
+        replacer.record_access(0, 3);
+        assert_eq!(replacer.size(), 1);
+        assert_eq!(replacer.evict(), Some(1));
+        assert_eq!(replacer.evict(), None);
+        assert_eq!(replacer.stats().hits, 1);
+    }
+}
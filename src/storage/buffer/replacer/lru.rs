@@ -0,0 +1,118 @@
+use crate::common::config::{FrameId, PageId};
+use crate::storage::buffer::replacer::{Replacer, ReplacerStats};
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone, Copy)]
+struct LruEntry {
+    last_access: u64,
+    evictable: bool,
+}
+
+/// LruReplacer evicts the page which has been accessed least recently.
+///
+/// THREAD SAFETY: NO
+pub struct LruReplacer {
+    frames: Vec<Option<LruEntry>>,
+    /// Evictable frames by their last access
+    evictable: BTreeMap<u64, FrameId>,
+    /// Logical clock, ticking on every access
+    now: u64,
+    stats: ReplacerStats,
+}
+
+impl LruReplacer {
+    pub fn new(num_frames: usize) -> Self {
+        LruReplacer {
+            frames: vec![None; num_frames],
+            evictable: BTreeMap::new(),
+            now: 0,
+            stats: ReplacerStats::default(),
+        }
+    }
+}
+
+impl Replacer for LruReplacer {
+    fn record_access(&mut self, frame_id: FrameId, _page_id: PageId) {
+        self.now += 1;
+        match &mut self.frames[frame_id] {
+            Some(entry) => {
+                self.stats.hits += 1;
+                if entry.evictable {
+                    self.evictable.remove(&entry.last_access);
+                    self.evictable.insert(self.now, frame_id);
+                }
+                entry.last_access = self.now;
+            }
+            None => {
+                self.stats.misses += 1;
+                self.frames[frame_id] = Some(LruEntry {
+                    last_access: self.now,
+                    evictable: false,
+                });
+            }
+        }
+    }
+
+    fn set_evictable(&mut self, frame_id: FrameId, evictable: bool) {
+        if let Some(entry) = &mut self.frames[frame_id] {
+            if entry.evictable == evictable {
+                return;
+            }
+            entry.evictable = evictable;
+            if evictable {
+                self.evictable.insert(entry.last_access, frame_id);
+            } else {
+                self.evictable.remove(&entry.last_access);
+            }
+        }
+    }
+
+    fn evict(&mut self) -> Option<FrameId> {
+        let (_, frame_id) = self.evictable.pop_first()?;
+        self.frames[frame_id] = None;
+        self.stats.evictions += 1;
+        Some(frame_id)
+    }
+
+    fn remove(&mut self, frame_id: FrameId) {
+        if let Some(entry) = self.frames[frame_id].take() {
+            if entry.evictable {
+                self.evictable.remove(&entry.last_access);
+            }
+        }
+    }
+
+    fn size(&self) -> usize {
+        self.evictable.len()
+    }
+
+    fn stats(&self) -> ReplacerStats {
+        self.stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::storage::buffer::replacer::{LruReplacer, Replacer};
+
+    #[test]
+    fn evict_least_recently_used() {
+        let mut replacer = LruReplacer::new(4);
+        for frame_id in 0..4 {
+            replacer.record_access(frame_id, frame_id as i32);
+            replacer.set_evictable(frame_id, true);
+        }
+        replacer.record_access(0, 0);
+        replacer.set_evictable(1, false);
+        assert_eq!(replacer.size(), 3);
+        assert_eq!(replacer.evict(), Some(2));
+        assert_eq!(replacer.evict(), Some(3));
+        replacer.remove(0);
+        assert_eq!(replacer.evict(), None);
+        replacer.set_evictable(1, true);
+        assert_eq!(replacer.evict(), Some(1));
+
+        let stats = replacer.stats();
+        assert_eq!((stats.hits, stats.misses, stats.evictions), (1, 4, 3));
+    }
+}
@@ -0,0 +1,134 @@
+use crate::common::config::{FrameId, PageId};
+use crate::storage::buffer::replacer::{Replacer, ReplacerStats};
+use std::collections::VecDeque;
+
+#[derive(Debug, Clone)]
+struct LruKEntry {
+    /// The time of the last k accesses at most, oldest first
+    history: VecDeque<u64>,
+    evictable: bool,
+}
+
+/// LruKReplacer evicts the page whose backward k-distance, i.e. the time since its k-th most
+/// recent access, is the largest. A page accessed less than k times has an infinite k-distance,
+/// and the one among them whose first access is the oldest is evicted first.
+///
+/// THREAD SAFETY: NO
+pub struct LruKReplacer {
+    k: usize,
+    frames: Vec<Option<LruKEntry>>,
+    num_evictable: usize,
+    /// Logical clock, ticking on every access
+    now: u64,
+    stats: ReplacerStats,
+}
+
+impl LruKReplacer {
+    pub fn new(num_frames: usize, k: usize) -> Self {
+        assert!(k > 0);
+        LruKReplacer {
+            k,
+            frames: vec![None; num_frames],
+            num_evictable: 0,
+            now: 0,
+            stats: ReplacerStats::default(),
+        }
+    }
+}
+
+impl Replacer for LruKReplacer {
+    fn record_access(&mut self, frame_id: FrameId, _page_id: PageId) {
+        self.now += 1;
+        match &mut self.frames[frame_id] {
+            Some(entry) => {
+                self.stats.hits += 1;
+                if entry.history.len() == self.k {
+                    entry.history.pop_front();
+                }
+                entry.history.push_back(self.now);
+            }
+            None => {
+                self.stats.misses += 1;
+                self.frames[frame_id] = Some(LruKEntry {
+                    history: VecDeque::from([self.now]),
+                    evictable: false,
+                });
+            }
+        }
+    }
+
+    fn set_evictable(&mut self, frame_id: FrameId, evictable: bool) {
+        if let Some(entry) = &mut self.frames[frame_id] {
+            if entry.evictable != evictable {
+                entry.evictable = evictable;
+                match evictable {
+                    true => self.num_evictable += 1,
+                    false => self.num_evictable -= 1,
+                }
+            }
+        }
+    }
+
+    fn evict(&mut self) -> Option<FrameId> {
+        // infinite distances first, then the oldest k-th most recent access
+        let k = self.k;
+        let (frame_id, _) = self
+            .frames
+            .iter()
+            .enumerate()
+            .filter_map(|(frame_id, entry)| match entry {
+                Some(entry) if entry.evictable => Some((frame_id, entry)),
+                _ => None,
+            })
+            .min_by_key(|(_, entry)| (entry.history.len() >= k, entry.history[0]))?;
+        self.frames[frame_id] = None;
+        self.num_evictable -= 1;
+        self.stats.evictions += 1;
+        Some(frame_id)
+    }
+
+    fn remove(&mut self, frame_id: FrameId) {
+        if let Some(entry) = self.frames[frame_id].take() {
+            if entry.evictable {
+                self.num_evictable -= 1;
+            }
+        }
+    }
+
+    fn size(&self) -> usize {
+        self.num_evictable
+    }
+
+    fn stats(&self) -> ReplacerStats {
+        self.stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::storage::buffer::replacer::{LruKReplacer, Replacer};
+
+    #[test]
+    fn evict_largest_k_distance() {
+        let mut replacer = LruKReplacer::new(4, 2);
+        // frame 0 and 2 are accessed twice, 1 and 3 once
+        for (frame_id, page_id) in [(0, 10), (1, 11), (2, 12), (0, 10), (3, 13), (2, 12)] {
+            replacer.record_access(frame_id, page_id);
+        }
+        for frame_id in 0..4 {
+            replacer.set_evictable(frame_id, true);
+        }
+        assert_eq!(replacer.size(), 4);
+        // infinite distances, by first access
+        assert_eq!(replacer.evict(), Some(1));
+        assert_eq!(replacer.evict(), Some(3));
+        // the second most recent access of frame 0 is older than the one of frame 2
+        replacer.set_evictable(0, false);
+        assert_eq!(replacer.evict(), Some(2));
+        assert_eq!(replacer.evict(), None);
+        replacer.set_evictable(0, true);
+        assert_eq!(replacer.evict(), Some(0));
+        assert_eq!(replacer.size(), 0);
+        assert_eq!(replacer.stats().evictions, 4);
+    }
+}
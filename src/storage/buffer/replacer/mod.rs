@@ -0,0 +1,168 @@
+use crate::common::config::{FrameId, PageId};
+use std::collections::HashMap;
+use std::ops::AddAssign;
+
+mod arc;
+mod clock;
+mod lru;
+mod lru_k;
+
+pub use arc::ArcReplacer;
+pub use clock::ClockReplacer;
+pub use lru::LruReplacer;
+pub use lru_k::LruKReplacer;
+
+/// Replacer picks the frame whose page is evicted from the buffer pool when a frame is needed and
+/// there is no free one.
+///
+/// A frame is tracked from the first access to the page it holds until the page is evicted or
+/// removed. An access to a tracked frame is a hit, any other access is a miss. Only frames which
+/// have been marked as evictable, i.e. whose page isn't pinned, can be picked.
+pub trait Replacer: Send {
+    /// Record an access to the given page, held by the given frame
+    fn record_access(&mut self, frame_id: FrameId, page_id: PageId);
+
+    /// Allow or forbid the eviction of the page held by the given frame. Untracked frames are
+    /// ignored.
+    fn set_evictable(&mut self, frame_id: FrameId, evictable: bool);
+
+    /// Pick an evictable frame according to the policy, and stop tracking it. Returns None if no
+    /// frame can be evicted.
+    fn evict(&mut self) -> Option<FrameId>;
+
+    /// Stop tracking the given frame, e.g. because its page has been deleted. It doesn't count as
+    /// an eviction.
+    fn remove(&mut self, frame_id: FrameId);
+
+    /// Returns the number of evictable frames
+    fn size(&self) -> usize;
+
+    /// Returns the hits, misses and evictions seen so far
+    fn stats(&self) -> ReplacerStats;
+}
+
+/// How well a replacement policy does on a workload
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ReplacerStats {
+    /// Accesses to a page which was in the pool
+    pub hits: u64,
+    /// Accesses to a page which had to be loaded
+    pub misses: u64,
+    pub evictions: u64,
+}
+
+impl ReplacerStats {
+    /// Returns the share of accesses which were hits, or 0 if there hasn't been any access
+    pub fn hit_ratio(&self) -> f64 {
+        let accesses = self.hits + self.misses;
+        if accesses == 0 {
+            return 0.0;
+        }
+        self.hits as f64 / accesses as f64
+    }
+}
+
+impl AddAssign for ReplacerStats {
+    fn add_assign(&mut self, other: ReplacerStats) {
+        self.hits += other.hits;
+        self.misses += other.misses;
+        self.evictions += other.evictions;
+    }
+}
+
+/// The page replacement policy of a buffer pool
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ReplacerPolicy {
+    /// Evict the least recently used page
+    #[default]
+    Lru,
+    /// Evict the page whose k-th most recent access is the oldest, pages accessed less than k
+    /// times first. It keeps pages which are accessed once, e.g. by scans, from pushing out the
+    /// frequently used ones.
+    LruK(usize),
+    /// Evict the first page found without its reference bit by a clock hand, which clears the
+    /// bits on its way. It approximates LRU at a lower cost.
+    Clock,
+    /// Adaptive Replacement Cache: balance recently and frequently used pages, adapting the
+    /// balance with the history of recently evicted pages.
+    Arc,
+}
+
+impl ReplacerPolicy {
+    /// Create a replacer of this policy for a pool of `num_frames` frames
+    pub fn create(&self, num_frames: usize) -> Box<dyn Replacer> {
+        match *self {
+            ReplacerPolicy::Lru => Box::new(LruReplacer::new(num_frames)),
+            ReplacerPolicy::LruK(k) => Box::new(LruKReplacer::new(num_frames, k)),
+            ReplacerPolicy::Clock => Box::new(ClockReplacer::new(num_frames)),
+            ReplacerPolicy::Arc => Box::new(ArcReplacer::new(num_frames)),
+        }
+    }
+}
+
+/// Replay a trace of page accesses against a pool of `num_frames` frames using the given policy,
+/// and return how well it did. Every page is unpinned right after its access. This lets policies
+/// be compared on the same workload without any I/O.
+pub fn replay_trace(policy: ReplacerPolicy, num_frames: usize, trace: &[PageId]) -> ReplacerStats {
+    let mut replacer = policy.create(num_frames);
+    let mut page_table: HashMap<PageId, FrameId> = HashMap::new();
+    let mut pages: Vec<Option<PageId>> = vec![None; num_frames];
+    let mut free_frames: Vec<FrameId> = (0..num_frames).rev().collect();
+    for &pid in trace {
+        let frame_id = match page_table.get(&pid) {
+            Some(&frame_id) => frame_id,
+            None => {
+                let frame_id = match free_frames.pop() {
+                    Some(frame_id) => frame_id,
+                    None => {
+                        let victim = replacer
+                            .evict()
+                            .expect("every page is unpinned after its access");
+                        page_table.remove(&pages[victim].take().unwrap());
+                        victim
+                    }
+                };
+                page_table.insert(pid, frame_id);
+                pages[frame_id] = Some(pid);
+                frame_id
+            }
+        };
+        replacer.record_access(frame_id, pid);
+        replacer.set_evictable(frame_id, true);
+    }
+    replacer.stats()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::common::config::PageId;
+    use crate::storage::buffer::replacer::{replay_trace, ReplacerPolicy, ReplacerStats};
+
+    #[test]
+    fn compare_on_trace() {
+        // a small hot set, read twice in a row, between scans which read every page once
+        let mut trace: Vec<PageId> = Vec::new();
+        for round in 0..50 {
+            for hot in 0..4 {
+                trace.push(hot);
+                trace.push(hot);
+            }
+            for scanned in 0..8 {
+                trace.push(1000 + round * 8 + scanned);
+            }
+        }
+        let lru = replay_trace(ReplacerPolicy::Lru, 8, &trace);
+        let lru_k = replay_trace(ReplacerPolicy::LruK(2), 8, &trace);
+        let clock = replay_trace(ReplacerPolicy::Clock, 8, &trace);
+        let arc = replay_trace(ReplacerPolicy::Arc, 8, &trace);
+        for stats in [lru, lru_k, clock, arc] {
+            assert_eq!(stats.hits + stats.misses, trace.len() as u64);
+            assert!(stats.hit_ratio() > 0.0 && stats.hit_ratio() < 1.0);
+        }
+        // the scans push the hot set out of LRU, and not out of the scan resistant policies
+        assert_eq!(lru.hits, 4 * 50);
+        assert!(lru_k.hits > lru.hits);
+        assert!(arc.hits > lru.hits);
+        assert_eq!(ReplacerStats::default().hit_ratio(), 0.0);
+    }
+}
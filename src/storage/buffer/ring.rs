@@ -0,0 +1,43 @@
+use crate::common::config::{FrameId, PageId};
+
+/// BufferRing is a small set of frames private to a scan. Pages read by the scan through the ring
+/// take the frames of the pages it read before, oldest first, instead of evicting pages of the
+/// pool which are used by others. See `BufferPoolManager::fetch_page_in_ring`.
+///
+/// The frames still belong to the pool: a frame whose page has been pinned or evicted by someone
+/// else in the meantime isn't reused, and a new frame is taken from the pool in its place.
+///
+/// THREAD SAFETY: NO
+pub struct BufferRing {
+    /// The frames used by the ring and the page each of them has been given
+    slots: Vec<Option<(FrameId, PageId)>>,
+    /// The slot to be reused next, i.e. the one of the oldest page
+    next: usize,
+}
+
+impl BufferRing {
+    /// Create a ring of `size` frames
+    pub fn new(size: usize) -> Self {
+        assert!(size > 0);
+        BufferRing {
+            slots: vec![None; size],
+            next: 0,
+        }
+    }
+
+    /// Returns the number of frames of the ring
+    pub fn size(&self) -> usize {
+        self.slots.len()
+    }
+
+    /// Returns the frame to be reused next, and the page the ring gave it
+    pub(crate) fn oldest(&self) -> Option<(FrameId, PageId)> {
+        self.slots[self.next]
+    }
+
+    /// Remember the frame of a page read through the ring, in place of the oldest one
+    pub(crate) fn push(&mut self, frame_id: FrameId, pid: PageId) {
+        self.slots[self.next] = Some((frame_id, pid));
+        self.next = (self.next + 1) % self.slots.len();
+    }
+}
@@ -0,0 +1,189 @@
+use crate::common::config::{FrameId, PageId, HEADER_PAGE_ID};
+use crate::storage::buffer::ReplacerStats;
+use std::time::Instant;
+
+/// What a page held by the buffer pool looks like. Pages don't record their type, so the pool
+/// records the type a page has last been viewed as through the typed accessors of a page guard,
+/// until the page leaves the pool.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PageType {
+    /// The header page of the database
+    Header,
+    Table,
+    Overflow,
+    FreeSpaceMap,
+    /// A page of a temporary file
+    Temporary,
+    /// A page which hasn't been viewed as any type since it has been brought into the pool
+    Unknown,
+}
+
+impl PageType {
+    /// Returns the type of the given page when it's brought into the pool
+    pub(crate) fn of(pid: PageId) -> PageType {
+        if pid == HEADER_PAGE_ID {
+            PageType::Header
+        } else {
+            PageType::Unknown
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            PageType::Header => "header",
+            PageType::Table => "table",
+            PageType::Overflow => "overflow",
+            PageType::FreeSpaceMap => "free_space_map",
+            PageType::Temporary => "temporary",
+            PageType::Unknown => "unknown",
+        }
+    }
+}
+
+/// The state of a frame of the buffer pool
+#[derive(Debug, Clone)]
+pub struct FrameSnapshot {
+    pub frame_id: FrameId,
+    /// The page held by the frame, or INVALID_PAGE_ID if the frame is free
+    pub page_id: PageId,
+    /// The type of the page held by the frame, or None if the frame is free
+    pub page_type: Option<PageType>,
+    pub pin_count: usize,
+    pub is_dirty: bool,
+    /// When the page has last been pinned, or None if the frame is free
+    pub last_access: Option<Instant>,
+}
+
+/// BufferPoolSnapshot is a read-only copy of the state of every frame of a buffer pool, and of its
+/// hit, miss and eviction counters, taken by `BufferPoolManager::snapshot`. Pages which stay pinned
+/// for a long time since their last access are likely pin leaks.
+///
+/// It can also be read as the rows of a system table, see `COLUMNS` and `rows`.
+#[derive(Debug, Clone)]
+pub struct BufferPoolSnapshot {
+    /// When the snapshot has been taken
+    pub taken_at: Instant,
+    pub frames: Vec<FrameSnapshot>,
+    pub stats: ReplacerStats,
+}
+
+impl BufferPoolSnapshot {
+    /// Name of the system table of the buffer pool
+    pub const TABLE_NAME: &'static str = "rustub_buffer_pool";
+
+    /// Columns of the system table of the buffer pool, one row per frame
+    pub const COLUMNS: [&'static str; 6] = [
+        "frame_id",
+        "page_id",
+        "page_type",
+        "pin_count",
+        "is_dirty",
+        "last_access_ms",
+    ];
+
+    /// Returns the frames holding a pinned page
+    pub fn pinned(&self) -> impl Iterator<Item = &FrameSnapshot> {
+        self.frames.iter().filter(|frame| frame.pin_count > 0)
+    }
+
+    /// Returns the rows of the system table of the buffer pool. The last access is given in
+    /// milliseconds before the snapshot. A free frame has no page id, type nor last access.
+    pub fn rows(&self) -> Vec<[String; 6]> {
+        self.frames
+            .iter()
+            .map(|frame| {
+                let (page_id, page_type) = match frame.page_type {
+                    Some(page_type) => (frame.page_id.to_string(), page_type.name().to_string()),
+                    None => (String::new(), String::new()),
+                };
+                let last_access = match frame.last_access {
+                    Some(at) => self.taken_at.duration_since(at).as_millis().to_string(),
+                    None => String::new(),
+                };
+                [
+                    frame.frame_id.to_string(),
+                    page_id,
+                    page_type,
+                    frame.pin_count.to_string(),
+                    frame.is_dirty.to_string(),
+                    last_access,
+                ]
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::common::config::{HEADER_PAGE_ID, INVALID_PAGE_ID};
+    use crate::storage::buffer::{BufferPoolManager, BufferPoolSnapshot, PageType};
+    use crate::storage::disk::InMemDiskManager;
+    use std::sync::Arc;
+
+    #[test]
+    fn snapshot_frames() {
+        let disk = Arc::new(InMemDiskManager::new());
+        let bpm = BufferPoolManager::new(3, disk).unwrap();
+        let mut table = bpm.new_page_guarded().unwrap();
+        let table_pid = table.page_id();
+        table.as_table_page_mut().init(table_pid, INVALID_PAGE_ID);
+        drop(table);
+        // a leaked pin
+        let (other_pid, _) = bpm.new_page().unwrap();
+        bpm.fetch_page_read(table_pid).unwrap();
+
+        let snapshot = bpm.snapshot();
+        assert_eq!(snapshot.frames.len(), 3);
+        let pinned: Vec<_> = snapshot.pinned().map(|frame| frame.page_id).collect();
+        assert_eq!(pinned, vec![other_pid]);
+        let table = &snapshot.frames[0];
+        assert_eq!(table.page_id, table_pid);
+        assert_eq!(table.page_type, Some(PageType::Table));
+        assert!(table.is_dirty);
+        assert_eq!(snapshot.frames[1].page_type, Some(PageType::Unknown));
+        assert_eq!(snapshot.frames[2].page_id, INVALID_PAGE_ID);
+        assert!(snapshot.frames[2].last_access.is_none());
+        assert_eq!((snapshot.stats.hits, snapshot.stats.misses), (1, 2));
+
+        let rows = snapshot.rows();
+        assert_eq!(BufferPoolSnapshot::COLUMNS[2], "page_type");
+        assert_eq!(
+            rows[0][..5],
+            ["0", &table_pid.to_string(), "table", "0", "true"]
+        );
+        assert_eq!(rows[1][2..4], ["unknown", "1"]);
+        assert_eq!(rows[2][1..], ["", "", "0", "false", ""]);
+    }
+
+    #[test]
+    fn record_page_types() {
+        let disk = Arc::new(InMemDiskManager::new());
+        let bpm = BufferPoolManager::new(2, disk).unwrap();
+        bpm.fetch_page_read(HEADER_PAGE_ID).unwrap();
+        let overflow_pid = bpm.new_page_guarded().unwrap().page_id();
+        bpm.fetch_page_read(overflow_pid)
+            .unwrap()
+            .as_overflow_page();
+        let types = |bpm: &BufferPoolManager| -> Vec<_> {
+            bpm.snapshot()
+                .frames
+                .iter()
+                .map(|frame| frame.page_type)
+                .collect()
+        };
+        assert_eq!(
+            types(&bpm),
+            [Some(PageType::Header), Some(PageType::Overflow)]
+        );
+
+        // a page is recorded as the type it has last been viewed as, until it leaves the pool
+        bpm.fetch_page_write(overflow_pid)
+            .unwrap()
+            .as_free_space_map_page_mut();
+        assert_eq!(types(&bpm)[1], Some(PageType::FreeSpaceMap));
+        assert!(bpm.delete_page(overflow_pid).unwrap());
+        assert_eq!(types(&bpm)[1], None);
+        bpm.new_page_guarded().unwrap();
+        assert_eq!(types(&bpm)[1], Some(PageType::Unknown));
+    }
+}
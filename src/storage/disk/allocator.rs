@@ -0,0 +1,247 @@
+use crate::common::config::{PageId, HEADER_PAGE_ID};
+
+/// Returns the number of pages tracked by a single bitmap page of the given page size, whose last
+/// `reserved_size` bytes are reserved for the disk manager, see `DiskManager::reserved_size`
+#[inline]
+pub const fn pages_per_bitmap(page_size: usize, reserved_size: usize) -> usize {
+    (page_size - reserved_size) * 8
+}
+
+/// FreePageMap tracks which pages of a database are in use. It is persisted as bitmap pages which
+/// are reserved inside the database itself.
+///
+/// The page ids are divided into groups of `N = pages_per_bitmap(page_size, reserved_size)` pages.
+/// The second page of each group is the bitmap page of that group, in which bit `i` is set if page
+/// `group * N + i` is allocated:
+///
+/// ----------------------------------------------------------------------------------------
+/// | header (0) | bitmap (1) | data (2) | ... | data (N) | bitmap (N + 1) | data (N + 2) | ...
+/// ----------------------------------------------------------------------------------------
+///
+/// The header page and the bitmap pages are always marked as allocated. A new group, together with
+/// its bitmap page, is only added when all existing groups are full, so freed pages are always
+/// reused before the database grows.
+///
+/// FreePageMap only manages the in-memory copy. Every mutation returns the bitmap page which has to
+/// be written back by the owning disk manager.
+///
+/// THREAD SAFETY: NO
+pub(crate) struct FreePageMap {
+    page_size: usize,
+    /// The bytes at the end of a bitmap page which the disk manager overwrites, and which don't
+    /// hold any bit
+    reserved_size: usize,
+    pages_per_bitmap: usize,
+    bitmaps: Vec<Box<[u8]>>,
+    /// All groups before this one are known to be full
+    first_free_group: usize,
+}
+
+impl FreePageMap {
+    /// Load the free page map of a database which has `num_pages` pages of `page_size` bytes, the
+    /// last `reserved_size` of which are reserved for the disk manager. `read` is called to fetch
+    /// the content of every existing bitmap page. Returns the map together with the bitmap pages
+    /// which have been created and must be persisted, which only happens for an empty database.
+    pub fn load<F>(
+        page_size: usize,
+        reserved_size: usize,
+        num_pages: usize,
+        mut read: F,
+    ) -> (FreePageMap, Vec<PageId>)
+    where
+        F: FnMut(PageId, &mut [u8]),
+    {
+        let mut map = FreePageMap {
+            page_size,
+            reserved_size,
+            pages_per_bitmap: pages_per_bitmap(page_size, reserved_size),
+            bitmaps: Vec::new(),
+            first_free_group: 0,
+        };
+        let mut created = Vec::new();
+        let mut group = 0;
+        while map.bitmap_page_id(group) < num_pages as PageId {
+            let mut bitmap = vec![0u8; page_size].into_boxed_slice();
+            read(map.bitmap_page_id(group), &mut bitmap);
+            map.bitmaps.push(bitmap);
+            group += 1;
+        }
+        if map.bitmaps.is_empty() {
+            created.push(map.add_group());
+        }
+        (map, created)
+    }
+
+    /// Returns true if the given page is a bitmap page, which must never be touched by anyone but
+    /// the disk manager.
+    #[inline]
+    pub fn is_bitmap_page(&self, pid: PageId) -> bool {
+        pid >= 0 && pid as usize % self.pages_per_bitmap == 1
+    }
+
+    /// Returns true if the given page has been allocated
+    pub fn is_allocated(&self, pid: PageId) -> bool {
+        if pid < 0 {
+            return false;
+        }
+        let (group, bit) = self.locate(pid);
+        match self.bitmaps.get(group) {
+            Some(bitmap) => bitmap[bit / 8] & (1 << (bit % 8)) != 0,
+            None => false,
+        }
+    }
+
+    /// Returns the number of pages covered by the map, i.e. the maximum size of the database in
+    /// pages without adding another group.
+    pub fn capacity(&self) -> usize {
+        self.bitmaps.len() * self.pages_per_bitmap
+    }
+
+    /// Allocate the free page with the lowest page id. Returns the page id of the allocated page,
+    /// and the id of the bitmap page which has been modified.
+    pub fn allocate(&mut self) -> (PageId, PageId) {
+        let bitmap_size = self.page_size - self.reserved_size;
+        for group in self.first_free_group..self.bitmaps.len() {
+            if let Some(bit) = FreePageMap::first_zero_bit(&self.bitmaps[group][..bitmap_size]) {
+                self.first_free_group = group;
+                self.bitmaps[group][bit / 8] |= 1 << (bit % 8);
+                let pid = (group * self.pages_per_bitmap + bit) as PageId;
+                return (pid, self.bitmap_page_id(group));
+            }
+        }
+        self.first_free_group = self.bitmaps.len();
+        self.add_group();
+        self.allocate()
+    }
+
+    /// Mark the given page as free. Returns the id of the bitmap page which has been modified, or
+    /// None if the page can't be deallocated.
+    pub fn deallocate(&mut self, pid: PageId) -> Option<PageId> {
+        if pid == HEADER_PAGE_ID || self.is_bitmap_page(pid) || !self.is_allocated(pid) {
+            return None;
+        }
+        let (group, bit) = self.locate(pid);
+        self.bitmaps[group][bit / 8] &= !(1 << (bit % 8));
+        self.first_free_group = self.first_free_group.min(group);
+        Some(self.bitmap_page_id(group))
+    }
+
+    /// Returns the content of the given bitmap page
+    pub fn bitmap(&self, bitmap_pid: PageId) -> &[u8] {
+        assert!(self.is_bitmap_page(bitmap_pid));
+        &self.bitmaps[bitmap_pid as usize / self.pages_per_bitmap]
+    }
+
+    /// Append a new group whose pages are all free, except the reserved ones. Returns the id of
+    /// its bitmap page.
+    fn add_group(&mut self) -> PageId {
+        let group = self.bitmaps.len();
+        let mut bitmap = vec![0u8; self.page_size].into_boxed_slice();
+        if group == 0 {
+            // the header page
+            bitmap[0] |= 1;
+        }
+        // the bitmap page itself
+        bitmap[0] |= 1 << 1;
+        self.bitmaps.push(bitmap);
+        self.bitmap_page_id(group)
+    }
+
+    #[inline]
+    fn bitmap_page_id(&self, group: usize) -> PageId {
+        (group * self.pages_per_bitmap + 1) as PageId
+    }
+
+    #[inline]
+    fn locate(&self, pid: PageId) -> (usize, usize) {
+        let pid = pid as usize;
+        (pid / self.pages_per_bitmap, pid % self.pages_per_bitmap)
+    }
+
+    fn first_zero_bit(bitmap: &[u8]) -> Option<usize> {
+        bitmap
+            .iter()
+            .position(|b| *b != 0xff)
+            .map(|idx| idx * 8 + bitmap[idx].trailing_ones() as usize)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::common::config::{HEADER_PAGE_ID, PAGE_SIZE, PAGE_TRAILER_SIZE};
+    use crate::storage::disk::allocator::{pages_per_bitmap, FreePageMap};
+    use std::collections::HashMap;
+
+    const PAGES_PER_BITMAP: usize = pages_per_bitmap(PAGE_SIZE, PAGE_TRAILER_SIZE);
+
+    #[test]
+    fn allocate_deallocate() {
+        let (mut map, created) =
+            FreePageMap::load(PAGE_SIZE, PAGE_TRAILER_SIZE, 0, |_, _| unreachable!());
+        assert_eq!(created, vec![1]);
+        assert!(map.is_allocated(HEADER_PAGE_ID));
+        assert!(map.is_allocated(1));
+
+        assert_eq!(map.allocate(), (2, 1));
+        assert_eq!(map.allocate(), (3, 1));
+        assert_eq!(map.allocate(), (4, 1));
+        assert_eq!(map.deallocate(3), Some(1));
+        assert_eq!(map.deallocate(3), None);
+        assert!(!map.is_allocated(3));
+        // freed pages are reused first
+        assert_eq!(map.allocate(), (3, 1));
+        assert_eq!(map.allocate(), (5, 1));
+
+        // reserved pages can't be freed
+        assert_eq!(map.deallocate(HEADER_PAGE_ID), None);
+        assert_eq!(map.deallocate(1), None);
+    }
+
+    #[test]
+    fn grow_and_reload() {
+        let (mut map, _) = FreePageMap::load(PAGE_SIZE, PAGE_TRAILER_SIZE, 0, |_, _| {});
+        for expected in 2..PAGES_PER_BITMAP {
+            assert_eq!(map.allocate().0 as usize, expected);
+        }
+        // the first page of the next group is a normal page, followed by its bitmap
+        let (pid, bitmap_pid) = map.allocate();
+        assert_eq!(pid as usize, PAGES_PER_BITMAP);
+        assert_eq!(bitmap_pid as usize, PAGES_PER_BITMAP + 1);
+        assert!(map.is_bitmap_page(bitmap_pid));
+        assert_eq!(map.allocate().0 as usize, PAGES_PER_BITMAP + 2);
+        assert_eq!(map.capacity(), 2 * PAGES_PER_BITMAP);
+        map.deallocate(42);
+
+        let stored: HashMap<_, _> = [1, bitmap_pid]
+            .iter()
+            .map(|pid| (*pid, map.bitmap(*pid).to_vec()))
+            .collect();
+        let (mut reloaded, created) = FreePageMap::load(
+            PAGE_SIZE,
+            PAGE_TRAILER_SIZE,
+            PAGES_PER_BITMAP + 3,
+            |pid, buf| {
+                assert_eq!(buf.len(), PAGE_SIZE);
+                buf.copy_from_slice(&stored[&pid]);
+            },
+        );
+        assert!(created.is_empty());
+        assert!(!reloaded.is_allocated(42));
+        assert!(reloaded.is_allocated(43));
+        assert_eq!(reloaded.allocate().0, 42);
+        assert_eq!(reloaded.allocate().0 as usize, PAGES_PER_BITMAP + 3);
+    }
+
+    #[test]
+    fn page_size() {
+        let (mut map, created) = FreePageMap::load(1024, PAGE_TRAILER_SIZE, 0, |_, _| {});
+        assert_eq!(created, vec![1]);
+        assert_eq!(map.bitmap(1).len(), 1024);
+        let n = pages_per_bitmap(1024, PAGE_TRAILER_SIZE);
+        for _ in 2..n {
+            map.allocate();
+        }
+        assert_eq!(map.allocate(), (n as i32, n as i32 + 1));
+        assert!(map.is_bitmap_page(n as i32 + 1));
+    }
+}
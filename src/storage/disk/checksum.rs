@@ -0,0 +1,247 @@
+use crate::common::config::{PageId, TablespaceId, DEFAULT_TABLESPACE_ID, PAGE_TRAILER_SIZE};
+use crate::common::error::{IOContext, Result};
+use crate::storage::disk::compression::{self, ExtentMap};
+use crate::storage::disk::superblock::Superblock;
+use crate::storage::disk::tablespace::{self, page_id_in, Tablespace};
+use crate::RustubError;
+use bytes::{Buf, BufMut};
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::path::Path;
+
+// Every page written by the disk manager ends with a trailer, which lets us detect a page that has
+// been corrupted on disk, torn by a crash in the middle of a write, or written to the wrong place.
+//
+// Trailer format (size in byte):
+// -------------------------------------------------
+// | ... page data ... | page id (4) | checksum (4) |
+// -------------------------------------------------
+//
+// The checksum is a CRC32 of everything before it, including the page id. A page which is all
+// zeros has never been written and is valid.
+
+/// Why a page is considered to be corrupted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Corruption {
+    /// The checksum doesn't match the content, e.g. the page is torn or bits have flipped
+    ChecksumMismatch,
+    /// The page is intact, but it has been written for another page id
+    Misplaced(PageId),
+    /// The record of a compressed page can't be decoded
+    InvalidRecord,
+}
+
+impl Corruption {
+    #[inline]
+    pub fn reason(&self) -> &'static str {
+        match self {
+            Corruption::ChecksumMismatch => "checksum mismatch",
+            Corruption::Misplaced(_) => "misplaced page",
+            Corruption::InvalidRecord => "invalid compressed record",
+        }
+    }
+}
+
+impl Display for Corruption {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Corruption::ChecksumMismatch => write!(f, "checksum mismatch"),
+            Corruption::Misplaced(pid) => write!(f, "page belongs to page id {}", pid),
+            Corruption::InvalidRecord => write!(f, "invalid compressed record"),
+        }
+    }
+}
+
+/// Stamp the trailer of the page which is going to be written as `pid`. The trailer is at the end
+/// of `data`, whatever the page size is.
+pub fn stamp_page(pid: PageId, data: &mut [u8]) {
+    let offset_page_id = data.len() - PAGE_TRAILER_SIZE;
+    let offset_checksum = offset_page_id + 4;
+    (&mut data[offset_page_id..]).put_i32(pid);
+    let checksum = crc32fast::hash(&data[..offset_checksum]);
+    (&mut data[offset_checksum..]).put_u32(checksum);
+}
+
+/// Verify the trailer of the page which has been read as `pid`.
+pub fn verify_page(pid: PageId, data: &[u8]) -> std::result::Result<(), Corruption> {
+    let offset_page_id = data.len() - PAGE_TRAILER_SIZE;
+    let offset_checksum = offset_page_id + 4;
+    let checksum = (&data[offset_checksum..]).get_u32();
+    if checksum != crc32fast::hash(&data[..offset_checksum]) {
+        if data.iter().all(|b| *b == 0) {
+            // never written
+            return Ok(());
+        }
+        return Err(Corruption::ChecksumMismatch);
+    }
+    let stamped = (&data[offset_page_id..]).get_i32();
+    if stamped != pid {
+        return Err(Corruption::Misplaced(stamped));
+    }
+    Ok(())
+}
+
+/// The result of verifying a whole database
+#[derive(Debug, Default)]
+pub struct VerifyReport {
+    /// Page size of the database, as recorded in its superblock
+    pub page_size: usize,
+    /// Number of pages which have been checked
+    pub num_pages: usize,
+    /// All pages which failed the check
+    pub corrupted: Vec<(PageId, Corruption)>,
+    /// Size of the incomplete pages at the end of the segment files, if any
+    pub trailing_bytes: usize,
+}
+
+impl VerifyReport {
+    /// Returns true if no problem has been found
+    pub fn is_ok(&self) -> bool {
+        self.corrupted.is_empty() && self.trailing_bytes == 0
+    }
+}
+
+/// Scan every page of the given database, in every segment file of every tablespace, and report
+/// the corrupted ones. The database must not be opened by anyone else while it is verified. A file
+/// without a valid superblock is rejected.
+pub fn verify_database_file(db_file: &str) -> Result<VerifyReport> {
+    let file = fs::File::open(db_file)
+        .map_err(|e| RustubError::IOError(e, IOContext::Other("can't open database file")))?;
+    let superblock = Superblock::read_from_file(&file)?;
+    let mut report = VerifyReport {
+        page_size: superblock.page_size,
+        ..VerifyReport::default()
+    };
+    verify_tablespace(
+        &superblock,
+        DEFAULT_TABLESPACE_ID,
+        file,
+        Path::new(db_file),
+        &mut report,
+    )?;
+    for info in tablespace::load_catalog(&tablespace::catalog_path(db_file))? {
+        let path = tablespace::tablespace_path(db_file, &info);
+        let file = fs::File::open(&path)
+            .map_err(|e| RustubError::IOError(e, IOContext::Other("can't open segment file")))?;
+        verify_tablespace(&superblock, info.id, file, &path, &mut report)?;
+    }
+    Ok(report)
+}
+
+/// Scan every page of the tablespace whose first segment file is `first`, opened from `path`.
+fn verify_tablespace(
+    database: &Superblock,
+    id: TablespaceId,
+    first: fs::File,
+    path: &Path,
+    report: &mut VerifyReport,
+) -> Result<()> {
+    if Superblock::read_from_file(&first)?.compressed {
+        return verify_compressed_tablespace(database, id, first, path, report);
+    }
+    let page_size = database.page_size;
+    let mut buf = vec![0u8; page_size];
+    let mut file = first;
+    for segment in 0.. {
+        if segment > 0 {
+            file = match fs::File::open(tablespace::segment_path(path, segment)) {
+                Ok(file) => file,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => break,
+                Err(e) => {
+                    return Err(RustubError::IOError(
+                        e,
+                        IOContext::Other("can't open segment file"),
+                    ))
+                }
+            };
+        }
+        if !Superblock::read_from_file(&file)?.is_segment_of(database, id, segment) {
+            return Err(RustubError::IncompatibleDatabase(
+                "segment file belongs to another database",
+            ));
+        }
+        // the pages start right after the block of the superblock
+        for page in 0..database.segment_pages {
+            let pid = page_id_in(id, segment * database.segment_pages + page);
+            let offset = (page + 1) as u64 * page_size as u64;
+            let n = Tablespace::read_at(&file, &mut buf, offset)
+                .map_err(|e| RustubError::IOError(e, IOContext::Page(pid)))?;
+            if n < page_size {
+                report.trailing_bytes += n;
+                break;
+            }
+            if let Err(c) = verify_page(pid, &buf) {
+                report.corrupted.push((pid, c));
+            }
+            report.num_pages += 1;
+        }
+    }
+    Ok(())
+}
+
+/// Scan every page of the compressed tablespace whose first segment file is `first`, as listed in
+/// its extent map.
+fn verify_compressed_tablespace(
+    database: &Superblock,
+    id: TablespaceId,
+    first: fs::File,
+    path: &Path,
+    report: &mut VerifyReport,
+) -> Result<()> {
+    let map = ExtentMap::load(
+        &compression::extent_map_path(path),
+        database.page_size,
+        database.segment_pages,
+    )?;
+    let mut buf = vec![0u8; database.page_size];
+    let mut segments = vec![first];
+    for (page_no, extent) in map.pages() {
+        let pid = page_id_in(id, page_no as usize);
+        let (segment, offset) = map.locate(extent);
+        while segments.len() <= segment {
+            let n = segments.len();
+            let file = fs::File::open(tablespace::segment_path(path, n)).map_err(|e| {
+                RustubError::IOError(e, IOContext::Other("can't open segment file"))
+            })?;
+            if !Superblock::read_from_file(&file)?.is_segment_of(database, id, n) {
+                return Err(RustubError::IncompatibleDatabase(
+                    "segment file belongs to another database",
+                ));
+            }
+            segments.push(file);
+        }
+        let mut record = vec![0u8; extent.len as usize];
+        let n = Tablespace::read_at(&segments[segment], &mut record, offset)
+            .map_err(|e| RustubError::IOError(e, IOContext::Page(pid)))?;
+        let result = match n == record.len() {
+            true => compression::decode_record(pid, &record, &mut buf),
+            false => Err(Corruption::InvalidRecord),
+        };
+        if let Err(c) = result.and_then(|_| verify_page(pid, &buf)) {
+            report.corrupted.push((pid, c));
+        }
+        report.num_pages += 1;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::common::config::PAGE_SIZE;
+    use crate::storage::disk::checksum::{stamp_page, verify_page, Corruption};
+
+    #[test]
+    fn stamp_verify() {
+        let mut page = [0u8; PAGE_SIZE];
+        // a page which has never been written
+        assert_eq!(verify_page(3, &page), Ok(()));
+
+        page[..5].copy_from_slice(b"hello");
+        stamp_page(3, &mut page);
+        assert_eq!(verify_page(3, &page), Ok(()));
+        assert_eq!(verify_page(4, &page), Err(Corruption::Misplaced(3)));
+
+        page[100] ^= 1;
+        assert_eq!(verify_page(3, &page), Err(Corruption::ChecksumMismatch));
+    }
+}
@@ -0,0 +1,515 @@
+use crate::common::config::PageId;
+use crate::common::error::{IOContext, Result};
+use crate::storage::disk::checksum::Corruption;
+use crate::storage::disk::tablespace;
+use crate::RustubError;
+use bytes::{Buf, BufMut};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::Path;
+
+/// Number of chunks in the size of a page. The records of compressed pages are stored in runs of
+/// chunks, so a page takes at least `page_size / CHUNKS_PER_PAGE` bytes on disk.
+pub const CHUNKS_PER_PAGE: usize = 8;
+const RECORD_HEADER_SIZE: usize = 8;
+/// Set in the length of a record whose page is stored as is, because it doesn't compress
+const UNCOMPRESSED: u32 = 1 << 31;
+const ENTRY_SIZE: usize = 16;
+
+// In a compressed tablespace, pages don't have a fixed position in the segment files. Every page
+// is compressed on its own and stored as a record in a run of contiguous chunks of a segment,
+// which is never split across segments. The segment files still start with their superblock:
+//
+// ------------------------------------------------------------------------------
+// | superblock | chunk 0 | chunk 1 | ... | chunk (segment_pages * CHUNKS_PER_PAGE - 1) |
+// ------------------------------------------------------------------------------
+//
+// Record format (size in byte):
+// --------------------------------------------------------------------
+// | page id (4) | length (4) | compressed page, or the page as is |
+// --------------------------------------------------------------------
+//
+// The length is the size of the rest of the record. Its highest bit is set if the page is stored
+// as is, because it doesn't get any smaller. The page is compressed with its trailer, which is
+// verified once decompressed.
+//
+// The extent of every page, i.e. where its record is, is kept in memory and saved in an extent map
+// file next to the first segment, with the `.extents` extension. The extent map file is replaced
+// on every sync. A page is always written to a free extent, and the extent of its previous record
+// is only reused once the extent map file doesn't refer to it anymore. After a crash, the extent
+// map file and the records it refers to are the state of the tablespace as of the last sync.
+//
+// Extent map file format (size in byte):
+// ---------------------------------------------------------
+// | count (4) | entry 1 | ... | entry count | checksum (4) |
+// ---------------------------------------------------------
+//
+// Entry format (size in byte):
+// ----------------------------------------------------
+// | page number (4) | first chunk (8) | length (4) |
+// ----------------------------------------------------
+//
+// Chunks are numbered across segments, chunk `c` being chunk `c % chunks per segment` of segment
+// `c / chunks per segment`. The length is the size of the whole record.
+
+/// Where the record of a page is stored
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Extent {
+    /// The first chunk, numbered across segments
+    pub start: u64,
+    /// Size of the record in bytes
+    pub len: u32,
+}
+
+/// Returns the extent map file of the compressed tablespace whose first segment is `path`
+pub(crate) fn extent_map_path(path: &Path) -> std::path::PathBuf {
+    let mut path = path.as_os_str().to_os_string();
+    path.push(".extents");
+    path.into()
+}
+
+/// Encode the record of the given page, which has its trailer stamped.
+pub(crate) fn encode_record(pid: PageId, page: &[u8]) -> Vec<u8> {
+    let mut record =
+        vec![0u8; RECORD_HEADER_SIZE + lz4_flex::block::get_maximum_output_size(page.len())];
+    let len = lz4_flex::block::compress_into(page, &mut record[RECORD_HEADER_SIZE..])
+        .expect("the output is large enough for any page");
+    let len = if len < page.len() {
+        len as u32
+    } else {
+        record[RECORD_HEADER_SIZE..RECORD_HEADER_SIZE + page.len()].copy_from_slice(page);
+        page.len() as u32 | UNCOMPRESSED
+    };
+    (&mut record[..]).put_i32(pid);
+    (&mut record[4..]).put_u32(len);
+    record.truncate(RECORD_HEADER_SIZE + (len & !UNCOMPRESSED) as usize);
+    record
+}
+
+/// Decode the record which has been read for `pid` into the given page. The trailer of the page is
+/// left for the caller to verify.
+pub(crate) fn decode_record(
+    pid: PageId,
+    record: &[u8],
+    page: &mut [u8],
+) -> std::result::Result<(), Corruption> {
+    if record.len() < RECORD_HEADER_SIZE {
+        return Err(Corruption::InvalidRecord);
+    }
+    let stored = (&record[..]).get_i32();
+    let len = (&record[4..]).get_u32();
+    let payload = &record[RECORD_HEADER_SIZE..];
+    if (len & !UNCOMPRESSED) as usize != payload.len() {
+        return Err(Corruption::InvalidRecord);
+    }
+    if stored != pid {
+        return Err(Corruption::Misplaced(stored));
+    }
+    if len & UNCOMPRESSED != 0 {
+        if payload.len() != page.len() {
+            return Err(Corruption::InvalidRecord);
+        }
+        page.copy_from_slice(payload);
+        return Ok(());
+    }
+    match lz4_flex::block::decompress_into(payload, page) {
+        Ok(n) if n == page.len() => Ok(()),
+        _ => Err(Corruption::InvalidRecord),
+    }
+}
+
+/// The extents of an `ExtentMap` at some point, encoded as the extent map file, along with the
+/// chunks released before that point.
+pub(crate) struct ExtentSnapshot {
+    data: Vec<u8>,
+    released: Vec<(u64, u64)>,
+}
+
+impl ExtentSnapshot {
+    /// Replace the extent map file at `path` with the extents of the snapshot
+    pub fn store(&self, path: &Path) -> Result<()> {
+        tablespace::write_file_atomically(path, &self.data, "can't write extent map")
+    }
+}
+
+/// ExtentMap tracks where the record of every page of a compressed tablespace is, and which chunks
+/// are free.
+///
+/// THREAD SAFETY: NO
+pub(crate) struct ExtentMap {
+    chunk_size: usize,
+    chunks_per_segment: u64,
+    /// The extent of every page which has been written, by page number
+    extents: HashMap<u32, Extent>,
+    /// Runs of free chunks, by their first chunk. A run never spans two segments.
+    free: BTreeMap<u64, u64>,
+    /// Runs of chunks which aren't used anymore, but are still referred to by the extent map file
+    pending: Vec<(u64, u64)>,
+    /// All chunks from this one on have never been used
+    end: u64,
+}
+
+impl ExtentMap {
+    pub fn new(page_size: usize, segment_pages: usize) -> Self {
+        ExtentMap {
+            chunk_size: page_size / CHUNKS_PER_PAGE,
+            chunks_per_segment: (segment_pages * CHUNKS_PER_PAGE) as u64,
+            extents: HashMap::new(),
+            free: BTreeMap::new(),
+            pending: Vec::new(),
+            end: 0,
+        }
+    }
+
+    /// Load the extent map file at `path`. A tablespace without extent map file has never been
+    /// synced, and has no page.
+    pub fn load(path: &Path, page_size: usize, segment_pages: usize) -> Result<Self> {
+        let mut map = ExtentMap::new(page_size, segment_pages);
+        let data = match fs::read(path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(map),
+            Err(e) => {
+                return Err(RustubError::IOError(
+                    e,
+                    IOContext::Other("can't read extent map"),
+                ))
+            }
+        };
+        let corrupted = || RustubError::IncompatibleDatabase("corrupted extent map");
+        if data.len() < 8 {
+            return Err(corrupted());
+        }
+        let (mut entries, mut checksum) = data.split_at(data.len() - 4);
+        if checksum.get_u32() != crc32fast::hash(entries) {
+            return Err(corrupted());
+        }
+        let count = entries.get_u32() as usize;
+        if entries.remaining() != count * ENTRY_SIZE {
+            return Err(corrupted());
+        }
+        let mut used = Vec::with_capacity(count);
+        for _ in 0..count {
+            let page_no = entries.get_u32();
+            let extent = Extent {
+                start: entries.get_u64(),
+                len: entries.get_u32(),
+            };
+            used.push((extent.start, extent.start + map.num_chunks(extent.len)));
+            map.extents.insert(page_no, extent);
+        }
+        // everything between the extents in use is free
+        used.sort_unstable();
+        for (start, end) in used {
+            if start < map.end {
+                return Err(corrupted());
+            }
+            map.free_range(map.end, start);
+            map.end = end;
+        }
+        Ok(map)
+    }
+
+    /// Replace the extent map file at `path` with the current extents. The chunks which have been
+    /// released since the previous save can be reused from now on.
+    pub fn store(&mut self, path: &Path) -> Result<()> {
+        let snapshot = self.snapshot();
+        let result = snapshot.store(path);
+        self.release(snapshot, result.is_ok());
+        result
+    }
+
+    /// Returns a copy of the current extents, to be stored once the records they refer to are
+    /// durable. The chunks released so far are handed over to the snapshot, see `release`.
+    pub fn snapshot(&mut self) -> ExtentSnapshot {
+        let mut data = Vec::with_capacity(8 + self.extents.len() * ENTRY_SIZE);
+        data.put_u32(self.extents.len() as u32);
+        for (page_no, extent) in self.pages() {
+            data.put_u32(page_no);
+            data.put_u64(extent.start);
+            data.put_u32(extent.len);
+        }
+        let checksum = crc32fast::hash(&data);
+        data.put_u32(checksum);
+        ExtentSnapshot {
+            data,
+            released: std::mem::take(&mut self.pending),
+        }
+    }
+
+    /// Take back the chunks released before the given snapshot was taken. They can be reused if the
+    /// snapshot has been stored, since the extent map file doesn't refer to them anymore, or else
+    /// they stay pending until the next save.
+    pub fn release(&mut self, snapshot: ExtentSnapshot, stored: bool) {
+        if !stored {
+            self.pending.extend(snapshot.released);
+            return;
+        }
+        for (start, len) in snapshot.released {
+            self.free_run(start, len);
+        }
+    }
+
+    /// Returns the extent of the given page, if it has ever been written
+    #[inline]
+    pub fn get(&self, page_no: u32) -> Option<Extent> {
+        self.extents.get(&page_no).copied()
+    }
+
+    /// Returns every page which has been written with its extent, by page number
+    pub fn pages(&self) -> Vec<(u32, Extent)> {
+        let mut pages: Vec<_> = self.extents.iter().map(|(p, e)| (*p, *e)).collect();
+        pages.sort_unstable_by_key(|(page_no, _)| *page_no);
+        pages
+    }
+
+    /// Returns the number of pages covered by the map, i.e. one past the highest page written
+    pub fn num_pages(&self) -> usize {
+        self.extents
+            .keys()
+            .max()
+            .map_or(0, |page_no| *page_no as usize + 1)
+    }
+
+    /// Returns the segment holding the given extent, and the offset of the extent in it
+    pub fn locate(&self, extent: Extent) -> (usize, u64) {
+        let segment = extent.start / self.chunks_per_segment;
+        let chunk = extent.start % self.chunks_per_segment;
+        // the chunks come after the block of the superblock
+        let offset = (chunk + CHUNKS_PER_PAGE as u64) * self.chunk_size as u64;
+        (segment as usize, offset)
+    }
+
+    /// Allocate an extent for a record of `len` bytes, using the first free run which is large
+    /// enough, or the chunks which have never been used.
+    pub fn allocate(&mut self, len: usize) -> Extent {
+        let n = self.num_chunks(len as u32);
+        assert!(
+            n <= self.chunks_per_segment,
+            "a record must fit in a segment"
+        );
+        let run = self
+            .free
+            .iter()
+            .find(|(_, run)| **run >= n)
+            .map(|(s, r)| (*s, *r));
+        let start = match run {
+            Some((start, run)) => {
+                self.free.remove(&start);
+                if run > n {
+                    self.free.insert(start + n, run - n);
+                }
+                start
+            }
+            None => {
+                let mut start = self.end;
+                let segment_end = (start / self.chunks_per_segment + 1) * self.chunks_per_segment;
+                if start + n > segment_end {
+                    // skip the end of the segment, which is too small
+                    self.free_run(start, segment_end - start);
+                    start = segment_end;
+                }
+                self.end = start + n;
+                start
+            }
+        };
+        Extent {
+            start,
+            len: len as u32,
+        }
+    }
+
+    /// Make the given extent the one of the page. Its previous extent is released once the extent
+    /// map file doesn't refer to it anymore.
+    pub fn install(&mut self, page_no: u32, extent: Extent) {
+        if let Some(old) = self.extents.insert(page_no, extent) {
+            let n = self.num_chunks(old.len);
+            self.pending.push((old.start, n));
+        }
+    }
+
+    /// Forget the extent of a page which has been deallocated. The extent is released once the
+    /// extent map file doesn't refer to it anymore, and the page reads as zeros meanwhile.
+    pub fn remove(&mut self, page_no: u32) {
+        if let Some(old) = self.extents.remove(&page_no) {
+            let n = self.num_chunks(old.len);
+            self.pending.push((old.start, n));
+        }
+    }
+
+    /// Give back an extent which has been allocated, but isn't used.
+    pub fn discard(&mut self, extent: Extent) {
+        let n = self.num_chunks(extent.len);
+        self.free_run(extent.start, n);
+    }
+
+    /// Returns the number of chunks taken by a record of `len` bytes
+    #[inline]
+    fn num_chunks(&self, len: u32) -> u64 {
+        (len as u64).div_ceil(self.chunk_size as u64)
+    }
+
+    /// Free the chunks from `start` to `end`, which may span several segments.
+    fn free_range(&mut self, mut start: u64, end: u64) {
+        while start < end {
+            let segment_end = (start / self.chunks_per_segment + 1) * self.chunks_per_segment;
+            let run_end = segment_end.min(end);
+            self.free_run(start, run_end - start);
+            start = run_end;
+        }
+    }
+
+    /// Free a run of chunks of a single segment, merging it with its free neighbours.
+    fn free_run(&mut self, mut start: u64, mut len: u64) {
+        if len == 0 {
+            return;
+        }
+        if !start.is_multiple_of(self.chunks_per_segment) {
+            let prev = self.free.range(..start).next_back().map(|(s, l)| (*s, *l));
+            if let Some((prev_start, prev_len)) = prev {
+                if prev_start + prev_len == start {
+                    self.free.remove(&prev_start);
+                    start = prev_start;
+                    len += prev_len;
+                }
+            }
+        }
+        let end = start + len;
+        if !end.is_multiple_of(self.chunks_per_segment) {
+            if let Some(next_len) = self.free.remove(&end) {
+                len += next_len;
+            }
+        }
+        self.free.insert(start, len);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::common::config::PAGE_SIZE;
+    use crate::storage::disk::checksum::Corruption;
+    use crate::storage::disk::compression::{decode_record, encode_record, Extent, ExtentMap};
+    use std::fs;
+    use std::path::Path;
+
+    #[test]
+    fn encode_decode_record() {
+        let mut page = [0u8; PAGE_SIZE];
+        for (i, b) in page.iter_mut().enumerate() {
+            *b = b"archived row "[i % 13];
+        }
+        let record = encode_record(7, &page);
+        assert!(record.len() < PAGE_SIZE / 8);
+        let mut decoded = [0u8; PAGE_SIZE];
+        decode_record(7, &record, &mut decoded).unwrap();
+        assert_eq!(decoded, page);
+        assert_eq!(
+            decode_record(8, &record, &mut decoded),
+            Err(Corruption::Misplaced(7))
+        );
+        assert_eq!(
+            decode_record(7, &record[..record.len() - 1], &mut decoded),
+            Err(Corruption::InvalidRecord)
+        );
+
+        // a page which doesn't compress is stored as is
+        let mut state = 1u32;
+        for b in page.iter_mut() {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            *b = (state >> 16) as u8;
+        }
+        let record = encode_record(7, &page);
+        assert_eq!(record.len(), PAGE_SIZE + 8);
+        decode_record(7, &record, &mut decoded).unwrap();
+        assert_eq!(decoded, page);
+    }
+
+    #[test]
+    fn allocate_and_reload_extents() {
+        // 2 pages per segment, i.e. 16 chunks of 512 bytes
+        let mut map = ExtentMap::new(PAGE_SIZE, 2);
+        let a = map.allocate(1000);
+        assert_eq!(
+            a,
+            Extent {
+                start: 0,
+                len: 1000
+            }
+        );
+        map.install(0, a);
+        let b = map.allocate(PAGE_SIZE + 8);
+        assert_eq!(b.start, 2);
+        map.install(1, b);
+        // 5 chunks are left in the first segment, too few for another full page
+        let c = map.allocate(PAGE_SIZE + 8);
+        assert_eq!(c.start, 16);
+        assert_eq!(map.locate(c), (1, PAGE_SIZE as u64));
+        map.install(2, c);
+        assert_eq!(map.allocate(2000).start, 11);
+        map.discard(Extent {
+            start: 11,
+            len: 2000,
+        });
+
+        // the old extent is only reused once the map has been saved
+        let path = Path::new("allocate_and_reload_extents.extents");
+        let a2 = map.allocate(500);
+        assert_eq!(a2.start, 11);
+        map.install(0, a2);
+        assert_eq!(map.allocate(1000).start, 12);
+        map.store(path).unwrap();
+        assert_eq!(map.allocate(1000).start, 0);
+
+        let reloaded = ExtentMap::load(path, PAGE_SIZE, 2).unwrap();
+        assert_eq!(reloaded.get(0), Some(a2));
+        assert_eq!(reloaded.get(2), Some(c));
+        assert_eq!(reloaded.get(3), None);
+        assert_eq!(reloaded.num_pages(), 3);
+        let mut reloaded = reloaded;
+        assert_eq!(reloaded.allocate(1000).start, 0);
+        assert_eq!(reloaded.allocate(2000).start, 12);
+
+        // the extent of a deallocated page is reused once the map has been saved
+        reloaded.remove(2);
+        assert_eq!(reloaded.get(2), None);
+        assert_eq!(reloaded.allocate(PAGE_SIZE + 8).start, 32);
+        reloaded.store(path).unwrap();
+        assert_eq!(reloaded.allocate(PAGE_SIZE + 8).start, 16);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn write_while_storing_extents() {
+        let path = Path::new("write_while_storing_extents.extents");
+        let mut map = ExtentMap::new(PAGE_SIZE, 2);
+        let a = map.allocate(1000);
+        map.install(0, a);
+        let snapshot = map.snapshot();
+        // a page written while the segment files are synced
+        let a2 = map.allocate(1000);
+        map.install(0, a2);
+        snapshot.store(path).unwrap();
+        map.release(snapshot, true);
+
+        // the extent map file refers to the old record, which is kept until the next save
+        assert_eq!(ExtentMap::load(path, PAGE_SIZE, 2).unwrap().get(0), Some(a));
+        assert_eq!(map.get(0), Some(a2));
+        assert_eq!(map.allocate(1000).start, 4);
+        map.store(path).unwrap();
+        assert_eq!(
+            ExtentMap::load(path, PAGE_SIZE, 2).unwrap().get(0),
+            Some(a2)
+        );
+        assert_eq!(map.allocate(1000).start, 0);
+
+        // the chunks released before a snapshot which couldn't be stored stay pending
+        let a3 = map.allocate(1000);
+        assert_eq!(a3.start, 6);
+        map.install(0, a3);
+        let snapshot = map.snapshot();
+        map.release(snapshot, false);
+        assert_eq!(map.allocate(1000).start, 8);
+        map.store(path).unwrap();
+        assert_eq!(map.allocate(1000).start, 2);
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,272 @@
+use crate::common::config::{PageId, PAGE_TRAILER_SIZE};
+use crate::common::error::{IOContext, Result};
+use crate::RustubError;
+use chacha20::cipher::{KeyIvInit, StreamCipher, StreamCipherSeek};
+use chacha20::ChaCha20;
+use chacha20poly1305::aead::{AeadCore, AeadInPlace, OsRng};
+use chacha20poly1305::{ChaCha20Poly1305, KeyInit, Nonce, Tag};
+use std::fmt::{Debug, Formatter};
+use std::fs;
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+
+/// Size of every key, in bytes
+pub const KEY_SIZE: usize = 32;
+const NONCE_SIZE: usize = 12;
+const TAG_SIZE: usize = 16;
+/// Number of bytes of an encrypted page which hold its nonce and authentication tag
+pub const ENCRYPTION_OVERHEAD: usize = NONCE_SIZE + TAG_SIZE;
+/// The nonce of the key stream of the log, see `Cipher::apply_log_keystream`
+pub(crate) type LogNonce = [u8; NONCE_SIZE];
+/// Size of the data keys of a database, once wrapped by the key of the key file
+pub const WRAPPED_KEYS_SIZE: usize = NONCE_SIZE + 2 * KEY_SIZE + TAG_SIZE;
+
+// Encryption follows the envelope scheme. The pages and the log are encrypted with data keys which
+// are picked at random when the database is created, and never change. The data keys are stored
+// in the superblock, wrapped (i.e. encrypted) with the key supplied by the user in a key file.
+// Rotating the key file only rewraps the data keys, and a wrong key fails to unwrap them before
+// anything else is read.
+//
+// Pages are encrypted with ChaCha20-Poly1305 and a random nonce per write. The page id is the
+// associated data, so a page copied to another place doesn't decrypt. The nonce and the tag are
+// stored right before the page trailer, which is computed over the encrypted page:
+//
+// Encrypted page format (size in byte):
+// -------------------------------------------------------------------------
+// | ... encrypted page data ... | nonce (12) | tag (16) | page id (4) | checksum (4) |
+// -------------------------------------------------------------------------
+//
+// The log is addressed by byte offsets and must keep its size, so it is encrypted with the ChaCha20
+// key stream of the log key, at the offset of every byte in the log. The nonce of the key stream is
+// picked at random and stored in the header of the log file. It is renewed, and the log encrypted
+// again, every time the log is opened, since a flush which failed or didn't survive a crash may
+// have used the key stream past the end of the log. The log isn't authenticated: a tampered log
+// decrypts to garbage instead of failing, so log records must carry their own checksum.
+
+/// EncryptionKey is the key supplied by the user to open an encrypted database. A key file holds
+/// either the 32 bytes of the key, or their 64 hex digits.
+pub struct EncryptionKey([u8; KEY_SIZE]);
+
+impl EncryptionKey {
+    /// Pick a random key
+    pub fn generate() -> Self {
+        EncryptionKey(ChaCha20Poly1305::generate_key(&mut OsRng).into())
+    }
+
+    /// Read the key from the given key file.
+    pub fn load<P: AsRef<Path>>(key_file: P) -> Result<Self> {
+        let data = fs::read(key_file)
+            .map_err(|e| RustubError::IOError(e, IOContext::Other("can't read key file")))?;
+        let mut key = [0u8; KEY_SIZE];
+        if data.len() == KEY_SIZE {
+            key.copy_from_slice(&data);
+            return Ok(EncryptionKey(key));
+        }
+        let hex = data.trim_ascii();
+        if hex.len() != 2 * KEY_SIZE {
+            return Err(RustubError::EncryptionError("invalid key file"));
+        }
+        for (i, digits) in hex.chunks(2).enumerate() {
+            let digits = std::str::from_utf8(digits)
+                .map_err(|_| RustubError::EncryptionError("invalid key file"))?;
+            key[i] = u8::from_str_radix(digits, 16)
+                .map_err(|_| RustubError::EncryptionError("invalid key file"))?;
+        }
+        Ok(EncryptionKey(key))
+    }
+
+    /// Write the key as hex digits into a new key file, which only its owner can read.
+    pub fn store<P: AsRef<Path>>(&self, key_file: P) -> Result<()> {
+        let hex: String = self.0.iter().map(|b| format!("{:02x}", b)).collect();
+        fs::File::options()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(key_file)
+            .and_then(|mut file| {
+                file.write_all(hex.as_bytes())?;
+                file.sync_all()
+            })
+            .map_err(|e| RustubError::IOError(e, IOContext::Other("can't write key file")))
+    }
+}
+
+impl Debug for EncryptionKey {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "EncryptionKey(..)")
+    }
+}
+
+impl Drop for EncryptionKey {
+    fn drop(&mut self) {
+        self.0.fill(0u8);
+    }
+}
+
+/// Cipher encrypts the pages and the log of a database with its data keys.
+///
+/// THREAD SAFETY: YES
+pub(crate) struct Cipher {
+    page_key: [u8; KEY_SIZE],
+    log_key: [u8; KEY_SIZE],
+    page: ChaCha20Poly1305,
+}
+
+impl Cipher {
+    /// Create the cipher of a new database, with random data keys
+    pub fn generate() -> Self {
+        Cipher::new(
+            ChaCha20Poly1305::generate_key(&mut OsRng).into(),
+            ChaCha20Poly1305::generate_key(&mut OsRng).into(),
+        )
+    }
+
+    fn new(page_key: [u8; KEY_SIZE], log_key: [u8; KEY_SIZE]) -> Self {
+        Cipher {
+            page_key,
+            log_key,
+            page: ChaCha20Poly1305::new(&page_key.into()),
+        }
+    }
+
+    /// Returns the data keys wrapped with the given key
+    pub fn wrap(&self, key: &EncryptionKey) -> [u8; WRAPPED_KEYS_SIZE] {
+        let mut wrapped = [0u8; WRAPPED_KEYS_SIZE];
+        let (nonce, rest) = wrapped.split_at_mut(NONCE_SIZE);
+        let (keys, tag) = rest.split_at_mut(2 * KEY_SIZE);
+        nonce.copy_from_slice(&ChaCha20Poly1305::generate_nonce(&mut OsRng));
+        keys[..KEY_SIZE].copy_from_slice(&self.page_key);
+        keys[KEY_SIZE..].copy_from_slice(&self.log_key);
+        let computed = ChaCha20Poly1305::new(&key.0.into())
+            .encrypt_in_place_detached(Nonce::from_slice(nonce), b"", keys)
+            .expect("the data keys are small enough");
+        tag.copy_from_slice(&computed);
+        wrapped
+    }
+
+    /// Unwrap the data keys with the given key. Fails if the keys have been wrapped with another
+    /// key.
+    pub fn unwrap(wrapped: &[u8; WRAPPED_KEYS_SIZE], key: &EncryptionKey) -> Result<Self> {
+        let mut keys = [0u8; 2 * KEY_SIZE];
+        keys.copy_from_slice(&wrapped[NONCE_SIZE..NONCE_SIZE + 2 * KEY_SIZE]);
+        let nonce = Nonce::from_slice(&wrapped[..NONCE_SIZE]);
+        let tag = Tag::from_slice(&wrapped[NONCE_SIZE + 2 * KEY_SIZE..]);
+        ChaCha20Poly1305::new(&key.0.into())
+            .decrypt_in_place_detached(nonce, b"", &mut keys, tag)
+            .map_err(|_| RustubError::EncryptionError("wrong encryption key"))?;
+        let mut page_key = [0u8; KEY_SIZE];
+        let mut log_key = [0u8; KEY_SIZE];
+        page_key.copy_from_slice(&keys[..KEY_SIZE]);
+        log_key.copy_from_slice(&keys[KEY_SIZE..]);
+        keys.fill(0u8);
+        Ok(Cipher::new(page_key, log_key))
+    }
+
+    /// Encrypt the page which is going to be written as `pid`. The nonce and the tag are stored
+    /// right before the trailer, which is left for the caller to stamp.
+    pub fn encrypt_page(&self, pid: PageId, page: &mut [u8]) {
+        let (body, reserved) =
+            page.split_at_mut(page.len() - PAGE_TRAILER_SIZE - ENCRYPTION_OVERHEAD);
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let tag = self
+            .page
+            .encrypt_in_place_detached(&nonce, &pid.to_be_bytes(), body)
+            .expect("a page is small enough");
+        reserved[..NONCE_SIZE].copy_from_slice(&nonce);
+        reserved[NONCE_SIZE..ENCRYPTION_OVERHEAD].copy_from_slice(&tag);
+    }
+
+    /// Decrypt the page which has been read as `pid`, whose trailer has been verified. A page which
+    /// has never been written is all zeros, and is left as is.
+    pub fn decrypt_page(&self, pid: PageId, page: &mut [u8]) -> Result<()> {
+        if page.iter().all(|b| *b == 0) {
+            return Ok(());
+        }
+        let (body, reserved) =
+            page.split_at_mut(page.len() - PAGE_TRAILER_SIZE - ENCRYPTION_OVERHEAD);
+        let nonce = Nonce::from_slice(&reserved[..NONCE_SIZE]);
+        let tag = Tag::from_slice(&reserved[NONCE_SIZE..ENCRYPTION_OVERHEAD]);
+        self.page
+            .decrypt_in_place_detached(nonce, &pid.to_be_bytes(), body, tag)
+            .map_err(|_| RustubError::PageCorrupted(pid, "decryption failed"))
+    }
+
+    /// Pick the nonce of the key stream of a log file at random
+    pub fn generate_log_nonce() -> LogNonce {
+        ChaCha20Poly1305::generate_nonce(&mut OsRng).into()
+    }
+
+    /// Encrypt or decrypt the log data which starts at the given offset in a log file encrypted
+    /// with the given nonce.
+    pub fn apply_log_keystream(&self, nonce: &LogNonce, offset: u64, data: &mut [u8]) {
+        let mut stream = ChaCha20::new(&self.log_key.into(), nonce.into());
+        stream.seek(offset);
+        stream.apply_keystream(data);
+    }
+}
+
+impl Drop for Cipher {
+    fn drop(&mut self) {
+        self.page_key.fill(0u8);
+        self.log_key.fill(0u8);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::common::config::PAGE_SIZE;
+    use crate::storage::disk::encryption::{Cipher, EncryptionKey, ENCRYPTION_OVERHEAD};
+    use crate::RustubError;
+    use std::fs;
+
+    #[test]
+    fn wrap_and_encrypt() {
+        let key = EncryptionKey::generate();
+        let cipher = Cipher::generate();
+        let wrapped = cipher.wrap(&key);
+        let unwrapped = Cipher::unwrap(&wrapped, &key).unwrap();
+        assert!(matches!(
+            Cipher::unwrap(&wrapped, &EncryptionKey::generate()),
+            Err(RustubError::EncryptionError("wrong encryption key"))
+        ));
+
+        let data_size = PAGE_SIZE - 8 - ENCRYPTION_OVERHEAD;
+        let mut page = [7u8; PAGE_SIZE];
+        cipher.encrypt_page(3, &mut page);
+        assert_ne!(
+            page[..data_size],
+            [7u8; PAGE_SIZE - 8 - ENCRYPTION_OVERHEAD]
+        );
+        let mut copy = page;
+        unwrapped.decrypt_page(3, &mut page).unwrap();
+        assert_eq!(
+            page[..data_size],
+            [7u8; PAGE_SIZE - 8 - ENCRYPTION_OVERHEAD]
+        );
+        // the page id is authenticated
+        assert!(unwrapped.decrypt_page(4, &mut copy).is_err());
+        let mut zeros = [0u8; PAGE_SIZE];
+        cipher.decrypt_page(5, &mut zeros).unwrap();
+
+        // the log can be decrypted from any offset, with the nonce it has been encrypted with
+        let nonce = Cipher::generate_log_nonce();
+        let mut log = *b"hello, encrypted log";
+        cipher.apply_log_keystream(&nonce, 100, &mut log);
+        assert_ne!(&log, b"hello, encrypted log");
+        let mut other = *b"hello, encrypted log";
+        cipher.apply_log_keystream(&Cipher::generate_log_nonce(), 100, &mut other);
+        assert_ne!(log, other);
+        unwrapped.apply_log_keystream(&nonce, 107, &mut log[7..]);
+        assert_eq!(&log[7..], b"encrypted log");
+
+        let key_file = "wrap_and_encrypt.key";
+        let _ = fs::remove_file(key_file);
+        key.store(key_file).unwrap();
+        assert!(key.store(key_file).is_err());
+        Cipher::unwrap(&wrapped, &EncryptionKey::load(key_file).unwrap()).unwrap();
+        fs::write(key_file, "not a key").unwrap();
+        assert!(EncryptionKey::load(key_file).is_err());
+        fs::remove_file(key_file).unwrap();
+    }
+}
@@ -0,0 +1,318 @@
+use crate::common::config::{Lsn, PageId, TablespaceId};
+use crate::common::error::{IOContext, Result};
+use crate::storage::disk::{DiskManager, DiskMetricsSnapshot, FlushLogFuture};
+use crate::RustubError;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+use std::io::ErrorKind;
+use std::sync::Mutex;
+
+/// What happens to a page write which has been scripted to fail
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum WriteFault {
+    /// Nothing is written
+    Fail,
+    /// Only the first bytes of the page are written, the rest keeps its old content
+    Torn(usize),
+}
+
+#[derive(Default)]
+struct FaultState {
+    /// Number of page writes and log appends seen so far
+    num_writes: u64,
+    num_appends: u64,
+    /// Page writes and log appends to fail, by their sequence number
+    write_faults: HashMap<u64, WriteFault>,
+    append_faults: HashSet<u64>,
+    /// Pages whose reads return corrupted bytes, and the offset of the corrupted byte
+    corrupt_reads: HashMap<PageId, usize>,
+    /// Content of the pages before their first write since the last sync
+    before_images: HashMap<PageId, Box<[u8]>>,
+}
+
+/// FaultInjectingDiskManager wraps any disk manager and lets tests script failures in a
+/// deterministic way: failing the Nth page write or log append, tearing a page write, corrupting
+/// the bytes of page reads, and crashing.
+///
+/// Page writes go through to the wrapped disk manager, but are only considered durable after
+/// `sync`. A simulated `crash` puts every page written since the last sync back to what it was
+/// before, as if the writes had never left the OS page cache. Page allocation and the log are
+/// durable right away.
+///
+/// THREAD SAFETY: YES
+pub struct FaultInjectingDiskManager<D: DiskManager> {
+    inner: D,
+    state: Mutex<FaultState>,
+}
+
+impl<D: DiskManager> FaultInjectingDiskManager<D> {
+    pub fn new(inner: D) -> Self {
+        FaultInjectingDiskManager {
+            inner,
+            state: Mutex::new(FaultState::default()),
+        }
+    }
+
+    /// Returns the wrapped disk manager
+    pub fn inner(&self) -> &D {
+        &self.inner
+    }
+
+    /// Unwrap the disk manager. Unsynced page writes are kept.
+    pub fn into_inner(self) -> D {
+        self.inner
+    }
+
+    /// Fail the n-th page write from now on, 1 being the next one. Nothing is written.
+    pub fn fail_nth_write(&self, n: u64) {
+        let mut state = self.state.lock().unwrap();
+        let seq = state.num_writes + n;
+        state.write_faults.insert(seq, WriteFault::Fail);
+    }
+
+    /// Tear the n-th page write from now on, 1 being the next one. Only the first `len` bytes of
+    /// the page are written before the write fails. A torn page stays torn across a crash.
+    pub fn tear_nth_write(&self, n: u64, len: usize) {
+        assert!(len < self.inner.page_size());
+        let mut state = self.state.lock().unwrap();
+        let seq = state.num_writes + n;
+        state.write_faults.insert(seq, WriteFault::Torn(len));
+    }
+
+    /// Fail the n-th log append from now on, 1 being the next one. Nothing is appended.
+    pub fn fail_nth_append(&self, n: u64) {
+        let mut state = self.state.lock().unwrap();
+        let seq = state.num_appends + n;
+        state.append_faults.insert(seq);
+    }
+
+    /// Flip the bits of the byte at `offset` whenever the given page is read. The page on disk is
+    /// left intact.
+    pub fn corrupt_reads(&self, pid: PageId, offset: usize) {
+        assert!(offset < self.inner.page_size());
+        self.state.lock().unwrap().corrupt_reads.insert(pid, offset);
+    }
+
+    /// Forget every scripted fault which hasn't fired yet
+    pub fn clear_faults(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.write_faults.clear();
+        state.append_faults.clear();
+        state.corrupt_reads.clear();
+    }
+
+    /// Simulate a power loss: every page written since the last sync gets its old content back,
+    /// byte for byte, even if it was torn or corrupted.
+    pub fn crash(&self) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        let mut pids: Vec<_> = state.before_images.keys().copied().collect();
+        pids.sort_unstable();
+        for pid in pids {
+            let page = state.before_images.remove(&pid).unwrap();
+            self.inner.write_raw_page(pid, &page)?;
+        }
+        self.inner.sync()
+    }
+
+    /// Remember the content of the page as it's stored before its first write since the last sync.
+    /// The page isn't verified, so that a torn or corrupted page can be rewritten.
+    fn save_before_image(&self, state: &mut FaultState, pid: PageId) -> Result<()> {
+        if let Entry::Vacant(entry) = state.before_images.entry(pid) {
+            let mut page = vec![0u8; self.inner.page_size()].into_boxed_slice();
+            self.inner.read_raw_page(pid, &mut page)?;
+            entry.insert(page);
+        }
+        Ok(())
+    }
+}
+
+impl<D: DiskManager> DiskManager for FaultInjectingDiskManager<D> {
+    fn allocate_page(&self) -> Result<PageId> {
+        self.inner.allocate_page()
+    }
+
+    fn allocate_page_in(&self, tablespace: TablespaceId) -> Result<PageId> {
+        self.inner.allocate_page_in(tablespace)
+    }
+
+    fn deallocate_page(&self, pid: PageId) -> Result<()> {
+        self.inner.deallocate_page(pid)
+    }
+
+    fn write_page(&self, pid: PageId, data: &[u8]) -> Result<()> {
+        assert_eq!(data.len(), self.inner.page_size());
+        let mut state = self.state.lock().unwrap();
+        state.num_writes += 1;
+        let seq = state.num_writes;
+        match state.write_faults.remove(&seq) {
+            Some(WriteFault::Fail) => Err(RustubError::IOError(
+                std::io::Error::other("injected write failure"),
+                IOContext::Page(pid),
+            )),
+            Some(WriteFault::Torn(len)) => {
+                // the page is stamped as a whole by the wrapped disk manager, then only its first
+                // bytes are kept over the old stored page, so that the trailer doesn't match
+                let mut old = vec![0u8; data.len()];
+                self.inner.read_raw_page(pid, &mut old)?;
+                self.inner.write_page(pid, data)?;
+                let mut page = vec![0u8; data.len()];
+                self.inner.read_raw_page(pid, &mut page)?;
+                page[len..].copy_from_slice(&old[len..]);
+                self.inner.write_raw_page(pid, &page)?;
+                // what is on disk now must survive a crash
+                state.before_images.remove(&pid);
+                Err(RustubError::IOError(
+                    std::io::Error::new(ErrorKind::Interrupted, "injected torn write"),
+                    IOContext::Page(pid),
+                ))
+            }
+            None => {
+                self.save_before_image(&mut state, pid)?;
+                self.inner.write_page(pid, data)
+            }
+        }
+    }
+
+    fn read_page(&self, pid: PageId, data: &mut [u8]) -> Result<()> {
+        self.inner.read_page(pid, data)?;
+        if let Some(offset) = self.state.lock().unwrap().corrupt_reads.get(&pid) {
+            data[*offset] ^= 0xff;
+        }
+        Ok(())
+    }
+
+    fn write_raw_page(&self, pid: PageId, data: &[u8]) -> Result<()> {
+        self.inner.write_raw_page(pid, data)
+    }
+
+    fn read_raw_page(&self, pid: PageId, data: &mut [u8]) -> Result<()> {
+        self.inner.read_raw_page(pid, data)
+    }
+
+    fn page_size(&self) -> usize {
+        self.inner.page_size()
+    }
+
+    fn reserved_size(&self) -> usize {
+        self.inner.reserved_size()
+    }
+
+    fn sync(&self) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        self.inner.sync()?;
+        state.before_images.clear();
+        Ok(())
+    }
+
+    fn append_log(&self, data: &[u8]) -> Result<FlushLogFuture> {
+        let mut state = self.state.lock().unwrap();
+        state.num_appends += 1;
+        let seq = state.num_appends;
+        if state.append_faults.remove(&seq) {
+            return Err(RustubError::IOError(
+                std::io::Error::other("injected log append failure"),
+                IOContext::Log(self.inner.flushed_lsn() as u64),
+            ));
+        }
+        self.inner.append_log(data)
+    }
+
+    fn read_log(&self, data: &mut [u8], offset: u32) -> Result<usize> {
+        self.inner.read_log(data, offset)
+    }
+
+    fn flushed_lsn(&self) -> Lsn {
+        self.inner.flushed_lsn()
+    }
+
+    fn flush_log(&self, lsn: Lsn) -> Result<()> {
+        self.inner.flush_log(lsn)
+    }
+
+    fn is_flushed(&self) -> bool {
+        self.inner.is_flushed()
+    }
+
+    fn metrics(&self) -> DiskMetricsSnapshot {
+        self.inner.metrics()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::common::config::PAGE_SIZE;
+    use crate::common::error::IOContext;
+    use crate::storage::disk::{DiskManager, FaultInjectingDiskManager, InMemDiskManager};
+    use crate::RustubError;
+
+    #[test]
+    fn fail_nth_write_and_append() {
+        let dm = FaultInjectingDiskManager::new(InMemDiskManager::new());
+        let pid = dm.allocate_page().unwrap();
+        let mut buf = [0u8; PAGE_SIZE];
+
+        dm.fail_nth_write(2);
+        dm.write_page(pid, &[1u8; PAGE_SIZE][..]).unwrap();
+        assert!(matches!(
+            dm.write_page(pid, &[2u8; PAGE_SIZE][..]),
+            Err(RustubError::IOError(_, IOContext::Page(p))) if p == pid
+        ));
+        dm.read_page(pid, &mut buf[..]).unwrap();
+        assert_eq!(buf, [1u8; PAGE_SIZE]);
+        dm.write_page(pid, &[3u8; PAGE_SIZE][..]).unwrap();
+
+        dm.fail_nth_append(1);
+        assert!(dm.write_log(&b"lost"[..]).is_err());
+        dm.write_log(&b"kept"[..]).unwrap();
+        let mut log = [0u8; 4];
+        assert_eq!(dm.read_log(&mut log[..], 0).unwrap(), 4);
+        assert_eq!(&log[..], &b"kept"[..]);
+    }
+
+    #[test]
+    fn crash_drops_unsynced_writes() {
+        let dm = FaultInjectingDiskManager::new(InMemDiskManager::new());
+        let synced = dm.allocate_page().unwrap();
+        let unsynced = dm.allocate_page().unwrap();
+        let mut buf = [0u8; PAGE_SIZE];
+
+        dm.write_page(synced, &[1u8; PAGE_SIZE][..]).unwrap();
+        dm.sync().unwrap();
+        dm.write_page(synced, &[2u8; PAGE_SIZE][..]).unwrap();
+        dm.write_page(synced, &[3u8; PAGE_SIZE][..]).unwrap();
+        dm.write_page(unsynced, &[4u8; PAGE_SIZE][..]).unwrap();
+        // unsynced writes are visible until the crash
+        dm.read_page(unsynced, &mut buf[..]).unwrap();
+        assert_eq!(buf, [4u8; PAGE_SIZE]);
+
+        dm.crash().unwrap();
+        let dm = dm.into_inner();
+        dm.read_page(synced, &mut buf[..]).unwrap();
+        assert_eq!(buf, [1u8; PAGE_SIZE]);
+        dm.read_page(unsynced, &mut buf[..]).unwrap();
+        assert_eq!(buf, [0u8; PAGE_SIZE]);
+    }
+
+    #[test]
+    fn torn_write_and_corrupted_read() {
+        let dm = FaultInjectingDiskManager::new(InMemDiskManager::new());
+        let pid = dm.allocate_page().unwrap();
+        let mut buf = [0u8; PAGE_SIZE];
+        dm.write_page(pid, &[1u8; PAGE_SIZE][..]).unwrap();
+        dm.sync().unwrap();
+
+        dm.tear_nth_write(1, 512);
+        assert!(dm.write_page(pid, &[2u8; PAGE_SIZE][..]).is_err());
+        dm.crash().unwrap();
+        dm.read_page(pid, &mut buf[..]).unwrap();
+        assert_eq!(buf[..512], [2u8; 512]);
+        assert_eq!(buf[512..], [1u8; PAGE_SIZE - 512]);
+
+        dm.corrupt_reads(pid, 0);
+        dm.read_page(pid, &mut buf[..]).unwrap();
+        assert_eq!(buf[0], 2 ^ 0xff);
+        dm.clear_faults();
+        dm.read_page(pid, &mut buf[..]).unwrap();
+        assert_eq!(buf[0], 2);
+    }
+}
@@ -0,0 +1,359 @@
+use crate::common::config::Lsn;
+use crate::common::error::{IOContext, Result};
+use crate::storage::disk::encryption::{Cipher, LogNonce};
+use crate::storage::disk::metrics::DiskMetrics;
+use crate::storage::disk::tablespace;
+use crate::RustubError;
+use bytes::{Buf, BufMut};
+use std::fs;
+use std::io::{ErrorKind, Write};
+use std::mem;
+use std::os::unix::fs::FileExt;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::Instant;
+
+/// Size of the header of the log file of an encrypted database, which holds the nonce of the key
+/// stream of the log. The log of a database which isn't encrypted has no header.
+pub(crate) const LOG_HEADER_SIZE: usize = 16;
+const LOG_MAGIC: u32 = 0x5255_4c47;
+
+/// Give the log file of an encrypted database a new random nonce, and encrypt the log again with
+/// it. Returns the log file opened for appending, its size and the new nonce.
+///
+/// Log header format (size in byte):
+/// ----------------------------
+/// | magic (4) | nonce (12) |
+/// ----------------------------
+pub(crate) fn renew_log_nonce(path: &Path, cipher: &Cipher) -> Result<(fs::File, u64, LogNonce)> {
+    let context = "can't renew the nonce of the log";
+    let mut log = fs::read(path).map_err(|e| RustubError::IOError(e, IOContext::Other(context)))?;
+    if log.is_empty() {
+        log.resize(LOG_HEADER_SIZE, 0);
+    } else {
+        if log.len() < LOG_HEADER_SIZE || (&log[..]).get_u32() != LOG_MAGIC {
+            return Err(RustubError::IncompatibleDatabase(
+                "log file has no valid header",
+            ));
+        }
+        let mut old = LogNonce::default();
+        old.copy_from_slice(&log[4..LOG_HEADER_SIZE]);
+        cipher.apply_log_keystream(&old, 0, &mut log[LOG_HEADER_SIZE..]);
+    }
+    let nonce = Cipher::generate_log_nonce();
+    (&mut log[..]).put_u32(LOG_MAGIC);
+    log[4..LOG_HEADER_SIZE].copy_from_slice(&nonce);
+    cipher.apply_log_keystream(&nonce, 0, &mut log[LOG_HEADER_SIZE..]);
+    tablespace::write_file_atomically(path, &log, context)?;
+    let io = fs::File::options()
+        .append(true)
+        .read(true)
+        .open(path)
+        .map_err(|e| RustubError::IOError(e, IOContext::Other("can't open log file")))?;
+    Ok((io, log.len() as u64, nonce))
+}
+
+/// FlushLogFuture is handed out for every log append. It is resolved once the appended data is
+/// durable, i.e. it has been written and synced to the log file.
+pub struct FlushLogFuture {
+    /// The end of the appended data in the log
+    lsn: Lsn,
+    /// None if the data is already durable when the future is created
+    log: Option<Arc<LogShared>>,
+}
+
+impl FlushLogFuture {
+    /// Create a future which has already been resolved
+    pub fn ready(lsn: Lsn) -> Self {
+        FlushLogFuture { lsn, log: None }
+    }
+
+    /// Returns the LSN which has to be durable before the future is resolved
+    #[inline]
+    pub fn lsn(&self) -> Lsn {
+        self.lsn
+    }
+
+    /// Returns true if the appended data is durable
+    pub fn is_ready(&self) -> bool {
+        match &self.log {
+            Some(log) => log.durable_lsn.load(Ordering::Acquire) >= self.lsn as u64,
+            None => true,
+        }
+    }
+
+    /// Block until the appended data is durable. Returns the durable LSN at that moment, which can
+    /// be beyond the LSN of this future since appends are flushed in batches.
+    pub fn wait(&self) -> Result<Lsn> {
+        let log = match &self.log {
+            Some(log) => log,
+            None => return Ok(self.lsn),
+        };
+        let mut state = log.state.lock().unwrap();
+        loop {
+            if state.durable_lsn >= self.lsn as u64 {
+                return Ok(state.durable_lsn as Lsn);
+            }
+            if let Some((kind, msg)) = &state.error {
+                return Err(RustubError::IOError(
+                    std::io::Error::new(*kind, msg.clone()),
+                    IOContext::Log(state.durable_lsn),
+                ));
+            }
+            state = log.flushed.wait(state).unwrap();
+        }
+    }
+}
+
+struct LogState {
+    /// Appended data which hasn't been handed to the flusher yet
+    buffer: Vec<u8>,
+    /// End of the log, including the buffered data
+    appended_lsn: u64,
+    /// End of the durable part of the log
+    durable_lsn: u64,
+    /// Set once a flush fails. The log can't be trusted anymore and every following append fails.
+    error: Option<(ErrorKind, String)>,
+    shutdown: bool,
+}
+
+struct LogShared {
+    state: Mutex<LogState>,
+    /// Signaled when there is data to flush, or on shutdown
+    work: Condvar,
+    /// Signaled after every flush
+    flushed: Condvar,
+    /// Same as `LogState::durable_lsn`, published for lock-free readers
+    durable_lsn: AtomicU64,
+    metrics: Arc<DiskMetrics>,
+}
+
+/// LogWriter implements group commit on top of the log file. Appends are only copied into a buffer,
+/// while a background flusher repeatedly takes everything buffered so far and writes it with a
+/// single write followed by a single fsync. While a flush is in progress, all concurrent appends
+/// pile up in the buffer and are made durable together by the next flush, so the number of fsyncs
+/// doesn't grow with the number of committing transactions.
+///
+/// The LSN used here is the byte offset of the end of the appended data in the log file.
+///
+/// THREAD SAFETY: YES
+pub(crate) struct LogWriter {
+    shared: Arc<LogShared>,
+    /// Used by readers, never by the flusher
+    reader: fs::File,
+    /// Encrypts the log of an encrypted database, with the nonce of its log file
+    cipher: Option<(Arc<Cipher>, LogNonce)>,
+    /// Size of the header of the log file, which comes before the log
+    header_size: u64,
+    flusher: Option<thread::JoinHandle<()>>,
+}
+
+impl LogWriter {
+    /// Start a log writer appending to `io`, which is opened in append mode and `size` bytes long.
+    /// Appends and flushes are recorded into `metrics`. If a cipher is given, the log is encrypted
+    /// on the way to the file and decrypted on the way back, with the nonce of the log file given
+    /// by `renew_log_nonce`, and the log starts after the header of the file.
+    pub fn new(
+        io: fs::File,
+        size: u64,
+        metrics: Arc<DiskMetrics>,
+        cipher: Option<(Arc<Cipher>, LogNonce)>,
+    ) -> Result<Self> {
+        let header_size = match cipher {
+            Some(_) => LOG_HEADER_SIZE as u64,
+            None => 0,
+        };
+        let size = size - header_size;
+        let reader = io
+            .try_clone()
+            .map_err(|e| RustubError::IOError(e, IOContext::Other("can't open log file")))?;
+        let shared = Arc::new(LogShared {
+            state: Mutex::new(LogState {
+                buffer: Vec::new(),
+                appended_lsn: size,
+                durable_lsn: size,
+                error: None,
+                shutdown: false,
+            }),
+            work: Condvar::new(),
+            flushed: Condvar::new(),
+            durable_lsn: AtomicU64::new(size),
+            metrics,
+        });
+        let flusher = {
+            let shared = shared.clone();
+            thread::Builder::new()
+                .name("log-flusher".to_string())
+                .spawn(move || LogWriter::flush_loop(shared, io, header_size))
+                .map_err(|e| RustubError::IOError(e, IOContext::Other("can't start log flusher")))?
+        };
+        Ok(LogWriter {
+            shared,
+            reader,
+            cipher,
+            header_size,
+            flusher: Some(flusher),
+        })
+    }
+
+    /// Append the data to the log without waiting for it to be durable.
+    pub fn append(&self, data: &[u8]) -> Result<FlushLogFuture> {
+        let start = Instant::now();
+        let result = self.append_buffered(data);
+        self.shared
+            .metrics
+            .log_appends
+            .record(&result, data.len(), start);
+        result
+    }
+
+    fn append_buffered(&self, data: &[u8]) -> Result<FlushLogFuture> {
+        let mut state = self.shared.state.lock().unwrap();
+        if let Some((kind, msg)) = &state.error {
+            return Err(RustubError::IOError(
+                std::io::Error::new(*kind, msg.clone()),
+                IOContext::Log(state.appended_lsn),
+            ));
+        }
+        if data.is_empty() {
+            return Ok(FlushLogFuture::ready(state.appended_lsn as Lsn));
+        }
+        let lsn = state.appended_lsn + data.len() as u64;
+        if lsn > Lsn::MAX as u64 {
+            return Err(RustubError::IOError(
+                std::io::Error::other("log is full"),
+                IOContext::Log(state.appended_lsn),
+            ));
+        }
+        let start = state.buffer.len();
+        state.buffer.extend_from_slice(data);
+        if let Some((cipher, nonce)) = &self.cipher {
+            let offset = state.appended_lsn;
+            cipher.apply_log_keystream(nonce, offset, &mut state.buffer[start..]);
+        }
+        state.appended_lsn = lsn;
+        self.shared.work.notify_one();
+        Ok(FlushLogFuture {
+            lsn: lsn as Lsn,
+            log: Some(self.shared.clone()),
+        })
+    }
+
+    /// Block until everything appended so far is durable.
+    pub fn flush(&self) -> Result<()> {
+        let lsn = self.shared.state.lock().unwrap().appended_lsn;
+        self.flush_to(lsn)
+    }
+
+    /// Block until the log is durable up to the given LSN, which must not be beyond the end of
+    /// the log appended so far.
+    pub fn flush_to(&self, lsn: u64) -> Result<()> {
+        let appended_lsn = self.shared.state.lock().unwrap().appended_lsn;
+        if lsn > appended_lsn {
+            return Err(RustubError::IOError(
+                std::io::Error::other("LSN beyond the end of the log"),
+                IOContext::Log(appended_lsn),
+            ));
+        }
+        let future = FlushLogFuture {
+            lsn: lsn as Lsn,
+            log: Some(self.shared.clone()),
+        };
+        future.wait().map(|_| ())
+    }
+
+    /// Read the durable part of the log starting from `offset`. Returns the number of bytes read.
+    pub fn read(&self, data: &mut [u8], offset: u64) -> std::io::Result<usize> {
+        let durable = self.durable_lsn();
+        if offset >= durable {
+            return Ok(0);
+        }
+        let len = data.len().min((durable - offset) as usize);
+        let mut n = 0;
+        while n < len {
+            match self
+                .reader
+                .read_at(&mut data[n..len], self.header_size + offset + n as u64)
+            {
+                Ok(0) => break,
+                Ok(m) => n += m,
+                Err(ref e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        if let Some((cipher, nonce)) = &self.cipher {
+            cipher.apply_log_keystream(nonce, offset, &mut data[..n]);
+        }
+        Ok(n)
+    }
+
+    /// Returns the end of the durable part of the log
+    #[inline]
+    pub fn durable_lsn(&self) -> u64 {
+        self.shared.durable_lsn.load(Ordering::Acquire)
+    }
+
+    /// Returns true if everything appended so far is durable
+    pub fn is_flushed(&self) -> bool {
+        let state = self.shared.state.lock().unwrap();
+        state.durable_lsn == state.appended_lsn
+    }
+
+    fn flush_loop(shared: Arc<LogShared>, mut io: fs::File, header_size: u64) {
+        let mut batch = Vec::new();
+        loop {
+            let target = {
+                let mut state = shared.state.lock().unwrap();
+                while state.buffer.is_empty() && !state.shutdown {
+                    state = shared.work.wait(state).unwrap();
+                }
+                if state.buffer.is_empty() || state.error.is_some() {
+                    // shutdown, and there's nothing left to flush
+                    return;
+                }
+                mem::swap(&mut batch, &mut state.buffer);
+                state.appended_lsn
+            };
+
+            let start = Instant::now();
+            let result = io.write_all(&batch).and_then(|_| io.sync_data());
+            shared
+                .metrics
+                .log_flushes
+                .record(&result, batch.len(), start);
+            batch.clear();
+
+            let mut state = shared.state.lock().unwrap();
+            match result {
+                Ok(_) => {
+                    state.durable_lsn = target;
+                    shared.durable_lsn.store(target, Ordering::Release);
+                }
+                Err(e) => {
+                    error!("IO error while flushing log: {}", e);
+                    // Cut off whatever part of the batch made it into the file, so the log ends
+                    // with the last durable record. The key stream of an encrypted log is only
+                    // used again past that record once the log is opened with a new nonce.
+                    if let Err(e) = io.set_len(header_size + state.durable_lsn) {
+                        error!("Can't truncate log file after a failed flush: {}", e);
+                    }
+                    state.error = Some((e.kind(), e.to_string()));
+                }
+            }
+            shared.flushed.notify_all();
+        }
+    }
+}
+
+impl Drop for LogWriter {
+    /// Flush everything appended so far, then stop the flusher.
+    fn drop(&mut self) {
+        self.shared.state.lock().unwrap().shutdown = true;
+        self.shared.work.notify_one();
+        if let Some(flusher) = self.flusher.take() {
+            let _ = flusher.join();
+        }
+    }
+}
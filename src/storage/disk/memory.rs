@@ -0,0 +1,346 @@
+use crate::common::config::{Lsn, PageId, PAGE_SIZE, PAGE_TRAILER_SIZE};
+use crate::common::error::{IOContext, Result};
+use crate::storage::disk::allocator::FreePageMap;
+use crate::storage::disk::metrics::{DiskMetrics, DiskMetricsSnapshot};
+use crate::storage::disk::superblock::Superblock;
+use crate::storage::disk::{DiskManager, FlushLogFuture};
+use crate::RustubError;
+use std::collections::HashMap;
+use std::sync::RwLock;
+use std::time::Instant;
+
+/// A point-in-time copy of everything an `InMemDiskManager` holds. It can be used to restore the
+/// same or another in-memory disk manager later, e.g. to simulate a restart in tests.
+#[derive(Clone)]
+pub struct InMemSnapshot {
+    page_size: usize,
+    pages: HashMap<PageId, Box<[u8]>>,
+    log: Vec<u8>,
+}
+
+impl Default for InMemSnapshot {
+    fn default() -> Self {
+        InMemSnapshot {
+            page_size: PAGE_SIZE,
+            pages: HashMap::new(),
+            log: Vec::new(),
+        }
+    }
+}
+
+impl InMemSnapshot {
+    /// Returns the number of pages ever written
+    pub fn num_pages(&self) -> usize {
+        self.pages.len()
+    }
+
+    /// Returns the size of the log in bytes
+    pub fn log_size(&self) -> usize {
+        self.log.len()
+    }
+}
+
+/// InMemDiskManager keeps pages and log in memory and never touches the filesystem. It behaves like
+/// `FileBasedDiskManager`: reading a page which has never been written yields a zero-filled page,
+/// and the log is append only. Appended log data is durable right away.
+///
+/// THREAD SAFETY: YES
+pub struct InMemDiskManager {
+    page_size: usize,
+    pages: RwLock<HashMap<PageId, Box<[u8]>>>,
+    /// Free page bitmaps, which are also stored as pages to survive a snapshot
+    free_pages: RwLock<FreePageMap>,
+    log: RwLock<Vec<u8>>,
+
+    /// Nothing is ever synced, so only page reads, page writes and log appends are recorded
+    metrics: DiskMetrics,
+}
+
+impl InMemDiskManager {
+    pub fn new() -> Self {
+        InMemDiskManager::with_page_size(PAGE_SIZE)
+    }
+
+    /// Create a disk manager whose pages have the given size
+    pub fn with_page_size(page_size: usize) -> Self {
+        assert!(Superblock::is_valid_page_size(page_size));
+        let mut pages = HashMap::new();
+        let free_pages = InMemDiskManager::load_free_pages(page_size, &mut pages);
+        InMemDiskManager {
+            page_size,
+            pages: RwLock::new(pages),
+            free_pages: RwLock::new(free_pages),
+            log: RwLock::new(Vec::new()),
+            metrics: DiskMetrics::new(),
+        }
+    }
+
+    /// Create a disk manager with the contents of the given snapshot
+    pub fn with_snapshot(snapshot: &InMemSnapshot) -> Self {
+        let dm = InMemDiskManager::with_page_size(snapshot.page_size);
+        dm.restore(snapshot);
+        dm
+    }
+
+    /// Take a consistent copy of all pages and the log.
+    pub fn snapshot(&self) -> InMemSnapshot {
+        // Always latch pages before log, so a snapshot never observes a torn state.
+        let pages = self.pages.read().unwrap();
+        let log = self.log.read().unwrap();
+        InMemSnapshot {
+            page_size: self.page_size,
+            pages: pages.clone(),
+            log: log.clone(),
+        }
+    }
+
+    /// Replace all pages and the log with the contents of the given snapshot, which must have the
+    /// same page size. Counters are left untouched.
+    pub fn restore(&self, snapshot: &InMemSnapshot) {
+        assert_eq!(snapshot.page_size, self.page_size);
+        let mut free_pages = self.free_pages.write().unwrap();
+        let mut pages = self.pages.write().unwrap();
+        let mut log = self.log.write().unwrap();
+        *pages = snapshot.pages.clone();
+        *log = snapshot.log.clone();
+        *free_pages = InMemDiskManager::load_free_pages(self.page_size, &mut pages);
+    }
+
+    /// Load the free page bitmaps from the given pages, initializing them if there is none.
+    fn load_free_pages(page_size: usize, pages: &mut HashMap<PageId, Box<[u8]>>) -> FreePageMap {
+        let num_pages = pages.keys().max().map_or(0, |pid| *pid as usize + 1);
+        let (map, created) = FreePageMap::load(
+            page_size,
+            PAGE_TRAILER_SIZE,
+            num_pages,
+            |pid, buf| match pages.get(&pid) {
+                Some(page) => buf.copy_from_slice(page),
+                None => buf.fill(0u8),
+            },
+        );
+        for pid in created {
+            pages.insert(pid, Box::from(map.bitmap(pid)));
+        }
+        map
+    }
+
+    /// Persist the given bitmap page of the free page map
+    fn write_bitmap(&self, free_pages: &FreePageMap, bitmap_pid: PageId) {
+        let start = Instant::now();
+        let mut pages = self.pages.write().unwrap();
+        pages.insert(bitmap_pid, Box::from(free_pages.bitmap(bitmap_pid)));
+        self.metrics.page_writes.record_ok(self.page_size, start);
+    }
+}
+
+impl Default for InMemDiskManager {
+    fn default() -> Self {
+        InMemDiskManager::new()
+    }
+}
+
+impl DiskManager for InMemDiskManager {
+    fn allocate_page(&self) -> Result<PageId> {
+        let mut free_pages = self.free_pages.write().unwrap();
+        let (pid, bitmap_pid) = free_pages.allocate();
+        self.write_bitmap(&free_pages, bitmap_pid);
+        Ok(pid)
+    }
+
+    fn deallocate_page(&self, pid: PageId) -> Result<()> {
+        let mut free_pages = self.free_pages.write().unwrap();
+        match free_pages.deallocate(pid) {
+            Some(bitmap_pid) => {
+                self.write_bitmap(&free_pages, bitmap_pid);
+                Ok(())
+            }
+            None => Err(RustubError::PageNotAllocated(pid)),
+        }
+    }
+
+    fn write_page(&self, pid: PageId, data: &[u8]) -> Result<()> {
+        assert_eq!(data.len(), self.page_size);
+        let start = Instant::now();
+        let free_pages = self.free_pages.read().unwrap();
+        if free_pages.is_bitmap_page(pid) || !free_pages.is_allocated(pid) {
+            return Err(RustubError::PageNotAllocated(pid));
+        }
+        let mut pages = self.pages.write().unwrap();
+        match pages.get_mut(&pid) {
+            Some(page) => page.copy_from_slice(data),
+            None => {
+                pages.insert(pid, Box::from(data));
+            }
+        }
+        self.metrics.page_writes.record_ok(data.len(), start);
+        Ok(())
+    }
+
+    fn read_page(&self, pid: PageId, data: &mut [u8]) -> Result<()> {
+        assert_eq!(data.len(), self.page_size);
+        let start = Instant::now();
+        let pages = self.pages.read().unwrap();
+        match pages.get(&pid) {
+            Some(page) => data.copy_from_slice(page),
+            None => {
+                debug!("Read a page which has never been written");
+                data.fill(0u8);
+            }
+        }
+        self.metrics.page_reads.record_ok(data.len(), start);
+        Ok(())
+    }
+
+    #[inline]
+    fn page_size(&self) -> usize {
+        self.page_size
+    }
+
+    fn sync(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn append_log(&self, data: &[u8]) -> Result<FlushLogFuture> {
+        let start = Instant::now();
+        let mut log = self.log.write().unwrap();
+        if log.len() + data.len() > Lsn::MAX as usize {
+            let result = Err(RustubError::IOError(
+                std::io::Error::other("log is full"),
+                IOContext::Log(log.len() as u64),
+            ));
+            self.metrics.log_appends.record(&result, data.len(), start);
+            return result;
+        }
+        log.extend_from_slice(data);
+        self.metrics.log_appends.record_ok(data.len(), start);
+        Ok(FlushLogFuture::ready(log.len() as Lsn))
+    }
+
+    fn read_log(&self, data: &mut [u8], offset: u32) -> Result<usize> {
+        let log = self.log.read().unwrap();
+        let offset = offset as usize;
+        if offset >= log.len() {
+            debug!("end of log file");
+            return Ok(0);
+        }
+        let n = data.len().min(log.len() - offset);
+        data[..n].copy_from_slice(&log[offset..offset + n]);
+        data[n..].fill(0u8);
+        Ok(n)
+    }
+
+    #[inline]
+    fn flushed_lsn(&self) -> Lsn {
+        self.log.read().unwrap().len() as Lsn
+    }
+
+    /// The log is durable as soon as it's appended
+    fn flush_log(&self, lsn: Lsn) -> Result<()> {
+        let len = self.log.read().unwrap().len();
+        if lsn as usize > len {
+            return Err(RustubError::IOError(
+                std::io::Error::other("LSN beyond the end of the log"),
+                IOContext::Log(len as u64),
+            ));
+        }
+        Ok(())
+    }
+
+    #[inline]
+    fn is_flushed(&self) -> bool {
+        true
+    }
+
+    fn metrics(&self) -> DiskMetricsSnapshot {
+        self.metrics.snapshot()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::common::config::PAGE_SIZE;
+    use crate::storage::disk::{DiskManager, InMemDiskManager};
+
+    #[test]
+    fn read_write_page() {
+        let dm = InMemDiskManager::new();
+        let mut buf = [0u8; PAGE_SIZE];
+        let mut data = [0u8; PAGE_SIZE];
+        data[..14].copy_from_slice(b"A test string.");
+
+        // tolerate empty read
+        dm.read_page(0, &mut buf[..]).unwrap();
+        assert_eq!(buf, [0u8; PAGE_SIZE]);
+
+        dm.write_page(0, &data[..]).unwrap();
+        dm.read_page(0, &mut buf[..]).unwrap();
+        assert_eq!(buf, data);
+
+        let pid = dm.allocate_page().unwrap();
+        dm.write_page(pid, &[7u8; PAGE_SIZE][..]).unwrap();
+        dm.read_page(pid, &mut buf[..]).unwrap();
+        assert_eq!(buf, [7u8; PAGE_SIZE]);
+        dm.read_page(pid + 1, &mut buf[..]).unwrap();
+        assert_eq!(buf, [0u8; PAGE_SIZE]);
+    }
+
+    #[test]
+    fn read_write_log() {
+        let dm = InMemDiskManager::new();
+        let mut buf = [0u8; 8];
+        assert_eq!(dm.read_log(&mut buf[..], 0).unwrap(), 0);
+
+        dm.write_log(&b"12345"[..]).unwrap();
+        let future = dm.append_log(&b"678"[..]).unwrap();
+        assert!(future.is_ready());
+        assert_eq!(future.wait().unwrap(), 8);
+        dm.write_log(&[][..]).unwrap();
+        // appends are durable right away, without any flush
+        assert_eq!(dm.metrics().log_appends.count, 3);
+        assert_eq!(dm.metrics().log_appends.bytes, 8);
+        assert_eq!(dm.num_flushes(), 0);
+        assert_eq!(dm.flushed_lsn(), 8);
+        assert!(dm.is_flushed());
+
+        assert_eq!(dm.read_log(&mut buf[..], 0).unwrap(), 8);
+        assert_eq!(&buf[..], &b"12345678"[..]);
+        // a short read is padded with zeros
+        assert_eq!(dm.read_log(&mut buf[..], 5).unwrap(), 3);
+        assert_eq!(&buf[..], &b"678\0\0\0\0\0"[..]);
+        assert_eq!(dm.read_log(&mut buf[..], 8).unwrap(), 0);
+    }
+
+    #[test]
+    fn snapshot_restore() {
+        let dm = InMemDiskManager::new();
+        let mut buf = [0u8; PAGE_SIZE];
+        let first = dm.allocate_page().unwrap();
+        dm.write_page(first, &[1u8; PAGE_SIZE][..]).unwrap();
+        dm.write_log(&b"before"[..]).unwrap();
+        let snapshot = dm.snapshot();
+        // the written page and the free page bitmap
+        assert_eq!(snapshot.num_pages(), 2);
+        assert_eq!(snapshot.log_size(), 6);
+
+        dm.write_page(first, &[2u8; PAGE_SIZE][..]).unwrap();
+        let second = dm.allocate_page().unwrap();
+        dm.write_page(second, &[2u8; PAGE_SIZE][..]).unwrap();
+        dm.write_log(&b"after"[..]).unwrap();
+
+        // a restarted database only sees the snapshot, including its allocations
+        let restarted = InMemDiskManager::with_snapshot(&snapshot);
+        restarted.read_page(first, &mut buf[..]).unwrap();
+        assert_eq!(buf, [1u8; PAGE_SIZE]);
+        restarted.read_page(second, &mut buf[..]).unwrap();
+        assert_eq!(buf, [0u8; PAGE_SIZE]);
+        assert_eq!(restarted.allocate_page().unwrap(), second);
+
+        dm.restore(&snapshot);
+        dm.read_page(first, &mut buf[..]).unwrap();
+        assert_eq!(buf, [1u8; PAGE_SIZE]);
+        let mut log = [0u8; 6];
+        assert_eq!(dm.read_log(&mut log[..], 0).unwrap(), 6);
+        assert_eq!(&log[..], &b"before"[..]);
+        assert_eq!(dm.read_log(&mut log[..], 6).unwrap(), 0);
+    }
+}
@@ -0,0 +1,267 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// Number of latency buckets. Bucket `i` counts the operations which took less than `2^i`
+/// microseconds, the last one everything slower.
+pub const NUM_LATENCY_BUCKETS: usize = 24;
+
+/// A latency histogram with exponential buckets, which can be recorded into from many threads
+/// without any latch.
+///
+/// THREAD SAFETY: YES
+pub(crate) struct AtomicHistogram {
+    buckets: [AtomicU64; NUM_LATENCY_BUCKETS],
+    sum_nanos: AtomicU64,
+    max_nanos: AtomicU64,
+}
+
+impl AtomicHistogram {
+    pub fn new() -> Self {
+        AtomicHistogram {
+            buckets: std::array::from_fn(|_| AtomicU64::new(0)),
+            sum_nanos: AtomicU64::new(0),
+            max_nanos: AtomicU64::new(0),
+        }
+    }
+
+    pub fn record(&self, elapsed: Duration) {
+        let nanos = elapsed.as_nanos().min(u64::MAX as u128) as u64;
+        self.buckets[LatencyHistogram::bucket_of(elapsed)].fetch_add(1, Ordering::Relaxed);
+        self.sum_nanos.fetch_add(nanos, Ordering::Relaxed);
+        self.max_nanos.fetch_max(nanos, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> LatencyHistogram {
+        LatencyHistogram {
+            buckets: std::array::from_fn(|i| self.buckets[i].load(Ordering::Relaxed)),
+            sum: Duration::from_nanos(self.sum_nanos.load(Ordering::Relaxed)),
+            max: Duration::from_nanos(self.max_nanos.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+/// A point-in-time copy of a latency histogram
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct LatencyHistogram {
+    /// Number of operations in every bucket, see `LatencyHistogram::upper_bound`
+    pub buckets: [u64; NUM_LATENCY_BUCKETS],
+    /// Total time spent by all operations
+    pub sum: Duration,
+    /// Time spent by the slowest operation
+    pub max: Duration,
+}
+
+impl LatencyHistogram {
+    /// Returns the exclusive upper bound of the given bucket, or None for the last bucket which
+    /// has no bound.
+    pub fn upper_bound(bucket: usize) -> Option<Duration> {
+        if bucket + 1 < NUM_LATENCY_BUCKETS {
+            Some(Duration::from_micros(1 << bucket))
+        } else {
+            None
+        }
+    }
+
+    /// Returns the number of operations recorded
+    pub fn count(&self) -> u64 {
+        self.buckets.iter().sum()
+    }
+
+    /// Returns the average latency, zero if nothing has been recorded
+    pub fn mean(&self) -> Duration {
+        match self.count() {
+            0 => Duration::ZERO,
+            n => Duration::from_nanos((self.sum.as_nanos() / n as u128) as u64),
+        }
+    }
+
+    /// Returns an upper bound of the latency below which the given fraction (0.0 to 1.0) of the
+    /// operations fall. The precision is limited by the bucket size, and the result never exceeds
+    /// the maximum latency.
+    pub fn percentile(&self, fraction: f64) -> Duration {
+        let count = self.count();
+        if count == 0 {
+            return Duration::ZERO;
+        }
+        let target = ((count as f64 * fraction).ceil() as u64).clamp(1, count);
+        let mut seen = 0;
+        for (bucket, n) in self.buckets.iter().enumerate() {
+            seen += n;
+            if seen >= target {
+                return LatencyHistogram::upper_bound(bucket).map_or(self.max, |b| b.min(self.max));
+            }
+        }
+        self.max
+    }
+
+    fn bucket_of(elapsed: Duration) -> usize {
+        let micros = elapsed.as_micros();
+        // the number of bits needed for `micros`, i.e. the first bucket whose bound is above it
+        let bucket = (u128::BITS - micros.leading_zeros()) as usize;
+        bucket.min(NUM_LATENCY_BUCKETS - 1)
+    }
+}
+
+/// Counters of a single kind of I/O operation
+///
+/// THREAD SAFETY: YES
+pub(crate) struct IoCounter {
+    count: AtomicU64,
+    bytes: AtomicU64,
+    errors: AtomicU64,
+    latency: AtomicHistogram,
+}
+
+impl IoCounter {
+    fn new() -> Self {
+        IoCounter {
+            count: AtomicU64::new(0),
+            bytes: AtomicU64::new(0),
+            errors: AtomicU64::new(0),
+            latency: AtomicHistogram::new(),
+        }
+    }
+
+    /// Record an operation which has started at `start` and moved `bytes` bytes. Only successful
+    /// operations count towards the bytes and the latency.
+    pub fn record<T, E>(&self, result: &Result<T, E>, bytes: usize, start: Instant) {
+        if result.is_err() {
+            self.errors.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        self.record_ok(bytes, start);
+    }
+
+    /// Record a successful operation which has started at `start` and moved `bytes` bytes
+    pub fn record_ok(&self, bytes: usize, start: Instant) {
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+        self.latency.record(start.elapsed());
+    }
+
+    /// Returns the number of successful operations
+    #[inline]
+    pub fn count(&self) -> u64 {
+        self.count.load(Ordering::Relaxed)
+    }
+
+    fn snapshot(&self) -> IoStats {
+        IoStats {
+            count: self.count(),
+            bytes: self.bytes.load(Ordering::Relaxed),
+            errors: self.errors.load(Ordering::Relaxed),
+            latency: self.latency.snapshot(),
+        }
+    }
+}
+
+/// Statistics of a single kind of I/O operation
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct IoStats {
+    /// Number of successful operations
+    pub count: u64,
+    /// Number of bytes moved by the successful operations
+    pub bytes: u64,
+    /// Number of failed operations
+    pub errors: u64,
+    pub latency: LatencyHistogram,
+}
+
+/// DiskMetrics collects the I/O statistics of a disk manager.
+///
+/// THREAD SAFETY: YES
+pub(crate) struct DiskMetrics {
+    pub page_reads: IoCounter,
+    pub page_writes: IoCounter,
+    /// Syncs of the database file
+    pub syncs: IoCounter,
+    pub log_appends: IoCounter,
+    /// Writes of a batch of log data, each followed by a sync of the log file
+    pub log_flushes: IoCounter,
+}
+
+impl DiskMetrics {
+    pub fn new() -> Self {
+        DiskMetrics {
+            page_reads: IoCounter::new(),
+            page_writes: IoCounter::new(),
+            syncs: IoCounter::new(),
+            log_appends: IoCounter::new(),
+            log_flushes: IoCounter::new(),
+        }
+    }
+
+    pub fn snapshot(&self) -> DiskMetricsSnapshot {
+        DiskMetricsSnapshot {
+            page_reads: self.page_reads.snapshot(),
+            page_writes: self.page_writes.snapshot(),
+            syncs: self.syncs.snapshot(),
+            log_appends: self.log_appends.snapshot(),
+            log_flushes: self.log_flushes.snapshot(),
+        }
+    }
+}
+
+/// A point-in-time copy of the I/O statistics of a disk manager, since it has been created. Pages
+/// read and written by the disk manager for its own bookkeeping, e.g. free page bitmaps, are
+/// included.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct DiskMetricsSnapshot {
+    pub page_reads: IoStats,
+    pub page_writes: IoStats,
+    /// Syncs of the database file
+    pub syncs: IoStats,
+    /// Log appends, which only hand the data to the log writer
+    pub log_appends: IoStats,
+    /// Writes of a batch of log data, each followed by a sync of the log file
+    pub log_flushes: IoStats,
+}
+
+impl DiskMetricsSnapshot {
+    /// Returns the number of fsyncs, of both the database file and the log file
+    pub fn num_fsyncs(&self) -> u64 {
+        self.syncs.count + self.log_flushes.count
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::storage::disk::metrics::{DiskMetrics, LatencyHistogram, NUM_LATENCY_BUCKETS};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn latency_histogram() {
+        assert_eq!(LatencyHistogram::bucket_of(Duration::from_nanos(500)), 0);
+        assert_eq!(LatencyHistogram::bucket_of(Duration::from_micros(1)), 1);
+        assert_eq!(LatencyHistogram::bucket_of(Duration::from_micros(3)), 2);
+        assert_eq!(LatencyHistogram::bucket_of(Duration::from_micros(4)), 3);
+        assert_eq!(
+            LatencyHistogram::bucket_of(Duration::from_secs(3600)),
+            NUM_LATENCY_BUCKETS - 1
+        );
+
+        let metrics = DiskMetrics::new();
+        let latency = &metrics.page_reads.latency;
+        for micros in 1..=100 {
+            latency.record(Duration::from_micros(micros));
+        }
+        let histogram = latency.snapshot();
+        assert_eq!(histogram.count(), 100);
+        assert_eq!(histogram.max, Duration::from_micros(100));
+        assert_eq!(histogram.mean(), Duration::from_nanos(50_500));
+        // 50 falls into [32, 64), 100 into [64, 128)
+        assert_eq!(histogram.percentile(0.5), Duration::from_micros(64));
+        assert_eq!(histogram.percentile(0.99), Duration::from_micros(100));
+        assert_eq!(LatencyHistogram::default().percentile(0.5), Duration::ZERO);
+
+        let start = Instant::now();
+        metrics.page_writes.record(&Ok::<_, ()>(()), 4096, start);
+        metrics.page_writes.record(&Err::<(), _>(()), 4096, start);
+        let snapshot = metrics.snapshot();
+        assert_eq!(snapshot.page_writes.count, 1);
+        assert_eq!(snapshot.page_writes.bytes, 4096);
+        assert_eq!(snapshot.page_writes.errors, 1);
+        assert_eq!(snapshot.page_writes.latency.count(), 1);
+        assert_eq!(snapshot.num_fsyncs(), 0);
+    }
+}
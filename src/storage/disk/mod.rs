@@ -1,52 +1,221 @@
-use crate::common::config::{PageId, PAGE_SIZE};
-use crate::common::error::Result;
+use crate::common::config::{
+    Lsn, PageId, TablespaceId, DEFAULT_TABLESPACE_ID, HEADER_PAGE_ID, PAGE_SIZE, PAGE_TRAILER_SIZE,
+    SEGMENT_SIZE,
+};
+use crate::common::error::{IOContext, Result};
+use crate::storage::disk::encryption::Cipher;
+use crate::storage::disk::log_writer::LogWriter;
+use crate::storage::disk::metrics::DiskMetrics;
+use crate::storage::disk::tablespace::{Tablespace, MAX_TABLESPACES};
 use crate::RustubError;
+use std::collections::HashMap;
 use std::fs;
-use std::io::{Read, Seek, SeekFrom, Write};
-use std::sync::atomic::AtomicBool;
-use std::sync::Mutex;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Instant;
 
-pub type FlushLogFuture = fn();
+mod allocator;
+mod checksum;
+mod compression;
+mod encryption;
+mod fault;
+mod log_writer;
+mod memory;
+mod metrics;
+mod reader;
+mod superblock;
+mod tablespace;
+
+pub use checksum::{verify_database_file, Corruption, VerifyReport};
+pub use encryption::{EncryptionKey, ENCRYPTION_OVERHEAD};
+pub use fault::FaultInjectingDiskManager;
+pub use log_writer::FlushLogFuture;
+pub use memory::{InMemDiskManager, InMemSnapshot};
+pub use metrics::{DiskMetricsSnapshot, IoStats, LatencyHistogram, NUM_LATENCY_BUCKETS};
+pub use reader::PageReader;
+pub use superblock::{Superblock, FORMAT_VERSION};
+pub use tablespace::{
+    is_valid_tablespace_name, page_id_in, tablespace_of, TablespaceInfo, DEFAULT_TABLESPACE_NAME,
+    MAX_TABLESPACE_PAGES,
+};
 
 /// DiskManager takes care of the allocation and deallocation of pages within a database. It performs
 /// the reading and writing of pages to and from disk, providing a logical file layer within the
 /// context of a DBMS.
-pub trait DiskManager {
-    /// Write a page to the database file
-    fn write_page(&mut self, pid: PageId, data: &[u8]);
+///
+/// All methods take `&self` so that a single disk manager can be shared by many buffer pool workers
+/// as `Arc<dyn DiskManager>`. Implementations are responsible for their own synchronization.
+pub trait DiskManager: Send + Sync {
+    /// Allocate a free page and return its id. Pages which have been deallocated are reused before
+    /// the database grows.
+    fn allocate_page(&self) -> Result<PageId>;
+
+    /// Allocate a free page in the given tablespace and return its id, see `tablespace_of`.
+    /// `allocate_page` allocates in the default tablespace, which is the only one of disk managers
+    /// without tablespace support.
+    fn allocate_page_in(&self, tablespace: TablespaceId) -> Result<PageId> {
+        if tablespace != DEFAULT_TABLESPACE_ID {
+            return Err(RustubError::TablespaceError("unknown tablespace"));
+        }
+        self.allocate_page()
+    }
+
+    /// Deallocate the given page, so that it can be handed out again by `allocate_page`. The
+    /// content of the page is left as is.
+    fn deallocate_page(&self, pid: PageId) -> Result<()>;
+
+    /// Write a page to the database file. The page must have been allocated. The last
+    /// `reserved_size()` bytes of the page are reserved for the disk manager, and what is read back
+    /// there may differ from what has been written.
+    fn write_page(&self, pid: PageId, data: &[u8]) -> Result<()>;
+
+    /// Read a page from the database file. A page which has been allocated but never written reads
+    /// as zeros.
+    fn read_page(&self, pid: PageId, data: &mut [u8]) -> Result<()>;
+
+    /// Write a page as it's stored, i.e. without stamping its trailer nor encrypting it, so that
+    /// tests can leave a torn or corrupted page behind. Disk managers which store pages as they are
+    /// given don't need to override it.
+    fn write_raw_page(&self, pid: PageId, data: &[u8]) -> Result<()> {
+        self.write_page(pid, data)
+    }
 
-    fn read_page(&mut self, pid: PageId, data: &mut [u8]);
+    /// Read a page as it's stored, i.e. without verifying its trailer nor decrypting it. A page
+    /// which has been allocated but never written reads as zeros.
+    fn read_raw_page(&self, pid: PageId, data: &mut [u8]) -> Result<()> {
+        self.read_page(pid, data)
+    }
+
+    /// Returns the size of the pages of the database, which is fixed when the database is created.
+    /// Every page written or read must have exactly this size.
+    fn page_size(&self) -> usize;
+
+    /// Returns the number of bytes at the end of every page which are reserved for the disk
+    /// manager, e.g. for the page trailer.
+    fn reserved_size(&self) -> usize {
+        PAGE_TRAILER_SIZE
+    }
+
+    /// Make every page written so far durable, so that it survives a crash.
+    fn sync(&self) -> Result<()>;
+
+    /// Append the given data to the log without waiting for it to be durable. The returned future
+    /// is resolved once the data has been synced to the log file.
+    fn append_log(&self, data: &[u8]) -> Result<FlushLogFuture>;
+
+    /// Append the given data to the log. Returns once the data is durable.
+    fn write_log(&self, data: &[u8]) -> Result<()> {
+        self.append_log(data)?.wait().map(|_| ())
+    }
 
-    fn write_log(&mut self, data: &[u8]);
+    /// Read the durable log starting from `offset` into the given buf. Returns the number of bytes
+    /// read, which is 0 once the end of log is reached. The rest of a partially filled buf is
+    /// zeroed.
+    fn read_log(&self, data: &mut [u8], offset: u32) -> Result<usize>;
 
-    fn read_log(&mut self, data: &mut [u8], offset: u32) -> bool;
+    /// Returns the end of the durable part of the log
+    fn flushed_lsn(&self) -> Lsn;
 
-    fn num_flushes(&self) -> u32;
+    /// Block until the log is durable up to the given LSN. Fails if the LSN is beyond the end of
+    /// the log appended so far, or if the log can't be flushed.
+    fn flush_log(&self, lsn: Lsn) -> Result<()>;
 
+    /// Returns true if everything appended to the log is durable
     fn is_flushed(&self) -> bool;
 
-    fn num_writes(&self) -> u32;
+    /// Returns the I/O statistics of the disk manager since it has been created
+    fn metrics(&self) -> DiskMetricsSnapshot;
 
-    fn set_flush_log_future(&mut self, func: FlushLogFuture);
+    /// Returns the number of times the log has been synced
+    fn num_flushes(&self) -> u32 {
+        self.metrics().log_flushes.count as u32
+    }
+
+    /// Returns the number of pages written
+    fn num_writes(&self) -> u32 {
+        self.metrics().page_writes.count as u32
+    }
+}
+
+/// Options for opening a database with `FileBasedDiskManager`
+#[derive(Debug, Clone)]
+pub struct DiskOptions {
+    /// Size of the pages of a new database. An existing database must be opened with the page size
+    /// it has been created with.
+    pub page_size: usize,
+    /// Size in bytes of the segment files of a new database, which must be a multiple of the page
+    /// size. An existing database keeps the segment size it has been created with.
+    pub segment_size: usize,
+    /// Key file of an encrypted database, see `EncryptionKey`. A new database is encrypted if a key
+    /// file is given, and an encrypted database can only be opened with the key it's encrypted
+    /// with.
+    pub key_file: Option<PathBuf>,
+    /// Compress the pages of the default tablespace of a new database, see `ExtentMap`. An existing
+    /// database keeps compressing its pages or not. Compression can't be combined with encryption.
+    pub compression: bool,
+}
 
-    fn has_flush_log_future(&self) -> bool;
+impl Default for DiskOptions {
+    fn default() -> Self {
+        DiskOptions {
+            page_size: PAGE_SIZE,
+            segment_size: SEGMENT_SIZE,
+            key_file: None,
+            compression: false,
+        }
+    }
+}
+
+/// Options for creating a named tablespace with `FileBasedDiskManager::create_tablespace_with`
+#[derive(Debug, Clone, Default)]
+pub struct TablespaceOptions {
+    /// Compress the pages of the tablespace, e.g. for cold tables which are rarely written. Pages
+    /// then take less room on disk, at the cost of some CPU on every read and write.
+    pub compressed: bool,
 }
 
+/// FileBasedDiskManager stores the pages of a database in tablespaces, each of which is split into
+/// segment files of a fixed size, see `Tablespace`. The first segment of the default tablespace is
+/// the database file, and its superblock describes the database. Named tablespaces can be created
+/// in other directories, and are listed in a catalog next to the database file, with the same name
+/// and the `.tablespaces` extension. The log is stored next to it too, with the `.log` extension.
+///
+/// The tablespace of a page is encoded in the high bits of its id, see `tablespace_of`, so any
+/// page id can be routed to its file without any lookup on disk.
+///
+/// An encrypted database encrypts every page and the whole log with its data keys, which are
+/// stored in the superblock and protected by the key file, see `Cipher`.
+///
+/// The pages of a compressed tablespace are compressed one by one and packed in its segment files,
+/// which is transparent to the callers. Where every page is stored is only made durable by `sync`.
 pub struct FileBasedDiskManager {
     db_file: String,
     log_file: String,
-    db_io: fs::File,
-    log_io: fs::File,
+    catalog_file: PathBuf,
+    /// The superblock as it was when the database has been opened
+    superblock: Superblock,
+    /// The superblock as it is on disk
+    header: Mutex<Superblock>,
+    page_size: usize,
+    cipher: Option<Arc<Cipher>>,
+    tablespaces: RwLock<HashMap<TablespaceId, Arc<Tablespace>>>,
+    /// The named tablespaces, which also serializes their creation
+    catalog: Mutex<Vec<TablespaceInfo>>,
+    log: LogWriter,
 
-    num_flushes: u32,
-    num_writes: u32,
-    flush_log: bool,
-    flush_log_func: Option<FlushLogFuture>,
+    metrics: Arc<DiskMetrics>,
 }
 
 impl FileBasedDiskManager {
-    fn new(db_file: String) -> Result<FileBasedDiskManager> {
-        let mut log_file = if let Some(idx) = db_file.rfind(".") {
+    /// Open the given database with the default options, creating it if it doesn't exist.
+    pub fn new(db_file: String) -> Result<FileBasedDiskManager> {
+        FileBasedDiskManager::with_options(db_file, DiskOptions::default())
+    }
+
+    /// Open the given database, creating it if it doesn't exist. Opening a database which has been
+    /// created with another format or page size fails with `IncompatibleDatabase`.
+    pub fn with_options(db_file: String, options: DiskOptions) -> Result<FileBasedDiskManager> {
+        let mut log_file = if let Some(idx) = db_file.rfind('.') {
             db_file[0..idx].to_string()
         } else {
             db_file.clone()
@@ -71,11 +240,20 @@ impl FileBasedDiskManager {
             if file.is_err() {
                 return Err(RustubError::IOError(
                     file.err().unwrap(),
-                    "can't open log file",
+                    IOContext::Other("can't open log file"),
                 ));
             }
         }
         let log_io = file.unwrap();
+        let log_size = match log_io.metadata() {
+            Ok(meta) => meta.len(),
+            Err(e) => {
+                return Err(RustubError::IOError(
+                    e,
+                    IOContext::Other("can't stat log file"),
+                ))
+            }
+        };
         file = fs::File::options()
             .create(true)
             .write(true)
@@ -90,171 +268,429 @@ impl FileBasedDiskManager {
             if file.is_err() {
                 return Err(RustubError::IOError(
                     file.err().unwrap(),
-                    "can't open database file",
+                    IOContext::Other("can't open database file"),
+                ));
+            }
+        }
+        let db_io = file.unwrap();
+        let (superblock, cipher) = FileBasedDiskManager::open_superblock(&db_io, &options)?;
+        if !superblock.clean_shutdown {
+            warn!("Database {} has not been shut down cleanly", db_file);
+        }
+        let page_size = superblock.page_size;
+        let cipher = cipher.map(Arc::new);
+        let (log_io, log_size, log_cipher) = match &cipher {
+            Some(cipher) => {
+                drop(log_io);
+                let (io, size, nonce) = log_writer::renew_log_nonce(Path::new(&log_file), cipher)?;
+                (io, size, Some((cipher.clone(), nonce)))
+            }
+            None => (log_io, log_size, None),
+        };
+        let metrics = Arc::new(DiskMetrics::new());
+
+        let mut tablespaces = HashMap::new();
+        let default = Tablespace::open(
+            DEFAULT_TABLESPACE_ID,
+            PathBuf::from(&db_file),
+            db_io,
+            superblock.clone(),
+            cipher.clone(),
+            &metrics,
+        )?;
+        tablespaces.insert(DEFAULT_TABLESPACE_ID, Arc::new(default));
+        let catalog_file = tablespace::catalog_path(&db_file);
+        let catalog = tablespace::load_catalog(&catalog_file)?;
+        for info in &catalog {
+            let path = tablespace::tablespace_path(&db_file, info);
+            let first = fs::File::options()
+                .read(true)
+                .write(true)
+                .open(&path)
+                .map_err(|e| RustubError::IOError(e, IOContext::Other("can't open tablespace")))?;
+            let segment = Superblock::read_from_file(&first)?;
+            if !segment.is_segment_of(&superblock, info.id, 0) {
+                return Err(RustubError::IncompatibleDatabase(
+                    "segment file belongs to another database",
                 ));
             }
+            let mut header = superblock.for_segment(info.id, 0);
+            header.compressed = segment.compressed;
+            let space = Tablespace::open(info.id, path, first, header, cipher.clone(), &metrics)?;
+            tablespaces.insert(info.id, Arc::new(space));
         }
-        return Ok(FileBasedDiskManager {
+        let header = Superblock {
+            clean_shutdown: false,
+            ..superblock.clone()
+        };
+        Ok(FileBasedDiskManager {
             db_file,
             log_file,
-            db_io: file.unwrap(),
-            log_io,
-            num_flushes: 0,
-            num_writes: 0,
-            flush_log: false,
-            flush_log_func: None,
-        });
+            catalog_file,
+            superblock,
+            header: Mutex::new(header),
+            page_size,
+            cipher: cipher.clone(),
+            tablespaces: RwLock::new(tablespaces),
+            catalog: Mutex::new(catalog),
+            log: LogWriter::new(log_io, log_size, metrics.clone(), log_cipher)?,
+            metrics,
+        })
+    }
+
+    /// Returns the superblock of the database as it was when the database has been opened. Its
+    /// `clean_shutdown` flag tells whether the database needs recovery.
+    pub fn superblock(&self) -> &Superblock {
+        &self.superblock
+    }
+
+    /// Create a named tablespace whose segment files are stored in `dir`, which is created if
+    /// needed. Pages can then be allocated in the tablespace with `allocate_page_in`.
+    pub fn create_tablespace<P: AsRef<Path>>(&self, name: &str, dir: P) -> Result<TablespaceId> {
+        self.create_tablespace_with(name, dir, TablespaceOptions::default())
+    }
+
+    /// Create a named tablespace with the given options, as `create_tablespace` does.
+    pub fn create_tablespace_with<P: AsRef<Path>>(
+        &self,
+        name: &str,
+        dir: P,
+        options: TablespaceOptions,
+    ) -> Result<TablespaceId> {
+        if !tablespace::is_valid_tablespace_name(name) {
+            return Err(RustubError::TablespaceError("invalid tablespace name"));
+        }
+        let mut catalog = self.catalog.lock().unwrap();
+        if catalog.iter().any(|info| info.name == name) {
+            return Err(RustubError::TablespaceError("tablespace already exists"));
+        }
+        let id = (1..MAX_TABLESPACES as TablespaceId)
+            .find(|id| catalog.iter().all(|info| info.id != *id))
+            .ok_or(RustubError::TablespaceError("too many tablespaces"))?;
+        let dir = fs::create_dir_all(dir.as_ref())
+            .and_then(|_| fs::canonicalize(dir.as_ref()))
+            .map_err(|e| {
+                RustubError::IOError(e, IOContext::Other("can't create tablespace directory"))
+            })?;
+        let info = TablespaceInfo {
+            id,
+            name: name.to_string(),
+            dir,
+        };
+        let path = tablespace::tablespace_path(&self.db_file, &info);
+        let space = Tablespace::create(
+            id,
+            path.clone(),
+            &self.superblock,
+            options.compressed,
+            self.cipher.clone(),
+            &self.metrics,
+        )?;
+
+        // the tablespace only exists once it's in the catalog
+        let mut updated = catalog.clone();
+        updated.push(info);
+        if let Err(e) = tablespace::store_catalog(&self.catalog_file, &updated) {
+            drop(space);
+            let _ = fs::remove_file(&path);
+            return Err(e);
+        }
+        *catalog = updated;
+        self.tablespaces
+            .write()
+            .unwrap()
+            .insert(id, Arc::new(space));
+        Ok(id)
+    }
+
+    /// Returns true if the database is encrypted
+    pub fn is_encrypted(&self) -> bool {
+        self.cipher.is_some()
+    }
+
+    /// Returns true if the pages of the given tablespace are compressed
+    pub fn is_compressed(&self, tablespace: TablespaceId) -> Result<bool> {
+        match self.tablespaces.read().unwrap().get(&tablespace) {
+            Some(space) => Ok(space.is_compressed()),
+            None => Err(RustubError::TablespaceError("unknown tablespace")),
+        }
+    }
+
+    /// Protect the data keys of the encrypted database with the key of another key file. Once done,
+    /// the database can only be opened with the new key file. The pages and the log are not
+    /// rewritten, since the data keys stay the same.
+    pub fn rotate_key<P: AsRef<Path>>(&self, key_file: P) -> Result<()> {
+        let cipher = self
+            .cipher
+            .as_ref()
+            .ok_or(RustubError::EncryptionError("database isn't encrypted"))?;
+        let key = EncryptionKey::load(key_file)?;
+        let mut header = self.header.lock().unwrap();
+        let mut rotated = header.clone();
+        rotated.wrapped_keys = Some(cipher.wrap(&key));
+        let default = self.tablespace(HEADER_PAGE_ID)?;
+        default.write_header(&rotated)?;
+        default.sync()?;
+        *header = rotated;
+        Ok(())
+    }
+
+    /// Returns the id of the tablespace with the given name
+    pub fn tablespace_id(&self, name: &str) -> Option<TablespaceId> {
+        if name == DEFAULT_TABLESPACE_NAME {
+            return Some(DEFAULT_TABLESPACE_ID);
+        }
+        let catalog = self.catalog.lock().unwrap();
+        catalog
+            .iter()
+            .find(|info| info.name == name)
+            .map(|info| info.id)
     }
 
-    fn get_file_size(file_name: &String) -> usize {
-        // todo: this does not fail in bustub
-        let meta = fs::metadata(file_name).unwrap();
-        return meta.len() as usize;
+    /// Returns every tablespace of the database, starting with the default one
+    pub fn tablespaces(&self) -> Vec<TablespaceInfo> {
+        let dir = Path::new(&self.db_file).parent().unwrap_or(Path::new(""));
+        let mut tablespaces = vec![TablespaceInfo {
+            id: DEFAULT_TABLESPACE_ID,
+            name: DEFAULT_TABLESPACE_NAME.to_string(),
+            dir: dir.to_path_buf(),
+        }];
+        tablespaces.extend(self.catalog.lock().unwrap().iter().cloned());
+        tablespaces
+    }
+
+    /// Returns the tablespace holding the given page. A page of an unknown tablespace can't have
+    /// been allocated.
+    fn tablespace(&self, pid: PageId) -> Result<Arc<Tablespace>> {
+        if pid < 0 {
+            return Err(RustubError::PageNotAllocated(pid));
+        }
+        let tablespaces = self.tablespaces.read().unwrap();
+        match tablespaces.get(&tablespace_of(pid)) {
+            Some(space) => Ok(space.clone()),
+            None => Err(RustubError::PageNotAllocated(pid)),
+        }
+    }
+
+    /// Read the superblock of the database file, or create it if the file is empty. The superblock
+    /// on disk is marked as not cleanly shut down until the disk manager is dropped. Returns the
+    /// superblock as it was before, and the cipher of an encrypted database. Nothing is written if
+    /// the database can't be opened, e.g. with a wrong key.
+    fn open_superblock(
+        db_io: &fs::File,
+        options: &DiskOptions,
+    ) -> Result<(Superblock, Option<Cipher>)> {
+        let key = match &options.key_file {
+            Some(key_file) => Some(EncryptionKey::load(key_file)?),
+            None => None,
+        };
+        let (superblock, cipher) = if Tablespace::file_size(db_io)? == 0 {
+            if !options.segment_size.is_multiple_of(options.page_size) {
+                return Err(RustubError::IncompatibleDatabase(
+                    "segment size isn't a multiple of the page size",
+                ));
+            }
+            let segment_pages = options.segment_size / options.page_size;
+            let mut superblock = Superblock::new(options.page_size, segment_pages)?;
+            // there's nothing to recover in a new database
+            superblock.clean_shutdown = true;
+            superblock.compressed = options.compression;
+            Tablespace::check_compression(&superblock, key.is_some())?;
+            let cipher = key.map(|key| {
+                let cipher = Cipher::generate();
+                superblock.wrapped_keys = Some(cipher.wrap(&key));
+                cipher
+            });
+            (superblock, cipher)
+        } else {
+            let superblock = Superblock::read_from_file(db_io)?;
+            if superblock.page_size != options.page_size {
+                return Err(RustubError::IncompatibleDatabase("page size mismatch"));
+            }
+            let cipher = match (&superblock.wrapped_keys, key) {
+                (Some(wrapped_keys), Some(key)) => Some(Cipher::unwrap(wrapped_keys, &key)?),
+                (Some(_), None) => {
+                    return Err(RustubError::EncryptionError("database is encrypted"));
+                }
+                (None, Some(_)) => {
+                    return Err(RustubError::EncryptionError("database isn't encrypted"));
+                }
+                (None, None) => None,
+            };
+            (superblock, cipher)
+        };
+        let mut opened = superblock.clone();
+        opened.clean_shutdown = false;
+        Tablespace::write_superblock(db_io, &opened)?;
+        db_io
+            .sync_data()
+            .map_err(|e| RustubError::IOError(e, IOContext::Other("can't sync database file")))?;
+        Ok((superblock, cipher))
+    }
+
+    /// Make the log and every page durable, then mark the database as cleanly shut down.
+    fn shut_down(&self) -> Result<()> {
+        self.log.flush()?;
+        self.sync()?;
+        let mut superblock = self.header.lock().unwrap().clone();
+        superblock.clean_shutdown = true;
+        self.tablespace(HEADER_PAGE_ID)?.write_header(&superblock)?;
+        self.sync()
     }
 }
 
 impl DiskManager for FileBasedDiskManager {
-    // 1. What does the APPEND flag do when open a file? For a write, we need to ensure the data is
-    // passed to write once and the offset will be set to the end of file.
-    // 2. How does the seek affect read and write?
-    // 3. What if we try to seek to a position which is out of the size of file?
+    // Pages are accessed with positional I/O (pread/pwrite), which never touches the shared file
+    // cursor. Concurrent reads and writes of different pages don't need any latch at all, and the
+    // caller (buffer pool) is responsible for not writing the same page concurrently.
+    //
+    // The log file is opened with the APPEND flag. Every write goes to the end of file no matter
+    // where the cursor is, so appends are serialized through the log latch while reads use
+    // positional I/O against the cached log size.
 
-    /// Write the contents of the specified page into disk file. The page is flushed immediately.
+    /// Allocate a page in the default tablespace, marking it as used in the free page bitmap on
+    /// disk.
+    ///
+    /// THREAD SAFETY: YES
+    fn allocate_page(&self) -> Result<PageId> {
+        self.allocate_page_in(DEFAULT_TABLESPACE_ID)
+    }
+
+    /// Allocate a page in the given tablespace, marking it as used in the free page bitmap on disk.
+    ///
+    /// THREAD SAFETY: YES
+    fn allocate_page_in(&self, tablespace: TablespaceId) -> Result<PageId> {
+        let space = self.tablespaces.read().unwrap().get(&tablespace).cloned();
+        match space {
+            Some(space) => space.allocate(&self.metrics),
+            None => Err(RustubError::TablespaceError("unknown tablespace")),
+        }
+    }
+
+    /// Deallocate a page, marking it as free in the free page bitmap on disk.
+    ///
+    /// THREAD SAFETY: YES
+    fn deallocate_page(&self, pid: PageId) -> Result<()> {
+        self.tablespace(pid)?.deallocate(&self.metrics, pid)
+    }
+
+    /// Write the contents of the specified page into its segment file. The page is flushed
+    /// immediately. The page is stamped with a checksum which is verified when it's read back.
     ///
     /// Reminders:
     ///     1. Is the write atomic ?
     ///     2. What if we try write to an offset that is beyond the file size? The size of the file
-    ///     will be expanded and the gap will be filled with zero.
+    ///     will be expanded and the gap will be filled with zero. Since only allocated pages can be
+    ///     written, the file only grows when all freed pages have been reused.
     ///
-    /// THREAD SAFETY: NO
-    fn write_page(&mut self, pid: PageId, data: &[u8]) {
-        assert_eq!(data.len(), PAGE_SIZE);
-        let offset = pid as usize * PAGE_SIZE;
-        self.db_io.seek(SeekFrom::Start(offset as u64)).unwrap();
-        if let Err(e) = self.db_io.write_all(data) {
-            error!("IO error while writing page: {}", e);
-        }
-        self.db_io.flush();
+    /// THREAD SAFETY: YES
+    fn write_page(&self, pid: PageId, data: &[u8]) -> Result<()> {
+        assert_eq!(data.len(), self.page_size);
+        self.tablespace(pid)?.write_page(&self.metrics, pid, data)
     }
 
     /// Read the contents of the specified page into the given buf.
     ///
-    /// Reminders:
+    /// THREAD SAFETY: YES
+    fn read_page(&self, pid: PageId, data: &mut [u8]) -> Result<()> {
+        assert_eq!(data.len(), self.page_size);
+        self.tablespace(pid)?.read_page(&self.metrics, pid, data)
+    }
+
+    /// Write the page as it's given, below the checksum and encryption layer.
     ///
-    /// THREAD SAFETY: NO
-    fn read_page(&mut self, pid: PageId, mut data: &mut [u8]) {
-        assert_eq!(data.len(), PAGE_SIZE);
-        let offset = pid as usize * PAGE_SIZE;
-        if offset > FileBasedDiskManager::get_file_size(&self.db_file) {
-            error!("IO error while reading past end of file");
-        } else {
-            self.db_io.seek(SeekFrom::Start(offset as u64));
-            // todo: why could we ignore the failure of seek?
-            while !data.is_empty() {
-                match self.db_io.read(data) {
-                    // the read has reached its 'end-of-file'
-                    Ok(0) => break,
-                    Ok(n) => {
-                        let tmp = data;
-                        data = &mut tmp[n..];
-                    }
-                    Err(ref e) if e.kind() == std::io::ErrorKind::Interrupted => {}
-                    Err(e) => {
-                        error!("IO error while reading page");
-                        return;
-                    }
-                }
-            }
-            if !data.is_empty() {
-                debug!("Read less than a page");
-                data.fill(0u8);
-            }
-        }
+    /// THREAD SAFETY: YES
+    fn write_raw_page(&self, pid: PageId, data: &[u8]) -> Result<()> {
+        assert_eq!(data.len(), self.page_size);
+        self.tablespace(pid)?
+            .write_raw_page(&self.metrics, pid, data)
     }
 
-    /// Write the contents of the log into disk file. Only return when sync is done, and only perform
-    /// sequential write.
+    /// Read the page as it's stored, below the checksum and encryption layer.
     ///
-    /// THREAD-SAFETY: NO
-    fn write_log(&mut self, data: &[u8]) {
-        if data.is_empty() {
-            return;
-        }
-        // todo: try to make this async
-        self.num_flushes += 1;
-        if self.log_io.write_all(data).is_err() {
-            error!("IO error while writing log");
-            return;
-        }
-        self.log_io.flush().unwrap();
+    /// THREAD SAFETY: YES
+    fn read_raw_page(&self, pid: PageId, data: &mut [u8]) -> Result<()> {
+        assert_eq!(data.len(), self.page_size);
+        self.tablespace(pid)?
+            .read_raw_page(&self.metrics, pid, data)
+    }
+
+    #[inline]
+    fn page_size(&self) -> usize {
+        self.page_size
     }
 
-    /// Read the contents of the log into the given buf. Always read from the beginning and perform
-    /// sequential read.
+    /// Returns the size of the page trailer, and of the nonce and tag of an encrypted page.
+    fn reserved_size(&self) -> usize {
+        tablespace::reserved_size(self.cipher.is_some())
+    }
+
+    /// Sync every segment file of every tablespace. Page writes only reach the OS before that.
     ///
-    /// Returns false means already reach the end.
+    /// THREAD SAFETY: YES
+    fn sync(&self) -> Result<()> {
+        let tablespaces: Vec<_> = self.tablespaces.read().unwrap().values().cloned().collect();
+        let start = Instant::now();
+        let result = tablespaces.iter().try_for_each(|space| space.sync());
+        self.metrics.syncs.record(&result, 0, start);
+        result
+    }
+
+    /// Append the contents to the log. They are written and synced by the background log flusher,
+    /// together with whatever else has been appended in the meantime.
     ///
-    /// THREAD SAFETY: NO
-    fn read_log(&mut self, mut data: &mut [u8], offset: u32) -> bool {
-        if offset >= FileBasedDiskManager::get_file_size(&self.log_file) as u32 {
-            debug!("end of log file");
-            return false;
-        }
-        self.log_io.seek(SeekFrom::Start(offset as u64));
-        while !data.is_empty() {
-            match self.log_io.read(data) {
-                // the read has reached its 'end-of-file'
-                Ok(0) => {
-                    break;
-                }
-                Ok(n) => {
-                    let tmp = data;
-                    data = &mut tmp[n..];
-                }
-                Err(ref e) if e.kind() == std::io::ErrorKind::Interrupted => {}
-                Err(e) => {
-                    error!("IO error while reading page");
-                    return false;
+    /// THREAD-SAFETY: YES
+    fn append_log(&self, data: &[u8]) -> Result<FlushLogFuture> {
+        self.log.append(data)
+    }
+
+    /// Read the contents of the log into the given buf, starting from `offset`. Only the durable
+    /// part of the log can be read.
+    ///
+    /// THREAD SAFETY: YES
+    fn read_log(&self, data: &mut [u8], offset: u32) -> Result<usize> {
+        match self.log.read(data, offset as u64) {
+            Ok(n) => {
+                if n == 0 {
+                    debug!("end of log file");
                 }
+                data[n..].fill(0u8);
+                Ok(n)
             }
+            Err(e) => Err(RustubError::IOError(e, IOContext::Log(offset as u64))),
         }
-        if !data.is_empty() {
-            debug!("Read less than a page");
-            data.fill(0u8);
-        }
-        return true;
     }
 
     #[inline]
-    fn num_flushes(&self) -> u32 {
-        self.num_flushes
+    fn flushed_lsn(&self) -> Lsn {
+        self.log.durable_lsn() as Lsn
     }
 
-    #[inline]
-    fn is_flushed(&self) -> bool {
-        self.flush_log
+    fn flush_log(&self, lsn: Lsn) -> Result<()> {
+        self.log.flush_to(lsn as u64)
     }
 
     #[inline]
-    fn num_writes(&self) -> u32 {
-        self.num_writes
+    fn is_flushed(&self) -> bool {
+        self.log.is_flushed()
     }
 
-    #[inline]
-    fn set_flush_log_future(&mut self, func: FlushLogFuture) {
-        self.flush_log_func = Some(func);
+    fn metrics(&self) -> DiskMetricsSnapshot {
+        self.metrics.snapshot()
     }
+}
 
-    #[inline]
-    fn has_flush_log_future(&self) -> bool {
-        return self.flush_log_func.is_some();
+impl Drop for FileBasedDiskManager {
+    /// Mark the database as cleanly shut down, once everything is durable.
+    fn drop(&mut self) {
+        if let Err(e) = self.shut_down() {
+            error!("Can't shut down database {} cleanly: {}", self.db_file, e);
+        }
     }
 }
 
-pub struct InMemDiskManager {}
-
 #[cfg(test)]
-mod test {
+pub(crate) mod test {
     use flexi_logger::{colored_default_format, colored_opt_format};
     use std::sync::Once;
 
@@ -272,45 +708,67 @@ mod test {
         });
     }
 
-    use crate::common::config::PAGE_SIZE;
+    use crate::common::config::{PAGE_SIZE, PAGE_TRAILER_SIZE};
+    use crate::common::error::IOContext;
     use crate::common::memcpy;
-    use crate::storage::disk::{DiskManager, FileBasedDiskManager};
+    use crate::storage::disk::log_writer::LOG_HEADER_SIZE;
+    use crate::storage::disk::{
+        page_id_in, tablespace_of, verify_database_file, Corruption, DiskManager, DiskOptions,
+        EncryptionKey, FaultInjectingDiskManager, FileBasedDiskManager, Superblock,
+        TablespaceOptions, ENCRYPTION_OVERHEAD, FORMAT_VERSION,
+    };
+    use crate::RustubError;
     use std::fs;
     use std::fs::File;
     use std::io::{Read, Seek, SeekFrom, Write};
+    use std::os::unix::fs::FileExt;
     use std::panic;
+    use std::sync::Arc;
+    use std::thread;
+
+    /// Size of the page content which is read back as written, i.e. without the trailer
+    const DATA_SIZE: usize = PAGE_SIZE - PAGE_TRAILER_SIZE;
 
-    fn set_up() {
-        fs::remove_file("test.db");
-        fs::remove_file("test.log");
+    fn set_up(name: &str) {
+        tear_down(name);
         test_setup_logger();
     }
 
-    fn tear_down() {
-        fs::remove_file("test.db");
-        fs::remove_file("test.log");
+    pub(crate) fn tear_down(name: &str) {
+        let _ = fs::remove_file(format!("{}.db", name));
+        let _ = fs::remove_file(format!("{}.log", name));
+        let _ = fs::remove_file(format!("{}.tablespaces", name));
+        let _ = fs::remove_file(format!("{}.db.extents", name));
+        // segment files, and tablespaces created by the test
+        for segment in 1..16 {
+            let _ = fs::remove_file(format!("{}.db.{}", name, segment));
+        }
+        let _ = fs::remove_dir_all(format!("{}_tablespaces", name));
+        let _ = fs::remove_file(format!("{}.key", name));
+        let _ = fs::remove_file(format!("{}.key2", name));
     }
 
     // todo: how to setup and teardown tests in rust?
-    fn run_test<T>(test: T) -> ()
+    /// Run `test` against its own database file `<name>.db`, since tests run in parallel.
+    pub(crate) fn run_test<T>(name: &str, test: T)
     where
-        T: FnOnce() -> () + panic::UnwindSafe,
+        T: FnOnce(String) + panic::UnwindSafe,
     {
-        set_up();
+        set_up(name);
 
-        let result = panic::catch_unwind(|| test());
+        let db_file = format!("{}.db", name);
+        let result = panic::catch_unwind(|| test(db_file));
 
-        tear_down();
+        tear_down(name);
         assert!(result.is_ok())
     }
 
     #[test]
     fn read_write_page() {
-        run_test(|| {
+        run_test("read_write_page", |db_file| {
             let mut buf = [0u8; PAGE_SIZE];
             let mut data = [0u8; PAGE_SIZE];
-            let db_file = "test.db".to_string();
-            let mut dm = FileBasedDiskManager::new(db_file).unwrap();
+            let dm = FileBasedDiskManager::new(db_file).unwrap();
             let test_str = &b"A test string."[..];
             // todo: refactor this
             unsafe {
@@ -318,48 +776,771 @@ mod test {
             }
 
             // tolerate empty read
-            dm.read_page(0, &mut buf[..]);
+            dm.read_page(0, &mut buf[..]).unwrap();
 
             assert_eq!(buf, [0u8; PAGE_SIZE]);
 
-            dm.write_page(0, &data[..]);
-            dm.read_page(0, &mut buf[..]);
-            assert_eq!(buf, data);
+            dm.write_page(0, &data[..]).unwrap();
+            dm.read_page(0, &mut buf[..]).unwrap();
+            assert_eq!(buf[..DATA_SIZE], data[..DATA_SIZE]);
 
             buf.fill(0);
-            dm.write_page(5, &mut data[..]);
-            dm.read_page(0, &mut buf[..]);
-            assert_eq!(buf, data);
+            let pid = dm.allocate_page().unwrap();
+            dm.write_page(pid, &data[..]).unwrap();
+            dm.read_page(pid, &mut buf[..]).unwrap();
+            assert_eq!(buf[..DATA_SIZE], data[..DATA_SIZE]);
+
+            // the free page bitmap can't be overwritten by the caller
+            assert!(matches!(
+                dm.write_page(1, &[0xffu8; PAGE_SIZE][..]),
+                Err(RustubError::PageNotAllocated(1))
+            ));
+            dm.read_page(1, &mut buf[..]).unwrap();
+            assert_ne!(buf, [0xffu8; PAGE_SIZE]);
+        })
+    }
+
+    #[test]
+    fn allocate_deallocate_page() {
+        run_test("allocate_deallocate_page", |db_file| {
+            let dm = FileBasedDiskManager::new(db_file.clone()).unwrap();
+            let pids: Vec<_> = (0..4).map(|_| dm.allocate_page().unwrap()).collect();
+            assert_eq!(pids, vec![2, 3, 4, 5]);
+            for pid in &pids {
+                dm.write_page(*pid, &[*pid as u8; PAGE_SIZE][..]).unwrap();
+            }
+            dm.deallocate_page(3).unwrap();
+            dm.deallocate_page(4).unwrap();
+            assert!(matches!(
+                dm.deallocate_page(4),
+                Err(RustubError::PageNotAllocated(4))
+            ));
+            assert!(matches!(
+                dm.write_page(4, &[0u8; PAGE_SIZE][..]),
+                Err(RustubError::PageNotAllocated(4))
+            ));
+            let size = fs::metadata(&db_file).unwrap().len();
+            drop(dm);
+
+            // allocations survive a restart, and freed pages are reused before the file grows
+            let dm = FileBasedDiskManager::new(db_file.clone()).unwrap();
+            assert_eq!(dm.allocate_page().unwrap(), 3);
+            assert_eq!(dm.allocate_page().unwrap(), 4);
+            assert_eq!(dm.allocate_page().unwrap(), 6);
+            assert_eq!(fs::metadata(&db_file).unwrap().len(), size);
+
+            let mut buf = [0u8; PAGE_SIZE];
+            dm.read_page(5, &mut buf[..]).unwrap();
+            assert_eq!(buf[..DATA_SIZE], [5u8; DATA_SIZE]);
+        })
+    }
+
+    #[test]
+    fn read_torn_page() {
+        run_test("read_torn_page", |db_file| {
+            let dm = FileBasedDiskManager::new(db_file.clone()).unwrap();
+            let pid = dm.allocate_page().unwrap();
+            dm.write_page(pid, &[1u8; PAGE_SIZE][..]).unwrap();
+
+            // cut the last page in half, as if the machine crashed in the middle of the write
+            let file = File::options().write(true).open(&db_file).unwrap();
+            file.set_len(((pid as usize + 1) * PAGE_SIZE + PAGE_SIZE / 2) as u64)
+                .unwrap();
+
+            let mut buf = [0u8; PAGE_SIZE];
+            match dm.read_page(pid, &mut buf[..]) {
+                Err(e @ RustubError::IOError(_, IOContext::Page(p))) => {
+                    assert_eq!(p, pid);
+                    assert!(e.to_string().contains(&format!("page {}", pid)));
+                }
+                _ => panic!("a torn page must not be read successfully"),
+            }
+        })
+    }
+
+    #[test]
+    fn detect_torn_write() {
+        run_test("detect_torn_write", |db_file| {
+            let dm = FaultInjectingDiskManager::new(FileBasedDiskManager::new(db_file).unwrap());
+            let pid = dm.allocate_page().unwrap();
+            dm.write_page(pid, &[1u8; PAGE_SIZE][..]).unwrap();
+            dm.sync().unwrap();
+
+            dm.tear_nth_write(1, PAGE_SIZE / 2);
+            assert!(dm.write_page(pid, &[2u8; PAGE_SIZE][..]).is_err());
+            let mut buf = [0u8; PAGE_SIZE];
+            assert!(matches!(
+                dm.read_page(pid, &mut buf[..]),
+                Err(RustubError::PageCorrupted(p, _)) if p == pid
+            ));
+            // the torn page is on disk as written
+            dm.read_raw_page(pid, &mut buf[..]).unwrap();
+            assert_eq!(buf[..PAGE_SIZE / 2], [2u8; PAGE_SIZE / 2]);
+            assert_eq!(
+                buf[PAGE_SIZE / 2..DATA_SIZE],
+                [1u8; DATA_SIZE - PAGE_SIZE / 2]
+            );
+        })
+    }
+
+    #[test]
+    fn rewrite_torn_page() {
+        run_test("rewrite_torn_page", |db_file| {
+            let dm = FaultInjectingDiskManager::new(FileBasedDiskManager::new(db_file).unwrap());
+            let pid = dm.allocate_page().unwrap();
+            dm.write_page(pid, &[1u8; PAGE_SIZE][..]).unwrap();
+            dm.sync().unwrap();
+            dm.tear_nth_write(1, PAGE_SIZE / 2);
+            assert!(dm.write_page(pid, &[2u8; PAGE_SIZE][..]).is_err());
+            let mut torn = [0u8; PAGE_SIZE];
+            dm.read_raw_page(pid, &mut torn[..]).unwrap();
+
+            // the torn page is repaired by writing it again, which is undone by a crash
+            dm.write_page(pid, &[3u8; PAGE_SIZE][..]).unwrap();
+            let mut buf = [0u8; PAGE_SIZE];
+            dm.read_page(pid, &mut buf[..]).unwrap();
+            assert_eq!(buf[..DATA_SIZE], [3u8; DATA_SIZE]);
+            dm.crash().unwrap();
+            dm.read_raw_page(pid, &mut buf[..]).unwrap();
+            assert_eq!(buf, torn);
+            assert!(matches!(
+                dm.read_page(pid, &mut buf[..]),
+                Err(RustubError::PageCorrupted(p, _)) if p == pid
+            ));
+
+            // once synced, the repaired page survives a crash
+            dm.write_page(pid, &[4u8; PAGE_SIZE][..]).unwrap();
+            dm.sync().unwrap();
+            dm.crash().unwrap();
+            dm.read_page(pid, &mut buf[..]).unwrap();
+            assert_eq!(buf[..DATA_SIZE], [4u8; DATA_SIZE]);
+        })
+    }
+
+    #[test]
+    fn detect_corrupted_page() {
+        run_test("detect_corrupted_page", |db_file| {
+            let dm = FileBasedDiskManager::new(db_file.clone()).unwrap();
+            let pids: Vec<_> = (0..3).map(|_| dm.allocate_page().unwrap()).collect();
+            for pid in &pids {
+                dm.write_page(*pid, &[*pid as u8; PAGE_SIZE][..]).unwrap();
+            }
+            assert!(verify_database_file(&db_file).unwrap().is_ok());
+
+            // flip a bit of the first page, and copy the second page over the third one
+            let file = File::options()
+                .read(true)
+                .write(true)
+                .open(&db_file)
+                .unwrap();
+            let mut buf = [0u8; PAGE_SIZE];
+            file.read_exact_at(&mut buf[..1], (pids[0] as u64 + 1) * PAGE_SIZE as u64)
+                .unwrap();
+            buf[0] ^= 0x10;
+            file.write_all_at(&buf[..1], (pids[0] as u64 + 1) * PAGE_SIZE as u64)
+                .unwrap();
+            file.read_exact_at(&mut buf[..], (pids[1] as u64 + 1) * PAGE_SIZE as u64)
+                .unwrap();
+            file.write_all_at(&buf[..], (pids[2] as u64 + 1) * PAGE_SIZE as u64)
+                .unwrap();
+
+            assert!(matches!(
+                dm.read_page(pids[0], &mut buf[..]),
+                Err(RustubError::PageCorrupted(pid, _)) if pid == pids[0]
+            ));
+            dm.read_page(pids[1], &mut buf[..]).unwrap();
+            assert_eq!(buf[..DATA_SIZE], [pids[1] as u8; DATA_SIZE]);
+            assert!(matches!(
+                dm.read_page(pids[2], &mut buf[..]),
+                Err(RustubError::PageCorrupted(pid, _)) if pid == pids[2]
+            ));
+
+            let report = verify_database_file(&db_file).unwrap();
+            assert_eq!(report.num_pages, pids[2] as usize + 1);
+            assert_eq!(
+                report.corrupted,
+                vec![
+                    (pids[0], Corruption::ChecksumMismatch),
+                    (pids[2], Corruption::Misplaced(pids[1]))
+                ]
+            );
+        })
+    }
+
+    #[test]
+    fn superblock_and_page_size() {
+        run_test("superblock_and_page_size", |db_file| {
+            let options = DiskOptions {
+                page_size: 8192,
+                ..DiskOptions::default()
+            };
+            let dm = FileBasedDiskManager::with_options(db_file.clone(), options.clone()).unwrap();
+            assert_eq!(dm.page_size(), 8192);
+            assert!(dm.superblock().clean_shutdown);
+            let pid = dm.allocate_page().unwrap();
+            dm.write_page(pid, &[3u8; 8192][..]).unwrap();
+            // marked as open on disk
+            assert!(!Superblock::read_from(&db_file).unwrap().clean_shutdown);
+            drop(dm);
+
+            let superblock = Superblock::read_from(&db_file).unwrap();
+            assert_eq!(superblock.format_version, FORMAT_VERSION);
+            assert_eq!(superblock.page_size, 8192);
+            assert!(superblock.clean_shutdown);
+            let report = verify_database_file(&db_file).unwrap();
+            assert!(report.is_ok());
+            assert_eq!(report.page_size, 8192);
+
+            // a database can't be opened with another page size
+            assert!(matches!(
+                FileBasedDiskManager::new(db_file.clone()),
+                Err(RustubError::IncompatibleDatabase(_))
+            ));
+            let dm = FileBasedDiskManager::with_options(db_file.clone(), options.clone()).unwrap();
+            assert!(dm.superblock().clean_shutdown);
+            let mut buf = [0u8; 8192];
+            dm.read_page(pid, &mut buf[..]).unwrap();
+            assert_eq!(
+                buf[..8192 - PAGE_TRAILER_SIZE],
+                [3u8; 8192 - PAGE_TRAILER_SIZE]
+            );
+            // crash without shutting down
+            std::mem::forget(dm);
+            let dm = FileBasedDiskManager::with_options(db_file.clone(), options).unwrap();
+            assert!(!dm.superblock().clean_shutdown);
+            drop(dm);
+
+            // a file in another format is rejected and left untouched
+            fs::write(&db_file, [7u8; 3 * PAGE_SIZE]).unwrap();
+            assert!(matches!(
+                FileBasedDiskManager::new(db_file.clone()),
+                Err(RustubError::IncompatibleDatabase("not a rustub database"))
+            ));
+            assert_eq!(fs::read(&db_file).unwrap(), [7u8; 3 * PAGE_SIZE]);
+            assert!(matches!(
+                FileBasedDiskManager::with_options(
+                    db_file,
+                    DiskOptions {
+                        page_size: 1000,
+                        ..DiskOptions::default()
+                    }
+                ),
+                Err(RustubError::IncompatibleDatabase(_))
+            ));
+        })
+    }
+
+    #[test]
+    fn segments_and_tablespaces() {
+        run_test("segments_and_tablespaces", |db_file| {
+            let options = DiskOptions {
+                segment_size: 4 * PAGE_SIZE,
+                ..DiskOptions::default()
+            };
+            let dm = FileBasedDiskManager::with_options(db_file.clone(), options).unwrap();
+            assert_eq!(dm.superblock().segment_pages, 4);
+            let pids: Vec<_> = (0..10).map(|_| dm.allocate_page().unwrap()).collect();
+            for pid in &pids {
+                dm.write_page(*pid, &[*pid as u8; PAGE_SIZE][..]).unwrap();
+            }
+            // pages 0 to 11 are spread over 3 segment files, each starting with a superblock
+            assert_eq!(fs::metadata(&db_file).unwrap().len(), 5 * PAGE_SIZE as u64);
+            assert_eq!(
+                fs::metadata(format!("{}.1", db_file)).unwrap().len(),
+                5 * PAGE_SIZE as u64
+            );
+            let segment = Superblock::read_from(&format!("{}.2", db_file)).unwrap();
+            assert!(segment.is_segment_of(dm.superblock(), 0, 2));
+
+            let dir = "segments_and_tablespaces_tablespaces/cold";
+            let cold = dm.create_tablespace("cold", dir).unwrap();
+            assert_eq!(cold, 1);
+            assert_eq!(dm.tablespace_id("cold"), Some(cold));
+            assert_eq!(dm.tablespace_id("default"), Some(0));
+            assert!(matches!(
+                dm.create_tablespace("cold", dir),
+                Err(RustubError::TablespaceError(_))
+            ));
+            assert!(matches!(
+                dm.create_tablespace("../cold", dir),
+                Err(RustubError::TablespaceError(_))
+            ));
+            let cold_pids: Vec<_> = (0..5).map(|_| dm.allocate_page_in(cold).unwrap()).collect();
+            assert_eq!(cold_pids[0], page_id_in(cold, 2));
+            for pid in &cold_pids {
+                assert_eq!(tablespace_of(*pid), cold);
+                dm.write_page(*pid, &[*pid as u8 ^ 0xff; PAGE_SIZE][..])
+                    .unwrap();
+            }
+            assert!(matches!(
+                dm.allocate_page_in(5),
+                Err(RustubError::TablespaceError(_))
+            ));
+            let unknown = page_id_in(5, 2);
+            assert!(matches!(
+                dm.write_page(unknown, &[0u8; PAGE_SIZE][..]),
+                Err(RustubError::PageNotAllocated(pid)) if pid == unknown
+            ));
+            dm.deallocate_page(cold_pids[1]).unwrap();
+            drop(dm);
+
+            // the segment size of an existing database wins, and tablespaces are found again
+            let dm = FileBasedDiskManager::new(db_file.clone()).unwrap();
+            assert_eq!(dm.superblock().segment_pages, 4);
+            let tablespaces = dm.tablespaces();
+            assert_eq!(tablespaces.len(), 2);
+            assert_eq!(tablespaces[1].name, "cold");
+            assert_eq!(tablespaces[1].dir, fs::canonicalize(dir).unwrap());
+            let mut buf = [0u8; PAGE_SIZE];
+            for pid in &pids {
+                dm.read_page(*pid, &mut buf[..]).unwrap();
+                assert_eq!(buf[..DATA_SIZE], [*pid as u8; DATA_SIZE]);
+            }
+            dm.read_page(cold_pids[4], &mut buf[..]).unwrap();
+            assert_eq!(buf[..DATA_SIZE], [cold_pids[4] as u8 ^ 0xff; DATA_SIZE]);
+            assert_eq!(dm.allocate_page_in(cold).unwrap(), cold_pids[1]);
+            drop(dm);
+
+            let report = verify_database_file(&db_file).unwrap();
+            assert!(report.is_ok());
+            assert_eq!(report.num_pages, 12 + 7);
+            // the last page of the tablespace is the third page of its second segment
+            let cold_segment = format!("{}/segments_and_tablespaces.db.cold.1", dir);
+            let file = File::options().write(true).open(cold_segment).unwrap();
+            file.write_all_at(&[1u8], 3 * PAGE_SIZE as u64).unwrap();
+            let report = verify_database_file(&db_file).unwrap();
+            assert_eq!(
+                report.corrupted,
+                vec![(cold_pids[4], Corruption::ChecksumMismatch)]
+            );
+        })
+    }
+
+    #[test]
+    fn encrypted_database() {
+        run_test("encrypted_database", |db_file| {
+            let key_file = "encrypted_database.key";
+            let new_key_file = "encrypted_database.key2";
+            EncryptionKey::generate().store(key_file).unwrap();
+            EncryptionKey::generate().store(new_key_file).unwrap();
+            let options = DiskOptions {
+                key_file: Some(key_file.into()),
+                ..DiskOptions::default()
+            };
+            let dm = FileBasedDiskManager::with_options(db_file.clone(), options.clone()).unwrap();
+            assert!(dm.is_encrypted());
+            assert_eq!(dm.reserved_size(), PAGE_TRAILER_SIZE + ENCRYPTION_OVERHEAD);
+            let data_size = PAGE_SIZE - dm.reserved_size();
+            let pid = dm.allocate_page().unwrap();
+            dm.write_page(pid, &[b's'; PAGE_SIZE][..]).unwrap();
+            dm.write_log(&b"secret log record"[..]).unwrap();
+            let mut buf = [0u8; PAGE_SIZE];
+            dm.read_page(pid, &mut buf[..]).unwrap();
+            assert_eq!(buf[..data_size], vec![b's'; data_size][..]);
+            drop(dm);
+
+            // nothing is stored in clear, but the trailers can still be verified without the key
+            let raw = fs::read(&db_file).unwrap();
+            assert!(!raw.windows(64).any(|w| w.iter().all(|b| *b == b's')));
+            let log = fs::read("encrypted_database.log").unwrap();
+            assert_eq!(log.len(), LOG_HEADER_SIZE + 17);
+            assert_ne!(&log[LOG_HEADER_SIZE..], &b"secret log record"[..]);
+            assert!(verify_database_file(&db_file).unwrap().is_ok());
+
+            // opening without the key or with a wrong key fails, and leaves the database as is
+            assert!(matches!(
+                FileBasedDiskManager::new(db_file.clone()),
+                Err(RustubError::EncryptionError("database is encrypted"))
+            ));
+            let wrong = DiskOptions {
+                key_file: Some(new_key_file.into()),
+                ..DiskOptions::default()
+            };
+            assert!(matches!(
+                FileBasedDiskManager::with_options(db_file.clone(), wrong.clone()),
+                Err(RustubError::EncryptionError("wrong encryption key"))
+            ));
+            assert_eq!(fs::read(&db_file).unwrap(), raw);
+
+            // the log is encrypted again with a new nonce on every open, so that the key stream
+            // past its end is never used twice
+            let dm = FileBasedDiskManager::with_options(db_file.clone(), options.clone()).unwrap();
+            assert!(dm.superblock().clean_shutdown);
+            let reopened = fs::read("encrypted_database.log").unwrap();
+            assert_eq!(reopened.len(), log.len());
+            assert_ne!(reopened[..LOG_HEADER_SIZE], log[..LOG_HEADER_SIZE]);
+            assert_ne!(reopened[LOG_HEADER_SIZE..], log[LOG_HEADER_SIZE..]);
+            let mut record = [0u8; 17];
+            assert_eq!(dm.read_log(&mut record[..], 0).unwrap(), 17);
+            assert_eq!(&record[..], &b"secret log record"[..]);
+            dm.write_log(&b"more"[..]).unwrap();
+            assert_eq!(dm.read_log(&mut record[..4], 17).unwrap(), 4);
+            assert_eq!(&record[..4], &b"more"[..]);
+
+            // after a rotation, only the new key opens the database
+            dm.rotate_key(new_key_file).unwrap();
+            drop(dm);
+            assert!(matches!(
+                FileBasedDiskManager::with_options(db_file.clone(), options),
+                Err(RustubError::EncryptionError("wrong encryption key"))
+            ));
+            let dm = FileBasedDiskManager::with_options(db_file.clone(), wrong).unwrap();
+            assert!(dm.superblock().clean_shutdown);
+            dm.read_page(pid, &mut buf[..]).unwrap();
+            assert_eq!(buf[..data_size], vec![b's'; data_size][..]);
+            drop(dm);
+
+            // an unencrypted database can't be opened with a key
+            let plain = FileBasedDiskManager::new("encrypted_database_plain.db".to_string());
+            drop(plain.unwrap());
+            let keyed = DiskOptions {
+                key_file: Some(key_file.into()),
+                ..DiskOptions::default()
+            };
+            assert!(matches!(
+                FileBasedDiskManager::with_options(
+                    "encrypted_database_plain.db".to_string(),
+                    keyed
+                ),
+                Err(RustubError::EncryptionError("database isn't encrypted"))
+            ));
+            tear_down("encrypted_database_plain");
+        })
+    }
+
+    #[test]
+    fn reopen_encrypted_database() {
+        run_test("reopen_encrypted_database", |db_file| {
+            let key_file = "reopen_encrypted_database.key";
+            EncryptionKey::generate().store(key_file).unwrap();
+            let options = DiskOptions {
+                page_size: 1024,
+                key_file: Some(key_file.into()),
+                ..DiskOptions::default()
+            };
+            // the bits of the last pages of the first group are stored right before the nonce and
+            // tag of the bitmap page
+            let pages_per_bitmap = (1024 - PAGE_TRAILER_SIZE - ENCRYPTION_OVERHEAD) * 8;
+            let dm = FileBasedDiskManager::with_options(db_file.clone(), options.clone()).unwrap();
+            let mut last = 0;
+            while (last as usize) < pages_per_bitmap + 2 {
+                last = dm.allocate_page().unwrap();
+            }
+            drop(dm);
+
+            // the lowest free page is allocated first, so no page has been lost nor freed
+            let dm = FileBasedDiskManager::with_options(db_file, options).unwrap();
+            assert_eq!(dm.allocate_page().unwrap(), last + 1);
+            dm.deallocate_page(last - 3).unwrap();
+            assert_eq!(dm.allocate_page().unwrap(), last - 3);
+        })
+    }
+
+    #[test]
+    fn compressed_tablespace() {
+        run_test("compressed_tablespace", |db_file| {
+            let options = DiskOptions {
+                segment_size: 4 * PAGE_SIZE,
+                compression: true,
+                ..DiskOptions::default()
+            };
+            let dm = FileBasedDiskManager::with_options(db_file.clone(), options.clone()).unwrap();
+            assert!(dm.is_compressed(0).unwrap());
+            let mut pids = Vec::new();
+            for i in 0..20 {
+                let pid = dm.allocate_page().unwrap();
+                let mut page = [0u8; PAGE_SIZE];
+                let row = format!("archived order {} shipped; ", i % 3);
+                for (j, b) in page.iter_mut().enumerate() {
+                    *b = row.as_bytes()[j % row.len()];
+                }
+                dm.write_page(pid, &page[..]).unwrap();
+                pids.push((pid, page));
+            }
+            // overwritten pages go to another place in the file
+            let (pid, page) = &mut pids[3];
+            page[..5].copy_from_slice(b"fresh");
+            dm.write_page(*pid, &page[..]).unwrap();
+            let dir = "compressed_tablespace_tablespaces";
+            let archive = dm
+                .create_tablespace_with("archive", dir, TablespaceOptions { compressed: true })
+                .unwrap();
+            let plain = dm.create_tablespace("plain", dir).unwrap();
+            assert!(dm.is_compressed(archive).unwrap());
+            assert!(!dm.is_compressed(plain).unwrap());
+            let archived = dm.allocate_page_in(archive).unwrap();
+            dm.write_page(archived, &[b'a'; PAGE_SIZE][..]).unwrap();
+            // a page which doesn't compress is stored as is
+            let mut noise = [0u8; PAGE_SIZE];
+            let mut state = 7u32;
+            for b in noise.iter_mut() {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                *b = (state >> 16) as u8;
+            }
+            let noisy = dm.allocate_page_in(archive).unwrap();
+            dm.write_page(noisy, &noise[..]).unwrap();
+            drop(dm);
+
+            // 21 pages of repetitive text, and the records of every write of the bitmap page, take
+            // a lot less room than the pages themselves
+            let size: u64 = (0..16)
+                .map(|segment| match segment {
+                    0 => db_file.clone(),
+                    n => format!("{}.{}", db_file, n),
+                })
+                .filter_map(|path| fs::metadata(path).ok())
+                .map(|meta| meta.len())
+                .sum();
+            assert!(size < 10 * PAGE_SIZE as u64);
+
+            let dm = FileBasedDiskManager::new(db_file.clone()).unwrap();
+            assert!(dm.is_compressed(0).unwrap());
+            assert!(dm.is_compressed(archive).unwrap());
+            let mut buf = [0u8; PAGE_SIZE];
+            for (pid, page) in &pids {
+                dm.read_page(*pid, &mut buf[..]).unwrap();
+                assert_eq!(buf[..DATA_SIZE], page[..DATA_SIZE]);
+            }
+            dm.read_page(archived, &mut buf[..]).unwrap();
+            assert_eq!(buf[..DATA_SIZE], [b'a'; DATA_SIZE]);
+            dm.read_page(noisy, &mut buf[..]).unwrap();
+            assert_eq!(buf[..DATA_SIZE], noise[..DATA_SIZE]);
+            // allocated but never written
+            let fresh = dm.allocate_page().unwrap();
+            dm.read_page(fresh, &mut buf[..]).unwrap();
+            assert_eq!(buf, [0u8; PAGE_SIZE]);
+            // the extent of a deallocated page is released
+            dm.deallocate_page(noisy).unwrap();
+            dm.read_page(noisy, &mut buf[..]).unwrap();
+            assert_eq!(buf, [0u8; PAGE_SIZE]);
+            drop(dm);
+
+            let report = verify_database_file(&db_file).unwrap();
+            assert!(report.is_ok());
+            // the bitmap page and 20 pages, the bitmap and the page left of the compressed
+            // tablespace, and the header and bitmap pages of the other one
+            assert_eq!(report.num_pages, 21 + 2 + 2);
+            // the first entry of the extent map is the one of the bitmap page
+            let extents = fs::read(format!("{}.extents", db_file)).unwrap();
+            let start = u64::from_be_bytes(extents[8..16].try_into().unwrap());
+            let file = File::options().write(true).open(&db_file).unwrap();
+            file.write_all_at(&[0xffu8; 4], PAGE_SIZE as u64 + start * 512 + 10)
+                .unwrap();
+            let report = verify_database_file(&db_file).unwrap();
+            assert_eq!(report.corrupted.len(), 1);
+            assert_eq!(report.corrupted[0].0, 1);
+
+            // compressed lengths would leak the content of encrypted pages
+            let key_file = "compressed_tablespace.key";
+            EncryptionKey::generate().store(key_file).unwrap();
+            let encrypted = DiskOptions {
+                key_file: Some(key_file.into()),
+                ..options
+            };
+            assert!(matches!(
+                FileBasedDiskManager::with_options(
+                    "compressed_tablespace_encrypted.db".to_string(),
+                    encrypted.clone()
+                ),
+                Err(RustubError::TablespaceError(_))
+            ));
+            let dm = FileBasedDiskManager::with_options(
+                "compressed_tablespace_encrypted.db".to_string(),
+                DiskOptions {
+                    compression: false,
+                    ..encrypted
+                },
+            )
+            .unwrap();
+            assert!(matches!(
+                dm.create_tablespace_with("archive", dir, TablespaceOptions { compressed: true }),
+                Err(RustubError::TablespaceError(_))
+            ));
+            drop(dm);
+            tear_down("compressed_tablespace_encrypted");
+        })
+    }
+
+    #[test]
+    fn io_metrics() {
+        run_test("io_metrics", |db_file| {
+            let dm = FileBasedDiskManager::new(db_file.clone()).unwrap();
+            let before = dm.metrics();
+            let pid = dm.allocate_page().unwrap();
+            dm.write_page(pid, &[1u8; PAGE_SIZE][..]).unwrap();
+            dm.write_page(pid, &[2u8; PAGE_SIZE][..]).unwrap();
+            let mut buf = [0u8; PAGE_SIZE];
+            dm.read_page(pid, &mut buf[..]).unwrap();
+            dm.sync().unwrap();
+            dm.write_log(&[0u8; 100][..]).unwrap();
+            dm.write_log(&[0u8; 28][..]).unwrap();
+
+            let after = dm.metrics();
+            // the allocation writes the free page bitmap
+            assert_eq!(after.page_writes.count - before.page_writes.count, 3);
+            assert_eq!(dm.num_writes() as u64, after.page_writes.count);
+            assert_eq!(
+                after.page_writes.bytes - before.page_writes.bytes,
+                3 * PAGE_SIZE as u64
+            );
+            assert_eq!(after.page_reads.count - before.page_reads.count, 1);
+            assert_eq!(after.syncs.count - before.syncs.count, 1);
+            assert_eq!(after.log_appends.count, 2);
+            assert_eq!(after.log_appends.bytes, 128);
+            assert_eq!(after.log_flushes.bytes, 128);
+            assert_eq!(after.log_flushes.count, dm.num_flushes() as u64);
+            assert!(after.num_fsyncs() >= 2);
+            assert_eq!(after.page_writes.latency.count(), after.page_writes.count);
+            assert!(after.page_writes.latency.max >= after.page_writes.latency.mean());
+
+            // rejected and failed operations
+            assert!(dm.write_page(pid + 1, &[0u8; PAGE_SIZE][..]).is_err());
+            assert_eq!(dm.metrics().page_writes.count, after.page_writes.count);
+            let len = fs::metadata(&db_file).unwrap().len();
+            File::options()
+                .write(true)
+                .open(&db_file)
+                .unwrap()
+                .set_len(len - 1)
+                .unwrap();
+            assert!(dm.read_page(pid, &mut buf[..]).is_err());
+            assert_eq!(dm.metrics().page_reads.errors, 1);
         })
     }
 
     #[test]
     fn read_write_log() {
-        let mut buf = [0u8; 16];
-        let mut data = [0u8; 16];
-        let db_file = "test.db".to_string();
-        let mut dm = FileBasedDiskManager::new(db_file).unwrap();
-
-        let test_str = &b"A test string."[..];
-        // todo: refactor this
-        unsafe {
-            memcpy(data.as_mut_ptr(), test_str.as_ptr(), test_str.len());
-        }
+        run_test("read_write_log", |db_file| {
+            let mut buf = [0u8; 16];
+            let mut data = [0u8; 16];
+            let dm = FileBasedDiskManager::new(db_file).unwrap();
 
-        // dm.read_log(&mut buf[..], 0);
+            let test_str = &b"A test string."[..];
+            // todo: refactor this
+            unsafe {
+                memcpy(data.as_mut_ptr(), test_str.as_ptr(), test_str.len());
+            }
+
+            assert_eq!(dm.read_log(&mut buf[..], 0).unwrap(), 0);
+
+            dm.write_log(&data[..]).unwrap();
+            assert_eq!(dm.read_log(&mut buf[..], 0).unwrap(), 16);
+            assert_eq!(buf, data);
+        })
+    }
+
+    #[test]
+    fn concurrent_read_write_page() {
+        run_test("concurrent_read_write_page", |db_file| {
+            let dm: Arc<dyn DiskManager> = Arc::new(FileBasedDiskManager::new(db_file).unwrap());
+            let workers: Vec<_> = (0..8)
+                .map(|i| {
+                    let dm = dm.clone();
+                    thread::spawn(move || {
+                        let mut buf = [0u8; PAGE_SIZE];
+                        let mut written = Vec::new();
+                        for round in 0..32u8 {
+                            let pid = dm.allocate_page().unwrap();
+                            let data = [round ^ (i << 5); PAGE_SIZE];
+                            dm.write_page(pid, &data[..]).unwrap();
+                            dm.read_page(pid, &mut buf[..]).unwrap();
+                            assert_eq!(buf[..DATA_SIZE], data[..DATA_SIZE]);
+                            written.push((pid, data[0]));
+                        }
+                        written
+                    })
+                })
+                .collect();
+            let mut written = Vec::new();
+            for w in workers {
+                written.extend(w.join().unwrap());
+            }
 
-        dm.write_log(&data[..]);
-        dm.read_log(&mut buf[..], 0);
-        assert_eq!(buf, data);
+            // every page has been handed out exactly once
+            let mut buf = [0u8; PAGE_SIZE];
+            written.sort();
+            written.dedup_by_key(|(pid, _)| *pid);
+            assert_eq!(written.len(), 8 * 32);
+            for (pid, byte) in written {
+                dm.read_page(pid, &mut buf[..]).unwrap();
+                assert_eq!(buf[..DATA_SIZE], [byte; DATA_SIZE]);
+            }
+        })
+    }
+
+    #[test]
+    fn concurrent_write_log() {
+        run_test("concurrent_write_log", |db_file| {
+            let dm: Arc<dyn DiskManager> = Arc::new(FileBasedDiskManager::new(db_file).unwrap());
+            let workers: Vec<_> = (0..4u8)
+                .map(|i| {
+                    let dm = dm.clone();
+                    thread::spawn(move || {
+                        for _ in 0..64 {
+                            dm.write_log(&[i; 16][..]).unwrap();
+                        }
+                    })
+                })
+                .collect();
+            for w in workers {
+                w.join().unwrap();
+            }
+            assert!(dm.is_flushed());
+            assert!(dm.num_flushes() <= 4 * 64);
+            assert_eq!(dm.flushed_lsn(), 4 * 64 * 16);
+
+            // every record must land in one piece, never interleaved with another append
+            let mut buf = [0u8; 16];
+            let mut counts = [0; 4];
+            for n in 0..4 * 64 {
+                assert_eq!(dm.read_log(&mut buf[..], n * 16).unwrap(), 16);
+                assert!(buf.iter().all(|b| *b == buf[0]));
+                counts[buf[0] as usize] += 1;
+            }
+            assert_eq!(counts, [64; 4]);
+            assert_eq!(dm.read_log(&mut buf[..], 4 * 64 * 16).unwrap(), 0);
+        })
+    }
+
+    #[test]
+    fn group_commit_log() {
+        run_test("group_commit_log", |db_file| {
+            let dm = FileBasedDiskManager::new(db_file.clone()).unwrap();
+            let futures: Vec<_> = (0..1000u32)
+                .map(|i| dm.append_log(&i.to_be_bytes()[..]).unwrap())
+                .collect();
+            for (i, future) in futures.iter().enumerate() {
+                assert_eq!(future.lsn() as usize, (i + 1) * 4);
+                assert!(future.wait().unwrap() >= future.lsn());
+                assert!(future.is_ready());
+            }
+            assert!(dm.is_flushed());
+            assert_eq!(dm.flushed_lsn(), 4000);
+            // appends piled up while the flusher was syncing are made durable together
+            assert!(dm.num_flushes() < 1000);
+
+            // everything appended is flushed before the disk manager goes away
+            let last = dm.append_log(&b"last"[..]).unwrap();
+            drop(dm);
+            assert_eq!(last.wait().unwrap(), 4004);
+            let dm = FileBasedDiskManager::new(db_file).unwrap();
+            assert_eq!(dm.flushed_lsn(), 4004);
+            let mut buf = [0u8; 4];
+            assert_eq!(dm.read_log(&mut buf[..], 4000).unwrap(), 4);
+            assert_eq!(&buf[..], &b"last"[..]);
+            assert_eq!(dm.read_log(&mut buf[..], 3996).unwrap(), 4);
+            assert_eq!(buf, 999u32.to_be_bytes());
+        })
     }
 
     #[test]
     fn append_read_write() {
+        set_up("append_read_write");
         let mut log = File::options()
             .append(true)
             .create(true)
             .read(true)
-            .open("test.log")
+            .open("append_read_write.log")
             .unwrap();
 
         log.write(&b"12345"[..]).unwrap();
@@ -394,5 +1575,6 @@ mod test {
         log.flush();
         log.read(&mut buf[..]).unwrap();
         assert_eq!(buf[0], '4' as u8);
+        tear_down("append_read_write");
     }
 }
@@ -0,0 +1,113 @@
+use crate::common::config::{PageId, TablespaceId, DEFAULT_TABLESPACE_ID};
+use crate::common::error::{IOContext, Result};
+use crate::storage::disk::encryption::Cipher;
+use crate::storage::disk::metrics::DiskMetrics;
+use crate::storage::disk::tablespace::{self, tablespace_of, Tablespace};
+use crate::storage::disk::{EncryptionKey, Superblock};
+use crate::RustubError;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// PageReader reads the pages of a database without writing to any of its files, e.g. to inspect
+/// a damaged database, or one opened by another process. Pages are verified and decrypted like
+/// `FileBasedDiskManager` does, but the log isn't replayed: pages are read as they are on disk.
+///
+/// THREAD SAFETY: YES
+pub struct PageReader {
+    superblock: Superblock,
+    tablespaces: HashMap<TablespaceId, Tablespace>,
+    metrics: DiskMetrics,
+}
+
+impl PageReader {
+    /// Open the given database read-only. An encrypted database can only be read with its key file.
+    pub fn open(db_file: &str, key_file: Option<&Path>) -> Result<Self> {
+        let open = |path: &Path, context| {
+            fs::File::open(path).map_err(|e| RustubError::IOError(e, IOContext::Other(context)))
+        };
+        let db_io = open(Path::new(db_file), "can't open database file")?;
+        let superblock = Superblock::read_from_file(&db_io)?;
+        let cipher = match (&superblock.wrapped_keys, key_file) {
+            (Some(wrapped_keys), Some(key_file)) => Some(Arc::new(Cipher::unwrap(
+                wrapped_keys,
+                &EncryptionKey::load(key_file)?,
+            )?)),
+            (Some(_), None) => return Err(RustubError::EncryptionError("database is encrypted")),
+            (None, Some(_)) => {
+                return Err(RustubError::EncryptionError("database isn't encrypted"));
+            }
+            (None, None) => None,
+        };
+        let metrics = DiskMetrics::new();
+
+        let mut tablespaces = HashMap::new();
+        let default = Tablespace::open_read_only(
+            DEFAULT_TABLESPACE_ID,
+            PathBuf::from(db_file),
+            db_io,
+            superblock.clone(),
+            cipher.clone(),
+            &metrics,
+        )?;
+        tablespaces.insert(DEFAULT_TABLESPACE_ID, default);
+        for info in tablespace::load_catalog(&tablespace::catalog_path(db_file))? {
+            let path = tablespace::tablespace_path(db_file, &info);
+            let first = open(&path, "can't open tablespace")?;
+            let segment = Superblock::read_from_file(&first)?;
+            if !segment.is_segment_of(&superblock, info.id, 0) {
+                return Err(RustubError::IncompatibleDatabase(
+                    "segment file belongs to another database",
+                ));
+            }
+            let mut header = superblock.for_segment(info.id, 0);
+            header.compressed = segment.compressed;
+            let space =
+                Tablespace::open_read_only(info.id, path, first, header, cipher.clone(), &metrics)?;
+            tablespaces.insert(info.id, space);
+        }
+        Ok(PageReader {
+            superblock,
+            tablespaces,
+            metrics,
+        })
+    }
+
+    /// Returns the size of the pages of the database
+    pub fn page_size(&self) -> usize {
+        self.superblock.page_size
+    }
+
+    /// Read the given page, which fails if the page is corrupted. A page which has been allocated
+    /// but never written reads as zeros.
+    pub fn read_page(&self, pid: PageId, data: &mut [u8]) -> Result<()> {
+        assert_eq!(data.len(), self.page_size());
+        if pid < 0 {
+            return Err(RustubError::PageNotAllocated(pid));
+        }
+        match self.tablespaces.get(&tablespace_of(pid)) {
+            Some(space) => space.read_page(&self.metrics, pid, data),
+            None => Err(RustubError::PageNotAllocated(pid)),
+        }
+    }
+
+    /// Read the given page like `read_page`, except that a corrupted page is read as it's stored,
+    /// without being decrypted, instead of failing. Returns why the page is corrupted, or None if
+    /// it isn't.
+    pub fn read_page_unverified(
+        &self,
+        pid: PageId,
+        data: &mut [u8],
+    ) -> Result<Option<&'static str>> {
+        match self.read_page(pid, data) {
+            Ok(()) => Ok(None),
+            Err(RustubError::PageCorrupted(_, reason)) => {
+                // the page is in a known tablespace, or it couldn't have been found corrupted
+                self.tablespaces[&tablespace_of(pid)].read_raw_page(&self.metrics, pid, data)?;
+                Ok(Some(reason))
+            }
+            Err(e) => Err(e),
+        }
+    }
+}
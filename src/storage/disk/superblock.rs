@@ -0,0 +1,317 @@
+use crate::common::config::{TablespaceId, MAX_PAGE_SIZE, MIN_PAGE_SIZE};
+use crate::common::error::{IOContext, Result};
+use crate::storage::disk::encryption::WRAPPED_KEYS_SIZE;
+use crate::RustubError;
+use bytes::{Buf, BufMut};
+use std::collections::hash_map::RandomState;
+use std::fs;
+use std::hash::{BuildHasher, Hasher};
+use std::os::unix::fs::FileExt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Identifies a rustub database file
+const MAGIC: &[u8; 8] = b"RUSTUBDB";
+/// Version of the on-disk format. Files of any other version are rejected.
+pub const FORMAT_VERSION: u32 = 3;
+/// Number of bytes of the superblock which are in use
+pub const SUPERBLOCK_SIZE: usize = 512;
+const CREATED_BY_SIZE: usize = 32;
+
+const OFFSET_FORMAT_VERSION: usize = 8;
+const OFFSET_PAGE_SIZE: usize = 12;
+const OFFSET_SEGMENT_PAGES: usize = 16;
+const OFFSET_TABLESPACE: usize = 20;
+const OFFSET_SEGMENT: usize = 24;
+const OFFSET_DATABASE_ID: usize = 28;
+const OFFSET_CREATED_AT: usize = 36;
+const OFFSET_CREATED_BY: usize = 44;
+const OFFSET_CLEAN_SHUTDOWN: usize = OFFSET_CREATED_BY + CREATED_BY_SIZE;
+const OFFSET_ENCRYPTED: usize = OFFSET_CLEAN_SHUTDOWN + 1;
+const OFFSET_COMPRESSED: usize = OFFSET_ENCRYPTED + 1;
+const OFFSET_WRAPPED_KEYS: usize = 80;
+const OFFSET_CHECKSUM: usize = SUPERBLOCK_SIZE - 4;
+
+// The superblock occupies the first page-sized block of every segment file, before the pages of
+// the segment. Only the first SUPERBLOCK_SIZE bytes are used, the rest of the block is zero. The
+// superblock of the first segment of the default tablespace, i.e. the database file itself,
+// describes the database. All other segment files carry a copy of it, with their own tablespace id
+// and segment number, which tells whether they belong to the database.
+//
+// Superblock format (size in byte):
+// ----------------------------------------------------------------------------------------------
+// | magic (8) | format version (4) | page size (4) | segment size (4) | tablespace id (4) |
+// ----------------------------------------------------------------------------------------------
+// | segment number (4) | database id (8) | created at (8) | created by (32) | clean shutdown (1) |
+// ----------------------------------------------------------------------------------------------
+// | encrypted (1) | compressed (1) | padding (1) | wrapped data keys (92) | ... unused ... |
+// ----------------------------------------------------------------------------------------
+// | checksum (4) |
+// ----------------
+//
+// The segment size is in pages. The database id is a random number picked when the database is
+// created. `created at` is in seconds since the unix epoch, `created by` the
+// zero padded version of rustub which has created the database. The clean shutdown flag and the data
+// keys of an encrypted database, see `Cipher`, are only stored in the database file. The compressed
+// flag is set in every segment file of a compressed tablespace, see `ExtentMap`. The checksum is a
+// CRC32 of everything before it.
+
+/// Superblock describes a database file as a whole.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Superblock {
+    pub format_version: u32,
+    pub page_size: usize,
+    /// Number of pages in a segment file
+    pub segment_pages: usize,
+    /// The tablespace and the segment of the file holding this superblock
+    pub tablespace: TablespaceId,
+    pub segment: usize,
+    /// Random number which identifies the database
+    pub database_id: u64,
+    /// Seconds since the unix epoch when the database has been created
+    pub created_at: u64,
+    /// Version of rustub which has created the database
+    pub created_by: String,
+    /// Cleared while the database is open. If it's not set when the database is opened, the
+    /// database has not been shut down properly and may need recovery.
+    pub clean_shutdown: bool,
+    /// The data keys of an encrypted database, wrapped with the key of its key file
+    pub wrapped_keys: Option<[u8; WRAPPED_KEYS_SIZE]>,
+    /// Set if the pages of the tablespace of the file are compressed
+    pub compressed: bool,
+}
+
+impl Superblock {
+    /// Create the superblock of a new database with the given page size and number of pages per
+    /// segment file.
+    pub fn new(page_size: usize, segment_pages: usize) -> Result<Self> {
+        if !Superblock::is_valid_page_size(page_size) {
+            return Err(RustubError::IncompatibleDatabase("unsupported page size"));
+        }
+        if !Superblock::is_valid_segment_size(segment_pages) {
+            return Err(RustubError::IncompatibleDatabase(
+                "unsupported segment size",
+            ));
+        }
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        // RandomState is seeded randomly, which is all we need for an id
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u128(now.as_nanos());
+        hasher.write_u32(std::process::id());
+        Ok(Superblock {
+            format_version: FORMAT_VERSION,
+            page_size,
+            segment_pages,
+            tablespace: 0,
+            segment: 0,
+            database_id: hasher.finish(),
+            created_at: now.as_secs(),
+            created_by: format!("rustub {}", env!("CARGO_PKG_VERSION")),
+            clean_shutdown: false,
+            wrapped_keys: None,
+            compressed: false,
+        })
+    }
+
+    /// Returns true if a database can be created with the given page size
+    #[inline]
+    pub fn is_valid_page_size(page_size: usize) -> bool {
+        page_size.is_power_of_two() && (MIN_PAGE_SIZE..=MAX_PAGE_SIZE).contains(&page_size)
+    }
+
+    /// Returns true if a database can be created with the given number of pages per segment
+    #[inline]
+    pub fn is_valid_segment_size(segment_pages: usize) -> bool {
+        segment_pages > 0 && segment_pages <= u32::MAX as usize
+    }
+
+    /// Returns the superblock of the given segment file of the database described by this
+    /// superblock. The segment is compressed if this superblock is.
+    pub fn for_segment(&self, tablespace: TablespaceId, segment: usize) -> Superblock {
+        Superblock {
+            tablespace,
+            segment,
+            clean_shutdown: false,
+            wrapped_keys: None,
+            ..self.clone()
+        }
+    }
+
+    /// Returns true if this superblock has been read from the given segment file of the database
+    /// described by `database`.
+    pub fn is_segment_of(
+        &self,
+        database: &Superblock,
+        tablespace: TablespaceId,
+        segment: usize,
+    ) -> bool {
+        self.database_id == database.database_id
+            && self.page_size == database.page_size
+            && self.segment_pages == database.segment_pages
+            && self.tablespace == tablespace
+            && self.segment == segment
+    }
+
+    /// Serialize the superblock into the first SUPERBLOCK_SIZE bytes of the given buf
+    pub fn encode(&self, buf: &mut [u8]) {
+        let buf = &mut buf[..SUPERBLOCK_SIZE];
+        buf.fill(0u8);
+        buf[..OFFSET_FORMAT_VERSION].copy_from_slice(MAGIC);
+        (&mut buf[OFFSET_FORMAT_VERSION..]).put_u32(self.format_version);
+        (&mut buf[OFFSET_PAGE_SIZE..]).put_u32(self.page_size as u32);
+        (&mut buf[OFFSET_SEGMENT_PAGES..]).put_u32(self.segment_pages as u32);
+        (&mut buf[OFFSET_TABLESPACE..]).put_u32(self.tablespace as u32);
+        (&mut buf[OFFSET_SEGMENT..]).put_u32(self.segment as u32);
+        (&mut buf[OFFSET_DATABASE_ID..]).put_u64(self.database_id);
+        (&mut buf[OFFSET_CREATED_AT..]).put_u64(self.created_at);
+        let created_by = self.created_by.as_bytes();
+        let len = created_by.len().min(CREATED_BY_SIZE);
+        buf[OFFSET_CREATED_BY..OFFSET_CREATED_BY + len].copy_from_slice(&created_by[..len]);
+        buf[OFFSET_CLEAN_SHUTDOWN] = self.clean_shutdown as u8;
+        buf[OFFSET_COMPRESSED] = self.compressed as u8;
+        if let Some(wrapped_keys) = &self.wrapped_keys {
+            buf[OFFSET_ENCRYPTED] = 1;
+            buf[OFFSET_WRAPPED_KEYS..OFFSET_WRAPPED_KEYS + WRAPPED_KEYS_SIZE]
+                .copy_from_slice(wrapped_keys);
+        }
+        let checksum = crc32fast::hash(&buf[..OFFSET_CHECKSUM]);
+        (&mut buf[OFFSET_CHECKSUM..]).put_u32(checksum);
+    }
+
+    /// Deserialize the superblock from the first SUPERBLOCK_SIZE bytes of the given buf. Fails if
+    /// the buf doesn't hold the superblock of a database in the current format.
+    pub fn decode(buf: &[u8]) -> Result<Self> {
+        if buf.len() < SUPERBLOCK_SIZE || &buf[..OFFSET_FORMAT_VERSION] != MAGIC {
+            return Err(RustubError::IncompatibleDatabase("not a rustub database"));
+        }
+        let checksum = (&buf[OFFSET_CHECKSUM..]).get_u32();
+        if checksum != crc32fast::hash(&buf[..OFFSET_CHECKSUM]) {
+            return Err(RustubError::IncompatibleDatabase(
+                "superblock checksum mismatch",
+            ));
+        }
+        let format_version = (&buf[OFFSET_FORMAT_VERSION..]).get_u32();
+        if format_version != FORMAT_VERSION {
+            return Err(RustubError::IncompatibleDatabase(
+                "unsupported format version",
+            ));
+        }
+        let page_size = (&buf[OFFSET_PAGE_SIZE..]).get_u32() as usize;
+        if !Superblock::is_valid_page_size(page_size) {
+            return Err(RustubError::IncompatibleDatabase("unsupported page size"));
+        }
+        let segment_pages = (&buf[OFFSET_SEGMENT_PAGES..]).get_u32() as usize;
+        if !Superblock::is_valid_segment_size(segment_pages) {
+            return Err(RustubError::IncompatibleDatabase(
+                "unsupported segment size",
+            ));
+        }
+        let tablespace = (&buf[OFFSET_TABLESPACE..]).get_u32();
+        if tablespace > TablespaceId::MAX as u32 {
+            return Err(RustubError::IncompatibleDatabase(
+                "unsupported tablespace id",
+            ));
+        }
+        let wrapped_keys = match buf[OFFSET_ENCRYPTED] {
+            0 => None,
+            _ => {
+                let mut wrapped_keys = [0u8; WRAPPED_KEYS_SIZE];
+                wrapped_keys.copy_from_slice(
+                    &buf[OFFSET_WRAPPED_KEYS..OFFSET_WRAPPED_KEYS + WRAPPED_KEYS_SIZE],
+                );
+                Some(wrapped_keys)
+            }
+        };
+        let created_by = &buf[OFFSET_CREATED_BY..OFFSET_CREATED_BY + CREATED_BY_SIZE];
+        let len = created_by
+            .iter()
+            .position(|b| *b == 0)
+            .unwrap_or(CREATED_BY_SIZE);
+        Ok(Superblock {
+            format_version,
+            page_size,
+            segment_pages,
+            tablespace: tablespace as TablespaceId,
+            segment: (&buf[OFFSET_SEGMENT..]).get_u32() as usize,
+            database_id: (&buf[OFFSET_DATABASE_ID..]).get_u64(),
+            created_at: (&buf[OFFSET_CREATED_AT..]).get_u64(),
+            created_by: String::from_utf8_lossy(&created_by[..len]).into_owned(),
+            clean_shutdown: buf[OFFSET_CLEAN_SHUTDOWN] != 0,
+            wrapped_keys,
+            compressed: buf[OFFSET_COMPRESSED] != 0,
+        })
+    }
+
+    /// Read the superblock of the given database file, without opening the database.
+    pub fn read_from(db_file: &str) -> Result<Self> {
+        let file = fs::File::open(db_file)
+            .map_err(|e| RustubError::IOError(e, IOContext::Other("can't open database file")))?;
+        Superblock::read_from_file(&file)
+    }
+
+    /// Read the superblock at the start of the given file
+    pub(crate) fn read_from_file(file: &fs::File) -> Result<Self> {
+        let mut buf = [0u8; SUPERBLOCK_SIZE];
+        match file.read_exact_at(&mut buf, 0) {
+            Ok(_) => Superblock::decode(&buf),
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+                Err(RustubError::IncompatibleDatabase("superblock is cut off"))
+            }
+            Err(e) => Err(RustubError::IOError(
+                e,
+                IOContext::Other("can't read superblock"),
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::storage::disk::encryption::WRAPPED_KEYS_SIZE;
+    use crate::storage::disk::superblock::{Superblock, FORMAT_VERSION, SUPERBLOCK_SIZE};
+    use crate::RustubError;
+
+    #[test]
+    fn encode_decode() {
+        let mut superblock = Superblock::new(8192, 128).unwrap();
+        superblock.clean_shutdown = true;
+        superblock.wrapped_keys = Some([5u8; WRAPPED_KEYS_SIZE]);
+        superblock.compressed = true;
+        let mut buf = [0u8; SUPERBLOCK_SIZE];
+        superblock.encode(&mut buf);
+        let decoded = Superblock::decode(&buf).unwrap();
+        assert_eq!(decoded, superblock);
+        assert_eq!(decoded.format_version, FORMAT_VERSION);
+        assert!(decoded.created_by.starts_with("rustub "));
+
+        buf[100] ^= 1;
+        assert!(matches!(
+            Superblock::decode(&buf),
+            Err(RustubError::IncompatibleDatabase(
+                "superblock checksum mismatch"
+            ))
+        ));
+        assert!(matches!(
+            Superblock::decode(&[0u8; SUPERBLOCK_SIZE]),
+            Err(RustubError::IncompatibleDatabase("not a rustub database"))
+        ));
+        assert!(Superblock::new(3000, 128).is_err());
+        assert!(Superblock::new(512, 128).is_err());
+        assert!(Superblock::new(4096, 0).is_err());
+
+        let segment = superblock.for_segment(3, 2);
+        superblock.encode(&mut buf);
+        let mut segment_buf = [0u8; SUPERBLOCK_SIZE];
+        segment.encode(&mut segment_buf);
+        let decoded = Superblock::decode(&segment_buf).unwrap();
+        assert!(decoded.is_segment_of(&superblock, 3, 2));
+        assert!(!decoded.is_segment_of(&superblock, 3, 1));
+        assert!(!decoded.clean_shutdown);
+        assert!(decoded.wrapped_keys.is_none());
+        assert!(decoded.compressed);
+        assert!(!Superblock::new(8192, 128)
+            .unwrap()
+            .is_segment_of(&superblock, 0, 0));
+    }
+}
@@ -0,0 +1,823 @@
+use crate::common::config::{
+    PageId, TablespaceId, DEFAULT_TABLESPACE_ID, PAGE_RESERVED_SIZE, PAGE_TRAILER_SIZE,
+};
+use crate::common::error::{IOContext, Result};
+use crate::storage::disk::allocator::FreePageMap;
+use crate::storage::disk::checksum;
+use crate::storage::disk::compression::{self, ExtentMap};
+use crate::storage::disk::encryption::{Cipher, ENCRYPTION_OVERHEAD};
+use crate::storage::disk::metrics::DiskMetrics;
+use crate::storage::disk::superblock::Superblock;
+use crate::RustubError;
+use bytes::{Buf, BufMut};
+use std::fs;
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Instant;
+
+/// Number of low bits of a page id which number the page inside its tablespace. The bits above
+/// hold the tablespace id, and the sign bit is never used.
+pub const TABLESPACE_PAGE_BITS: u32 = 24;
+/// Maximum number of pages in a tablespace
+pub const MAX_TABLESPACE_PAGES: usize = 1 << TABLESPACE_PAGE_BITS;
+/// Maximum number of tablespaces, including the default one
+pub const MAX_TABLESPACES: usize = 1 << (PageId::BITS - 1 - TABLESPACE_PAGE_BITS);
+/// Name of the default tablespace
+pub const DEFAULT_TABLESPACE_NAME: &str = "default";
+/// Maximum length of the name of a tablespace
+const MAX_NAME_SIZE: usize = 64;
+
+/// Returns the id of the given page of a tablespace
+#[inline]
+pub fn page_id_in(tablespace: TablespaceId, page_no: usize) -> PageId {
+    debug_assert!(page_no < MAX_TABLESPACE_PAGES);
+    ((tablespace as PageId) << TABLESPACE_PAGE_BITS) | page_no as PageId
+}
+
+/// Returns the tablespace holding the given page
+#[inline]
+pub fn tablespace_of(pid: PageId) -> TablespaceId {
+    (pid >> TABLESPACE_PAGE_BITS) as TablespaceId
+}
+
+/// Returns the number of the given page inside its tablespace
+#[inline]
+fn page_no_of(pid: PageId) -> usize {
+    pid as usize & (MAX_TABLESPACE_PAGES - 1)
+}
+
+/// Returns the number of bytes at the end of every page which are reserved for the page trailer,
+/// and for the nonce and tag of an encrypted page
+pub(crate) fn reserved_size(encrypted: bool) -> usize {
+    const _: () = assert!(PAGE_TRAILER_SIZE + ENCRYPTION_OVERHEAD <= PAGE_RESERVED_SIZE);
+    match encrypted {
+        true => PAGE_TRAILER_SIZE + ENCRYPTION_OVERHEAD,
+        false => PAGE_TRAILER_SIZE,
+    }
+}
+
+/// Returns the path of the given segment file of a tablespace whose first segment is `path`.
+pub(crate) fn segment_path(path: &Path, segment: usize) -> PathBuf {
+    if segment == 0 {
+        return path.to_path_buf();
+    }
+    let mut path = path.as_os_str().to_os_string();
+    path.push(format!(".{}", segment));
+    PathBuf::from(path)
+}
+
+/// Returns the path of the first segment of the named tablespace of a database
+pub(crate) fn tablespace_path(db_file: &str, info: &TablespaceInfo) -> PathBuf {
+    if info.id == DEFAULT_TABLESPACE_ID {
+        return PathBuf::from(db_file);
+    }
+    let db_name = Path::new(db_file).file_name().unwrap_or_default();
+    let mut name = db_name.to_os_string();
+    name.push(format!(".{}", info.name));
+    info.dir.join(name)
+}
+
+/// Returns the path of the catalog of the named tablespaces of a database, which is stored next to
+/// the database file, with the same name and the `.tablespaces` extension.
+pub(crate) fn catalog_path(db_file: &str) -> PathBuf {
+    let stem = match db_file.rfind('.') {
+        Some(idx) => &db_file[..idx],
+        None => db_file,
+    };
+    PathBuf::from(format!("{}.tablespaces", stem))
+}
+
+/// Returns true if the given name can be used for a new tablespace. The name ends up in file
+/// names, so only ascii letters, digits and '_' are allowed, and it must start with a letter.
+pub fn is_valid_tablespace_name(name: &str) -> bool {
+    name.len() <= MAX_NAME_SIZE
+        && name.starts_with(|c: char| c.is_ascii_alphabetic())
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && name != DEFAULT_TABLESPACE_NAME
+}
+
+/// Sync the directory entries of the given directory, e.g. after a file has been created in it.
+pub(crate) fn sync_dir(dir: &Path) -> Result<()> {
+    let dir = if dir.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        dir
+    };
+    fs::File::open(dir)
+        .and_then(|d| d.sync_all())
+        .map_err(|e| RustubError::IOError(e, IOContext::Other("can't sync directory")))
+}
+
+/// A named tablespace of a database
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TablespaceInfo {
+    pub id: TablespaceId,
+    pub name: String,
+    /// The directory which holds the segment files of the tablespace
+    pub dir: PathBuf,
+}
+
+// The named tablespaces of a database are listed in a catalog file next to the database file,
+// which is replaced as a whole whenever a tablespace is added.
+//
+// Catalog format (size in byte):
+// ----------------------------------------------------------------------
+// | count (4) | entry 1 | entry 2 | ... | entry count | checksum (4) |
+// ----------------------------------------------------------------------
+//
+// Entry format (size in byte):
+// -------------------------------------------------------------------------------
+// | tablespace id (1) | name size (2) | name | directory size (2) | directory |
+// -------------------------------------------------------------------------------
+//
+// The checksum is a CRC32 of everything before it.
+
+/// Load the catalog of the named tablespaces. A database without catalog has no named tablespace.
+pub(crate) fn load_catalog(path: &Path) -> Result<Vec<TablespaceInfo>> {
+    let data = match fs::read(path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => {
+            return Err(RustubError::IOError(
+                e,
+                IOContext::Other("can't read tablespace catalog"),
+            ))
+        }
+    };
+    let corrupted = || RustubError::IncompatibleDatabase("corrupted tablespace catalog");
+    if data.len() < 8 {
+        return Err(corrupted());
+    }
+    let (mut entries, mut checksum) = data.split_at(data.len() - 4);
+    if checksum.get_u32() != crc32fast::hash(entries) {
+        return Err(corrupted());
+    }
+    let count = entries.get_u32();
+    let mut catalog = Vec::new();
+    for _ in 0..count {
+        if entries.remaining() < 3 {
+            return Err(corrupted());
+        }
+        let id = entries.get_u8();
+        let name = read_string(&mut entries).ok_or_else(corrupted)?;
+        let dir = read_string(&mut entries).ok_or_else(corrupted)?;
+        catalog.push(TablespaceInfo {
+            id,
+            name,
+            dir: PathBuf::from(dir),
+        });
+    }
+    Ok(catalog)
+}
+
+/// Replace the catalog of the named tablespaces.
+pub(crate) fn store_catalog(path: &Path, catalog: &[TablespaceInfo]) -> Result<()> {
+    let mut data = Vec::new();
+    data.put_u32(catalog.len() as u32);
+    for info in catalog {
+        data.put_u8(info.id);
+        let dir = info.dir.to_str().ok_or_else(|| {
+            RustubError::IOError(
+                std::io::Error::other("tablespace directory isn't valid unicode"),
+                IOContext::Other("can't write tablespace catalog"),
+            )
+        })?;
+        for s in [info.name.as_str(), dir] {
+            data.put_u16(s.len() as u16);
+            data.put_slice(s.as_bytes());
+        }
+    }
+    let checksum = crc32fast::hash(&data);
+    data.put_u32(checksum);
+    write_file_atomically(path, &data, "can't write tablespace catalog")
+}
+
+/// Replace the file at `path` with the given data. The data is written aside and renamed over the
+/// old file, so a crash leaves either of them behind.
+pub(crate) fn write_file_atomically(path: &Path, data: &[u8], context: &'static str) -> Result<()> {
+    let mut tmp = path.as_os_str().to_os_string();
+    tmp.push(".tmp");
+    let result = fs::File::create(&tmp)
+        .and_then(|mut file| {
+            std::io::Write::write_all(&mut file, data)?;
+            file.sync_all()
+        })
+        .and_then(|_| fs::rename(&tmp, path));
+    if let Err(e) = result {
+        let _ = fs::remove_file(&tmp);
+        return Err(RustubError::IOError(e, IOContext::Other(context)));
+    }
+    sync_dir(path.parent().unwrap_or(Path::new("")))
+}
+
+fn read_string(buf: &mut &[u8]) -> Option<String> {
+    if buf.remaining() < 2 {
+        return None;
+    }
+    let len = buf.get_u16() as usize;
+    if buf.remaining() < len {
+        return None;
+    }
+    let s = String::from_utf8(buf[..len].to_vec()).ok();
+    buf.advance(len);
+    s
+}
+
+/// Tablespace stores the pages of a tablespace in segment files of `segment_pages` pages each. The
+/// first segment is stored at the path of the tablespace, segment `n` next to it with the `.n`
+/// extension. Every segment file starts with a superblock identifying it, followed by its pages:
+///
+/// -----------------------------------------------------------------------
+/// | superblock | page 0 | page 1 | ... | page (segment_pages - 1) |  <path>
+/// -----------------------------------------------------------------------
+/// | superblock | page segment_pages | ... |                           <path>.1
+/// ---------------------------------------------
+///
+/// Segment files are only created when a page in them is written. Each tablespace has its own
+/// free page bitmaps, stored in its own pages, so the first page of every tablespace is reserved.
+/// In the default tablespace, it's the header page.
+///
+/// The pages of a compressed tablespace are stored wherever their compressed records fit in the
+/// segment files instead, as tracked by its `ExtentMap`.
+///
+/// THREAD SAFETY: YES
+pub(crate) struct Tablespace {
+    id: TablespaceId,
+    path: PathBuf,
+    /// The superblock of the first segment, from which the ones of new segments are derived
+    header: Superblock,
+    segments: RwLock<Vec<Arc<fs::File>>>,
+    /// Encrypts the pages of an encrypted database
+    cipher: Option<Arc<Cipher>>,
+    /// The in-memory copy of the free page bitmaps of the tablespace, by page number
+    free_pages: RwLock<FreePageMap>,
+    /// Where the pages of a compressed tablespace are stored
+    extents: Option<Mutex<ExtentMap>>,
+}
+
+impl Tablespace {
+    /// Create a new tablespace of the database described by `database`, with its first segment at
+    /// `path`. Its pages are compressed if `compressed` is set.
+    pub fn create(
+        id: TablespaceId,
+        path: PathBuf,
+        database: &Superblock,
+        compressed: bool,
+        cipher: Option<Arc<Cipher>>,
+        metrics: &DiskMetrics,
+    ) -> Result<Self> {
+        let mut header = database.for_segment(id, 0);
+        header.compressed = compressed;
+        Tablespace::check_compression(&header, cipher.is_some())?;
+        let first = Tablespace::create_segment(&path, &header)?;
+        Tablespace::open(id, path, first, header, cipher, metrics)
+    }
+
+    /// Open the tablespace whose first segment, holding `header`, is `first`. The other segments
+    /// are looked up next to it.
+    pub fn open(
+        id: TablespaceId,
+        path: PathBuf,
+        first: fs::File,
+        header: Superblock,
+        cipher: Option<Arc<Cipher>>,
+        metrics: &DiskMetrics,
+    ) -> Result<Self> {
+        Tablespace::open_with(id, path, first, header, cipher, metrics, false)
+    }
+
+    /// Open the tablespace like `open` does, without writing to any of its files. The first
+    /// segment may be opened read-only, and pages can only be read.
+    pub fn open_read_only(
+        id: TablespaceId,
+        path: PathBuf,
+        first: fs::File,
+        header: Superblock,
+        cipher: Option<Arc<Cipher>>,
+        metrics: &DiskMetrics,
+    ) -> Result<Self> {
+        Tablespace::open_with(id, path, first, header, cipher, metrics, true)
+    }
+
+    fn open_with(
+        id: TablespaceId,
+        path: PathBuf,
+        first: fs::File,
+        header: Superblock,
+        cipher: Option<Arc<Cipher>>,
+        metrics: &DiskMetrics,
+        read_only: bool,
+    ) -> Result<Self> {
+        Tablespace::check_compression(&header, cipher.is_some())?;
+        let page_size = header.page_size;
+        let reserved_size = reserved_size(cipher.is_some());
+        let mut segments = vec![Arc::new(first)];
+        loop {
+            let segment = segments.len();
+            let file = match fs::File::options()
+                .read(true)
+                .write(!read_only)
+                .open(segment_path(&path, segment))
+            {
+                Ok(file) => file,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => break,
+                Err(e) => {
+                    return Err(RustubError::IOError(
+                        e,
+                        IOContext::Other("can't open segment file"),
+                    ))
+                }
+            };
+            if !Superblock::read_from_file(&file)?.is_segment_of(&header, id, segment) {
+                return Err(RustubError::IncompatibleDatabase(
+                    "segment file belongs to another database",
+                ));
+            }
+            segments.push(Arc::new(file));
+        }
+        let extents = match header.compressed {
+            true => Some(ExtentMap::load(
+                &compression::extent_map_path(&path),
+                page_size,
+                header.segment_pages,
+            )?),
+            false => None,
+        };
+        let num_pages = match &extents {
+            Some(extents) => extents.num_pages(),
+            None => {
+                // the first block of every segment holds its superblock
+                let last = segments.len() - 1;
+                let last_size = Tablespace::file_size(&segments[last])? as usize;
+                last * header.segment_pages
+                    + last_size.saturating_sub(page_size).div_ceil(page_size)
+            }
+        };
+
+        let tablespace = Tablespace {
+            id,
+            path,
+            header,
+            segments: RwLock::new(segments),
+            cipher,
+            // replaced right below, once the bitmaps have been read through the segments
+            free_pages: RwLock::new(FreePageMap::load(page_size, reserved_size, 0, |_, _| {}).0),
+            extents: extents.map(Mutex::new),
+        };
+        let mut err = None;
+        let (map, created) =
+            FreePageMap::load(page_size, reserved_size, num_pages, |page_no, buf| {
+                if err.is_none() {
+                    err = tablespace
+                        .read_page_at(metrics, page_no as usize, buf)
+                        .err();
+                }
+            });
+        if let Some(e) = err {
+            return Err(e);
+        }
+        // a read-only tablespace keeps its missing bitmaps in memory only
+        if !read_only {
+            for page_no in created {
+                tablespace.write_page_at(metrics, page_no as usize, map.bitmap(page_no))?;
+            }
+        }
+        *tablespace.free_pages.write().unwrap() = map;
+        Ok(tablespace)
+    }
+
+    #[inline]
+    pub fn id(&self) -> TablespaceId {
+        self.id
+    }
+
+    /// Returns the path of the first segment file
+    #[inline]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns true if the pages of the tablespace are compressed
+    #[inline]
+    pub fn is_compressed(&self) -> bool {
+        self.extents.is_some()
+    }
+
+    /// A compressed tablespace needs room for a page which doesn't compress in every segment, and
+    /// can't be encrypted: the size of the compressed pages would tell about their content.
+    pub fn check_compression(header: &Superblock, encrypted: bool) -> Result<()> {
+        if !header.compressed {
+            return Ok(());
+        }
+        if encrypted {
+            return Err(RustubError::TablespaceError(
+                "an encrypted tablespace can't be compressed",
+            ));
+        }
+        if header.segment_pages < 2 {
+            return Err(RustubError::TablespaceError(
+                "a compressed tablespace needs segments of 2 pages or more",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Allocate a page, marking it as used in the free page bitmap on disk.
+    pub fn allocate(&self, metrics: &DiskMetrics) -> Result<PageId> {
+        let mut free_pages = self.free_pages.write().unwrap();
+        let (page_no, bitmap_page_no) = free_pages.allocate();
+        if page_no as usize >= MAX_TABLESPACE_PAGES {
+            free_pages.deallocate(page_no);
+            return Err(RustubError::TablespaceError("tablespace is full"));
+        }
+        let bitmap = free_pages.bitmap(bitmap_page_no);
+        if let Err(e) = self.write_page_at(metrics, bitmap_page_no as usize, bitmap) {
+            // the page is not handed out, keep it free
+            free_pages.deallocate(page_no);
+            return Err(e);
+        }
+        Ok(page_id_in(self.id, page_no as usize))
+    }
+
+    /// Deallocate a page, marking it as free in the free page bitmap on disk. The extent of a page
+    /// of a compressed tablespace is released, and the page reads as zeros from now on.
+    pub fn deallocate(&self, metrics: &DiskMetrics, pid: PageId) -> Result<()> {
+        let mut free_pages = self.free_pages.write().unwrap();
+        let bitmap_page_no = match free_pages.deallocate(page_no_of(pid) as PageId) {
+            Some(bitmap_page_no) => bitmap_page_no,
+            None => return Err(RustubError::PageNotAllocated(pid)),
+        };
+        let bitmap = free_pages.bitmap(bitmap_page_no);
+        self.write_page_at(metrics, bitmap_page_no as usize, bitmap)?;
+        if let Some(extents) = &self.extents {
+            extents.lock().unwrap().remove(page_no_of(pid) as u32);
+        }
+        Ok(())
+    }
+
+    /// Write an allocated page. The free page bitmaps can't be written.
+    pub fn write_page(&self, metrics: &DiskMetrics, pid: PageId, data: &[u8]) -> Result<()> {
+        self.check_writable(pid)?;
+        self.write_page_at(metrics, page_no_of(pid), data)
+    }
+
+    /// Write an allocated page as it's given, without encrypting it nor stamping its trailer, e.g.
+    /// to leave a torn page behind.
+    pub fn write_raw_page(&self, metrics: &DiskMetrics, pid: PageId, data: &[u8]) -> Result<()> {
+        self.check_writable(pid)?;
+        self.write_stored_page_at(metrics, page_no_of(pid), data)
+    }
+
+    /// Read a page, and verify its trailer.
+    pub fn read_page(&self, metrics: &DiskMetrics, pid: PageId, data: &mut [u8]) -> Result<()> {
+        self.read_page_at(metrics, page_no_of(pid), data)
+    }
+
+    /// Read a page as it's stored, without verifying its trailer nor decrypting it.
+    pub fn read_raw_page(&self, metrics: &DiskMetrics, pid: PageId, data: &mut [u8]) -> Result<()> {
+        let start = Instant::now();
+        let result = self.read_raw_page_at(pid, page_no_of(pid), data);
+        metrics.page_reads.record(&result, data.len(), start);
+        result
+    }
+
+    /// Fail unless the given page is allocated and isn't a free page bitmap
+    fn check_writable(&self, pid: PageId) -> Result<()> {
+        let page_no = page_no_of(pid) as PageId;
+        let free_pages = self.free_pages.read().unwrap();
+        if free_pages.is_bitmap_page(page_no) || !free_pages.is_allocated(page_no) {
+            return Err(RustubError::PageNotAllocated(pid));
+        }
+        Ok(())
+    }
+
+    /// Sync every segment file. The extent map of a compressed tablespace is saved once the pages
+    /// it refers to are durable.
+    pub fn sync(&self) -> Result<()> {
+        // the extents are copied before the segment files are synced, so that an extent installed
+        // meanwhile, whose record may not be durable, isn't saved before the next sync
+        let snapshot = self
+            .extents
+            .as_ref()
+            .map(|extents| extents.lock().unwrap().snapshot());
+        let segments = self.segments.read().unwrap().clone();
+        let result = segments
+            .iter()
+            .try_for_each(|file| file.sync_data())
+            .map_err(|e| RustubError::IOError(e, IOContext::Other("can't sync segment file")))
+            .and_then(|_| match &snapshot {
+                Some(snapshot) => snapshot.store(&compression::extent_map_path(&self.path)),
+                None => Ok(()),
+            });
+        if let (Some(extents), Some(snapshot)) = (&self.extents, snapshot) {
+            extents.lock().unwrap().release(snapshot, result.is_ok());
+        }
+        result
+    }
+
+    /// Overwrite the superblock of the first segment
+    pub fn write_header(&self, header: &Superblock) -> Result<()> {
+        let first = self.segments.read().unwrap()[0].clone();
+        Tablespace::write_superblock(&first, header)
+    }
+
+    /// Write the superblock, together with the rest of its block, at the start of a segment file.
+    pub fn write_superblock(file: &fs::File, superblock: &Superblock) -> Result<()> {
+        let mut block = vec![0u8; superblock.page_size];
+        superblock.encode(&mut block);
+        file.write_all_at(&block, 0)
+            .map_err(|e| RustubError::IOError(e, IOContext::Other("can't write superblock")))
+    }
+
+    pub fn file_size(file: &fs::File) -> Result<u64> {
+        file.metadata()
+            .map(|meta| meta.len())
+            .map_err(|e| RustubError::IOError(e, IOContext::Other("can't stat segment file")))
+    }
+
+    /// Create a segment file holding only the given superblock, and make it durable.
+    fn create_segment(path: &Path, superblock: &Superblock) -> Result<fs::File> {
+        let file = fs::File::options()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(path)
+            .map_err(|e| RustubError::IOError(e, IOContext::Other("can't create segment file")))?;
+        Tablespace::write_superblock(&file, superblock)?;
+        file.sync_data()
+            .map_err(|e| RustubError::IOError(e, IOContext::Other("can't sync segment file")))?;
+        sync_dir(path.parent().unwrap_or(Path::new("")))?;
+        Ok(file)
+    }
+
+    /// Returns the given segment file. If the segment doesn't exist yet, it's created if `create`
+    /// is set, otherwise None is returned.
+    fn segment_file(&self, segment: usize, create: bool) -> Result<Option<Arc<fs::File>>> {
+        if let Some(file) = self.segments.read().unwrap().get(segment) {
+            return Ok(Some(file.clone()));
+        }
+        if !create {
+            return Ok(None);
+        }
+        let mut segments = self.segments.write().unwrap();
+        while segments.len() <= segment {
+            let n = segments.len();
+            let header = self.header.for_segment(self.id, n);
+            let file = Tablespace::create_segment(&segment_path(&self.path, n), &header)?;
+            segments.push(Arc::new(file));
+        }
+        Ok(Some(segments[segment].clone()))
+    }
+
+    /// Returns the segment file holding the given page of an uncompressed tablespace, and the
+    /// offset of the page in it, as `segment_file` does.
+    fn locate(&self, page_no: usize, create: bool) -> Result<Option<(Arc<fs::File>, u64)>> {
+        let segment = page_no / self.header.segment_pages;
+        let offset =
+            (page_no % self.header.segment_pages + 1) as u64 * self.header.page_size as u64;
+        Ok(self
+            .segment_file(segment, create)?
+            .map(|file| (file, offset)))
+    }
+
+    /// Write a whole page at its position in its segment file, encrypted and with its trailer
+    /// stamped.
+    fn write_page_at(&self, metrics: &DiskMetrics, page_no: usize, data: &[u8]) -> Result<()> {
+        let pid = page_id_in(self.id, page_no);
+        let mut page = data.to_vec();
+        if let Some(cipher) = &self.cipher {
+            cipher.encrypt_page(pid, &mut page);
+        }
+        checksum::stamp_page(pid, &mut page);
+        self.write_stored_page_at(metrics, page_no, &page)
+    }
+
+    /// Write a page which is already encrypted and stamped at its position in its segment file.
+    fn write_stored_page_at(
+        &self,
+        metrics: &DiskMetrics,
+        page_no: usize,
+        page: &[u8],
+    ) -> Result<()> {
+        let pid = page_id_in(self.id, page_no);
+        if let Some(extents) = &self.extents {
+            return self.write_record(metrics, extents, pid, page_no, page);
+        }
+        let (file, offset) = self.locate(page_no, true)?.unwrap();
+        let start = Instant::now();
+        let result = file.write_all_at(page, offset);
+        metrics.page_writes.record(&result, page.len(), start);
+        result.map_err(|e| RustubError::IOError(e, IOContext::Page(pid)))
+    }
+
+    /// Write the compressed record of a stamped page to a free extent, which becomes the extent of
+    /// the page once written.
+    fn write_record(
+        &self,
+        metrics: &DiskMetrics,
+        extents: &Mutex<ExtentMap>,
+        pid: PageId,
+        page_no: usize,
+        page: &[u8],
+    ) -> Result<()> {
+        let record = compression::encode_record(pid, page);
+        let mut map = extents.lock().unwrap();
+        let extent = map.allocate(record.len());
+        let (segment, offset) = map.locate(extent);
+        drop(map);
+        let start = Instant::now();
+        let result = self.segment_file(segment, true).and_then(|file| {
+            file.unwrap()
+                .write_all_at(&record, offset)
+                .map_err(|e| RustubError::IOError(e, IOContext::Page(pid)))
+        });
+        let mut map = extents.lock().unwrap();
+        match result {
+            Ok(_) => map.install(page_no as u32, extent),
+            Err(_) => map.discard(extent),
+        }
+        drop(map);
+        metrics.page_writes.record(&result, record.len(), start);
+        result
+    }
+
+    /// Read a whole page at its position in its segment file, verify its trailer and decrypt it.
+    fn read_page_at(&self, metrics: &DiskMetrics, page_no: usize, data: &mut [u8]) -> Result<()> {
+        let pid = page_id_in(self.id, page_no);
+        let start = Instant::now();
+        let result = self
+            .read_raw_page_at(pid, page_no, data)
+            .and_then(|_| {
+                checksum::verify_page(pid, data)
+                    .map_err(|c| RustubError::PageCorrupted(pid, c.reason()))
+            })
+            .and_then(|_| match &self.cipher {
+                Some(cipher) => cipher.decrypt_page(pid, data),
+                None => Ok(()),
+            });
+        metrics.page_reads.record(&result, data.len(), start);
+        result
+    }
+
+    /// Read a whole page at its position in its segment file. A page beyond the end of its segment
+    /// has been allocated but never written, and reads as zeros. A page which is cut off by the end
+    /// of file can't be trusted and results in an error.
+    fn read_raw_page_at(&self, pid: PageId, page_no: usize, data: &mut [u8]) -> Result<()> {
+        if let Some(extents) = &self.extents {
+            return self.read_record(extents, pid, page_no, data);
+        }
+        let (file, offset) = match self.locate(page_no, false)? {
+            Some(location) => location,
+            None => {
+                debug!("Read page {} past the last segment", pid);
+                data.fill(0u8);
+                return Ok(());
+            }
+        };
+        match Tablespace::read_at(&file, data, offset) {
+            Ok(0) => {
+                debug!("Read page {} past the end of file", pid);
+                data.fill(0u8);
+                Ok(())
+            }
+            Ok(n) if n < data.len() => Err(RustubError::IOError(
+                std::io::Error::new(
+                    std::io::ErrorKind::UnexpectedEof,
+                    format!("read {} bytes out of a page", n),
+                ),
+                IOContext::Page(pid),
+            )),
+            Ok(_) => Ok(()),
+            Err(e) => Err(RustubError::IOError(e, IOContext::Page(pid))),
+        }
+    }
+
+    /// Read the compressed record of a page and decompress it. A page which has never been written
+    /// reads as zeros.
+    fn read_record(
+        &self,
+        extents: &Mutex<ExtentMap>,
+        pid: PageId,
+        page_no: usize,
+        data: &mut [u8],
+    ) -> Result<()> {
+        let map = extents.lock().unwrap();
+        let extent = match map.get(page_no as u32) {
+            Some(extent) => extent,
+            None => {
+                debug!("Read page {} which has no extent", pid);
+                data.fill(0u8);
+                return Ok(());
+            }
+        };
+        let (segment, offset) = map.locate(extent);
+        drop(map);
+        let file = self.segment_file(segment, false)?.ok_or_else(|| {
+            RustubError::IOError(
+                std::io::Error::new(std::io::ErrorKind::NotFound, "segment file is missing"),
+                IOContext::Page(pid),
+            )
+        })?;
+        let mut record = vec![0u8; extent.len as usize];
+        let n = Tablespace::read_at(&file, &mut record, offset)
+            .map_err(|e| RustubError::IOError(e, IOContext::Page(pid)))?;
+        if n < record.len() {
+            return Err(RustubError::IOError(
+                std::io::Error::new(
+                    std::io::ErrorKind::UnexpectedEof,
+                    format!("read {} bytes out of a record", n),
+                ),
+                IOContext::Page(pid),
+            ));
+        }
+        compression::decode_record(pid, &record, data)
+            .map_err(|c| RustubError::PageCorrupted(pid, c.reason()))
+    }
+
+    /// Read from `file` at `offset` until `data` is full or the end of file is reached. Returns the
+    /// number of bytes actually read.
+    pub fn read_at(
+        file: &fs::File,
+        mut data: &mut [u8],
+        mut offset: u64,
+    ) -> std::io::Result<usize> {
+        let mut total = 0;
+        while !data.is_empty() {
+            match file.read_at(data, offset) {
+                // the read has reached its 'end-of-file'
+                Ok(0) => break,
+                Ok(n) => {
+                    let tmp = data;
+                    data = &mut tmp[n..];
+                    offset += n as u64;
+                    total += n;
+                }
+                Err(ref e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(total)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::storage::disk::tablespace::{
+        is_valid_tablespace_name, load_catalog, page_id_in, segment_path, store_catalog,
+        tablespace_of, TablespaceInfo, MAX_TABLESPACES,
+    };
+    use std::fs;
+    use std::path::{Path, PathBuf};
+
+    #[test]
+    fn page_ids_and_names() {
+        assert_eq!(MAX_TABLESPACES, 128);
+        assert_eq!(page_id_in(0, 42), 42);
+        assert_eq!(tablespace_of(page_id_in(5, 42)), 5);
+        assert_eq!(tablespace_of(page_id_in(127, (1 << 24) - 1)), 127);
+        assert!(page_id_in(127, (1 << 24) - 1) > 0);
+        assert_eq!(
+            segment_path(Path::new("a/b.db"), 0),
+            PathBuf::from("a/b.db")
+        );
+        assert_eq!(
+            segment_path(Path::new("a/b.db"), 3),
+            PathBuf::from("a/b.db.3")
+        );
+
+        assert!(is_valid_tablespace_name("archive_2020"));
+        assert!(!is_valid_tablespace_name("2020"));
+        assert!(!is_valid_tablespace_name("a.b"));
+        assert!(!is_valid_tablespace_name("../x"));
+        assert!(!is_valid_tablespace_name("default"));
+        assert!(!is_valid_tablespace_name(""));
+    }
+
+    #[test]
+    fn store_load_catalog() {
+        let path = Path::new("store_load_catalog.tablespaces");
+        let _ = fs::remove_file(path);
+        assert!(load_catalog(path).unwrap().is_empty());
+        let catalog = vec![
+            TablespaceInfo {
+                id: 1,
+                name: "cold".to_string(),
+                dir: PathBuf::from("/mnt/cold"),
+            },
+            TablespaceInfo {
+                id: 3,
+                name: "fast".to_string(),
+                dir: PathBuf::from("fast"),
+            },
+        ];
+        store_catalog(path, &catalog).unwrap();
+        assert_eq!(load_catalog(path).unwrap(), catalog);
+
+        let mut data = fs::read(path).unwrap();
+        data[6] ^= 1;
+        fs::write(path, data).unwrap();
+        assert!(load_catalog(path).is_err());
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -1,4 +1,5 @@
-mod disk;
+mod buffer;
+pub(crate) mod disk;
 pub mod index;
-mod page;
+pub(crate) mod page;
 pub mod table;
@@ -0,0 +1,197 @@
+use crate::common::config::{PageId, INVALID_PAGE_ID, PAGE_SIZE, USABLE_PAGE_SIZE};
+use crate::storage::page::{BasePage, Page};
+use bytes::{Buf, BufMut};
+use std::borrow::{Borrow, BorrowMut};
+
+const SIZE_FREE_SPACE_MAP_PAGE_HEADER: usize = 20;
+const SIZE_ENTRY: usize = 5;
+const OFFSET_LSN: usize = 4;
+const OFFSET_NEXT_PAGE_ID: usize = 8;
+const OFFSET_LAST_PAGE_ID: usize = 12;
+const OFFSET_ENTRY_COUNT: usize = 16;
+
+/// The free space of a page is recorded in units of this many bytes, rounded down
+const FREE_SPACE_UNIT: usize = PAGE_SIZE / 256;
+
+/// The number of pages a free space map page keeps track of, up to the end of its usable part
+pub const FREE_SPACE_MAP_PAGE_CAPACITY: usize =
+    (USABLE_PAGE_SIZE - SIZE_FREE_SPACE_MAP_PAGE_HEADER) / SIZE_ENTRY;
+
+/// Free space map pages record the approximate free space of the pages of a table, so that an
+/// insert finds a page with enough room without reading the pages of the table. The free space is
+/// rounded down to a multiple of `PAGE_SIZE / 256`, so that a page never has less room than
+/// recorded. The pages of a large table are spread over a chain of free space map pages.
+///
+/// Format (size in byte):
+/// ----------------------------------------------------------------------------------------
+/// | page id (4) | LSN (4) | next page id (4) | last page id (4) | entry count (4) | ... |
+/// ----------------------------------------------------------------------------------------
+///
+/// ----------------------------------------------------------------------
+/// | page_1 id (4) | page_1 free space (1) | page_2 id (4) | ... |
+/// ----------------------------------------------------------------------
+///
+/// The last page id is the last page of the table, and is only kept up to date in the first page
+/// of the chain.
+///
+/// A free space map page either owns its page, or views a page held by the buffer pool.
+pub struct FreeSpaceMapPage<P = BasePage> {
+    base: P,
+}
+
+impl<P: Borrow<BasePage>> FreeSpaceMapPage<P> {
+    /// View the given page as a free space map page
+    pub fn from_page(base: P) -> Self {
+        FreeSpaceMapPage { base }
+    }
+
+    /// Returns the page id of this free space map page
+    pub fn page_id(&self) -> PageId {
+        self.bytes().get_i32()
+    }
+
+    /// Returns the page id of the next free space map page of the chain
+    pub fn next_page_id(&self) -> PageId {
+        (&self.bytes()[OFFSET_NEXT_PAGE_ID..]).get_i32()
+    }
+
+    /// Returns the id of the last page of the table
+    pub fn last_page_id(&self) -> PageId {
+        (&self.bytes()[OFFSET_LAST_PAGE_ID..]).get_i32()
+    }
+
+    /// Returns the number of pages recorded in this page
+    pub fn entry_count(&self) -> usize {
+        let count = (&self.bytes()[OFFSET_ENTRY_COUNT..]).get_u32() as usize;
+        count.min(FREE_SPACE_MAP_PAGE_CAPACITY)
+    }
+
+    /// Returns the recorded free space of the given page, or None if it isn't recorded here
+    pub fn free_space(&self, pid: PageId) -> Option<usize> {
+        let entry = self.position(pid)?;
+        Some(self.entry_free_space(entry))
+    }
+
+    /// Returns the first recorded page with at least the given free space
+    pub fn find(&self, min_free_space: usize) -> Option<PageId> {
+        (0..self.entry_count())
+            .find(|entry| self.entry_free_space(*entry) >= min_free_space)
+            .map(|entry| self.entry_page_id(entry))
+    }
+
+    /// Returns the pages recorded in this page, with their recorded free space
+    pub fn entries(&self) -> Vec<(PageId, usize)> {
+        (0..self.entry_count())
+            .map(|entry| (self.entry_page_id(entry), self.entry_free_space(entry)))
+            .collect()
+    }
+
+    fn position(&self, pid: PageId) -> Option<usize> {
+        (0..self.entry_count()).find(|entry| self.entry_page_id(*entry) == pid)
+    }
+
+    fn entry_page_id(&self, entry: usize) -> PageId {
+        (&self.bytes()[SIZE_FREE_SPACE_MAP_PAGE_HEADER + entry * SIZE_ENTRY..]).get_i32()
+    }
+
+    fn entry_free_space(&self, entry: usize) -> usize {
+        let offset = SIZE_FREE_SPACE_MAP_PAGE_HEADER + entry * SIZE_ENTRY + 4;
+        self.bytes()[offset] as usize * FREE_SPACE_UNIT
+    }
+
+    fn bytes(&self) -> &[u8] {
+        self.base.borrow().data()
+    }
+}
+
+impl<P: BorrowMut<BasePage>> FreeSpaceMapPage<P> {
+    /// Format the page as the last free space map page of a chain, without any entry
+    pub fn init(&mut self, page_id: PageId) {
+        let data = self.data_mut();
+        (&mut data[..]).put_i32(page_id);
+        (&mut data[OFFSET_LSN..]).put_u32(0);
+        (&mut data[OFFSET_NEXT_PAGE_ID..]).put_i32(INVALID_PAGE_ID);
+        (&mut data[OFFSET_LAST_PAGE_ID..]).put_i32(INVALID_PAGE_ID);
+        (&mut data[OFFSET_ENTRY_COUNT..]).put_u32(0);
+    }
+
+    /// Set the page id of the next free space map page of the chain
+    pub fn set_next_page_id(&mut self, pid: PageId) {
+        (&mut self.data_mut()[OFFSET_NEXT_PAGE_ID..]).put_i32(pid)
+    }
+
+    /// Set the id of the last page of the table
+    pub fn set_last_page_id(&mut self, pid: PageId) {
+        (&mut self.data_mut()[OFFSET_LAST_PAGE_ID..]).put_i32(pid)
+    }
+
+    /// Record the free space of the given page. Returns false if the page isn't recorded here yet
+    /// and this page is full.
+    pub fn set_free_space(&mut self, pid: PageId, free_space: usize) -> bool {
+        let entry = match self.position(pid) {
+            Some(entry) => entry,
+            None if self.entry_count() < FREE_SPACE_MAP_PAGE_CAPACITY => {
+                let entry = self.entry_count();
+                let offset = SIZE_FREE_SPACE_MAP_PAGE_HEADER + entry * SIZE_ENTRY;
+                (&mut self.data_mut()[offset..]).put_i32(pid);
+                (&mut self.data_mut()[OFFSET_ENTRY_COUNT..]).put_u32(entry as u32 + 1);
+                entry
+            }
+            None => return false,
+        };
+        let offset = SIZE_FREE_SPACE_MAP_PAGE_HEADER + entry * SIZE_ENTRY + 4;
+        self.data_mut()[offset] = (free_space / FREE_SPACE_UNIT).min(255) as u8;
+        true
+    }
+}
+
+impl<P: BorrowMut<BasePage>> Page for FreeSpaceMapPage<P> {
+    fn data(&self) -> &[u8] {
+        self.base.borrow().data()
+    }
+
+    fn data_mut(&mut self) -> &mut [u8] {
+        self.base.borrow_mut().data_mut()
+    }
+
+    fn page_id(&self) -> PageId {
+        self.base.borrow().page_id()
+    }
+
+    fn is_dirty(&self) -> bool {
+        self.base.borrow().is_dirty()
+    }
+
+    fn pin_count(&self) -> usize {
+        self.base.borrow().pin_count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::storage::page::{BasePage, FreeSpaceMapPage, FREE_SPACE_MAP_PAGE_CAPACITY};
+
+    #[test]
+    fn record_free_space() {
+        let mut page = FreeSpaceMapPage::from_page(BasePage::new());
+        page.init(2);
+        assert!(page.set_free_space(10, 4000));
+        assert!(page.set_free_space(11, 100));
+        assert!(page.set_free_space(12, 1000));
+        // the free space is rounded down
+        assert_eq!(page.free_space(11), Some(96));
+        assert_eq!(page.find(500), Some(10));
+        assert!(page.set_free_space(10, 0));
+        assert_eq!(page.find(500), Some(12));
+        assert_eq!(page.find(1001), None);
+        assert_eq!(page.entry_count(), 3);
+
+        for pid in 13..FREE_SPACE_MAP_PAGE_CAPACITY as i32 + 10 {
+            assert!(page.set_free_space(pid, 0));
+        }
+        assert!(!page.set_free_space(1000, 0));
+        assert!(page.set_free_space(11, 4096));
+        assert_eq!(page.free_space(11), Some(4080));
+        assert_eq!(page.free_space(1000), None);
+    }
+}
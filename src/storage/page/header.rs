@@ -1,26 +1,55 @@
-use crate::common::config::{PageId, INVALID_PAGE_ID};
-use crate::common::{memcpy, memmove};
+use crate::common::config::{PageId, INVALID_PAGE_ID, USABLE_PAGE_SIZE};
 use crate::storage::page::{BasePage, Page};
 use bytes::{Buf, BufMut};
-use std::io::{Read, Write};
-use std::mem::transmute;
-use std::process::id;
-
-const HEADER_PAGE_COUNT_SIZE: usize = 4;
-const HEADER_PAGE_ENTRY_KEY_SIZE: usize = 32;
-const HEADER_PAGE_ENTRY_VALUE_SIZE: usize = 4;
-const HEADER_PAGE_ENTRY_SIZE: usize = HEADER_PAGE_ENTRY_KEY_SIZE + HEADER_PAGE_ENTRY_VALUE_SIZE;
-
-/// Database use this first page (page_id = 0) as header page to store metadata, in our case, we will
-/// contain information about table/index name (length less than 32 bytes) and their corresponding
-/// root page id.
+use std::borrow::{Borrow, BorrowMut};
+
+const OFFSET_RECORD_COUNT: usize = 0;
+const OFFSET_NEXT_PAGE_ID: usize = 4;
+const OFFSET_FREE_SPACE: usize = 8;
+const SIZE_HEADER_PAGE_HEADER: usize = 12;
+const SIZE_NAME_LEN: usize = 4;
+const SIZE_ROOT_ID: usize = 4;
+
+/// The maximum length in bytes of the name of a record, i.e. the length of the only record a header
+/// page can hold
+pub const HEADER_PAGE_MAX_NAME_SIZE: usize =
+    USABLE_PAGE_SIZE - SIZE_HEADER_PAGE_HEADER - SIZE_NAME_LEN - SIZE_ROOT_ID;
+
+/// Header pages store metadata of the database, in our case, the names of tables/indexes and their
+/// corresponding root page id. Names have a variable length, up to `HEADER_PAGE_MAX_NAME_SIZE`.
+/// Header pages are chained through their next page id when the records don't fit in a single page,
+/// see `HeaderDirectory`.
 ///
 /// Format (size in byte):
-/// ---------------------------------------------------------
-/// | record count (4) |   name (32)   | root id (4) | ... |
-/// ---------------------------------------------------------
-pub struct HeaderPage {
-    base: BasePage,
+/// -------------------------------------------------------------------------------
+/// | record count (4) | next page id (4) | free space pointer (4) | records ... |
+/// -------------------------------------------------------------------------------
+///
+/// Records are packed after the header, the free space pointer being the end of the last one:
+/// ----------------------------------------------------
+/// | name length (4) | name (name length) | root id (4) |
+/// ----------------------------------------------------
+///
+/// A header page either owns its page, or views a page held by the buffer pool.
+pub struct HeaderPage<P = BasePage> {
+    base: P,
+}
+
+/// A record of a header page, located by its offset in the page
+struct Record<'a> {
+    offset: usize,
+    name: &'a [u8],
+    root_id: PageId,
+}
+
+impl Record<'_> {
+    fn size(&self) -> usize {
+        SIZE_NAME_LEN + self.name.len() + SIZE_ROOT_ID
+    }
+
+    fn root_id_offset(&self) -> usize {
+        self.offset + SIZE_NAME_LEN + self.name.len()
+    }
 }
 
 impl HeaderPage {
@@ -28,118 +57,173 @@ impl HeaderPage {
         let mut page = HeaderPage {
             base: BasePage::new(),
         };
-        page.set_record_count(0);
-        return page;
+        page.init();
+        page
+    }
+}
+
+impl<P: Borrow<BasePage>> HeaderPage<P> {
+    /// View the given page as a header page
+    pub fn from_page(base: P) -> Self {
+        HeaderPage { base }
+    }
+
+    /// Returns the root page id of the given name, or INVALID_PAGE_ID if it isn't in the page
+    pub fn root_id(&self, name: &str) -> PageId {
+        match self.find(name) {
+            Some(record) => record.root_id,
+            None => INVALID_PAGE_ID,
+        }
+    }
+
+    pub fn record_count(&self) -> u32 {
+        (&self.base.borrow().data()[OFFSET_RECORD_COUNT..]).get_u32()
+    }
+
+    /// Returns the id of the next header page of the chain, or INVALID_PAGE_ID for the last one
+    pub fn next_page_id(&self) -> PageId {
+        (&self.base.borrow().data()[OFFSET_NEXT_PAGE_ID..]).get_i32()
+    }
+
+    /// Returns true if a record of the given name fits in the free space of the page
+    pub fn fits(&self, name: &str) -> bool {
+        SIZE_NAME_LEN + name.len() + SIZE_ROOT_ID <= USABLE_PAGE_SIZE - self.free_space_pointer()
+    }
+
+    /// Returns the names and root page ids of the records of the page
+    pub fn records(&self) -> Vec<(String, PageId)> {
+        self.iter()
+            .map(|record| {
+                let name = String::from_utf8_lossy(record.name).into_owned();
+                (name, record.root_id)
+            })
+            .collect()
+    }
+
+    fn free_space_pointer(&self) -> usize {
+        let pointer = (&self.base.borrow().data()[OFFSET_FREE_SPACE..]).get_u32() as usize;
+        pointer.clamp(SIZE_HEADER_PAGE_HEADER, USABLE_PAGE_SIZE)
+    }
+
+    fn find(&self, name: &str) -> Option<Record<'_>> {
+        self.iter().find(|record| record.name == name.as_bytes())
     }
 
+    /// Go through the records of the page. A record which doesn't fit in the used space of the page
+    /// ends the iteration, so that a corrupted page can't cause out of bounds accesses.
+    fn iter(&self) -> impl Iterator<Item = Record<'_>> {
+        let data = self.base.borrow().data();
+        let end = self.free_space_pointer();
+        let mut offset = SIZE_HEADER_PAGE_HEADER;
+        (0..self.record_count()).map_while(move |_| {
+            if offset + SIZE_NAME_LEN > end {
+                return None;
+            }
+            let name_len = (&data[offset..]).get_u32() as usize;
+            let name_offset = offset + SIZE_NAME_LEN;
+            if name_len > end - name_offset || end - name_offset - name_len < SIZE_ROOT_ID {
+                return None;
+            }
+            let record = Record {
+                offset,
+                name: &data[name_offset..name_offset + name_len],
+                root_id: (&data[name_offset + name_len..]).get_i32(),
+            };
+            offset += record.size();
+            Some(record)
+        })
+    }
+}
+
+impl<P: BorrowMut<BasePage>> HeaderPage<P> {
+    /// Format the page as the last header page of a chain, without any record
+    pub fn init(&mut self) {
+        self.set_record_count(0);
+        self.set_next_page_id(INVALID_PAGE_ID);
+        self.set_free_space_pointer(SIZE_HEADER_PAGE_HEADER);
+    }
+
+    /// Insert a record, returns false if the name is already in the page or if the record doesn't
+    /// fit in it
     pub fn insert_record(&mut self, name: &str, root_id: PageId) -> bool {
-        assert!(name.len() < HEADER_PAGE_ENTRY_KEY_SIZE);
         assert!(root_id > INVALID_PAGE_ID);
 
-        if self.find(name) != -1 {
-            // already exists
+        if self.find(name).is_some() || !self.fits(name) {
             return false;
         }
-        let num = self.record_count();
-        let offset = HEADER_PAGE_COUNT_SIZE + num as usize * HEADER_PAGE_ENTRY_SIZE;
-        (&mut self.data_mut()[offset..]).put_slice(name.as_bytes());
-        (&mut self.data_mut()[offset + HEADER_PAGE_ENTRY_KEY_SIZE..]).put_i32(root_id);
-        self.set_record_count(num + 1);
-        return true;
+        let offset = self.free_space_pointer();
+        let data = &mut self.data_mut()[offset..];
+        let mut buf = &mut data[..];
+        buf.put_u32(name.len() as u32);
+        buf.put_slice(name.as_bytes());
+        buf.put_i32(root_id);
+        self.set_free_space_pointer(offset + SIZE_NAME_LEN + name.len() + SIZE_ROOT_ID);
+        self.set_record_count(self.record_count() + 1);
+        true
     }
 
+    /// Delete a record, moving the records after it to fill its space
     pub fn delete_record(&mut self, name: &str) -> bool {
-        assert!(name.len() < HEADER_PAGE_ENTRY_KEY_SIZE);
-        let idx = self.find(name);
-        if idx == -1 {
-            return false;
-        }
-        let offset = HEADER_PAGE_COUNT_SIZE + idx as usize * HEADER_PAGE_ENTRY_SIZE;
-        let count = self.record_count();
-        // todo: fix this unsafe block
-        unsafe {
-            let start = self.data_mut().as_mut_ptr() as usize;
-            let dest = start + offset * idx as usize;
-            let src = dest + offset;
-            let n = (count - idx as u32 - 1) as usize * HEADER_PAGE_ENTRY_SIZE;
-            memmove(dest as *mut u8, src as *const u8, n);
-        }
+        let (start, size) = match self.find(name) {
+            Some(record) => (record.offset, record.size()),
+            None => return false,
+        };
+        let end = self.free_space_pointer();
+        self.data_mut().copy_within(start + size..end, start);
+        self.set_free_space_pointer(end - size);
         self.set_record_count(self.record_count() - 1);
         true
     }
 
     pub fn update_record(&mut self, name: &str, root_id: PageId) -> bool {
-        assert!(name.len() < HEADER_PAGE_ENTRY_KEY_SIZE);
-        let idx = self.find(name);
-        if idx == -1 {
-            return false;
-        }
-        let offset = idx as usize * HEADER_PAGE_ENTRY_SIZE
-            + HEADER_PAGE_COUNT_SIZE
-            + HEADER_PAGE_ENTRY_KEY_SIZE;
-        (&mut (self.data_mut()[offset as usize..])).put_i32(root_id);
-        return true;
+        let offset = match self.find(name) {
+            Some(record) => record.root_id_offset(),
+            None => return false,
+        };
+        (&mut self.data_mut()[offset..]).put_i32(root_id);
+        true
     }
 
-    pub fn root_id(&self, name: &str) -> PageId {
-        assert!(name.len() < HEADER_PAGE_ENTRY_KEY_SIZE);
-
-        let idx = self.find(name);
-        if idx == -1 {
-            return INVALID_PAGE_ID;
-        }
-        (&self.data()[(idx + 1) as usize * HEADER_PAGE_ENTRY_SIZE..]).get_i32()
+    pub fn set_next_page_id(&mut self, next_page_id: PageId) {
+        (&mut self.data_mut()[OFFSET_NEXT_PAGE_ID..]).put_i32(next_page_id)
     }
 
-    pub fn record_count(&self) -> u32 {
-        self.data().get_u32()
-    }
-
-    fn find(&self, name: &str) -> i32 {
-        let count = self.record_count();
-        for i in 0..count {
-            let offset = HEADER_PAGE_COUNT_SIZE + i as usize * HEADER_PAGE_ENTRY_SIZE;
-            if name
-                .as_bytes()
-                .eq(&self.data()[offset..offset + name.len()])
-            {
-                return i as i32;
-            }
-        }
-        return -1;
+    fn set_record_count(&mut self, count: u32) {
+        (&mut self.data_mut()[OFFSET_RECORD_COUNT..]).put_u32(count)
     }
 
-    fn set_record_count(&mut self, count: u32) {
-        self.data_mut().put_u32(count)
+    fn set_free_space_pointer(&mut self, pointer: usize) {
+        (&mut self.data_mut()[OFFSET_FREE_SPACE..]).put_u32(pointer as u32)
     }
 }
 
-impl Page for HeaderPage {
+impl<P: BorrowMut<BasePage>> Page for HeaderPage<P> {
     fn data(&self) -> &[u8] {
-        self.base.data()
+        self.base.borrow().data()
     }
 
     fn data_mut(&mut self) -> &mut [u8] {
-        self.base.data_mut()
+        self.base.borrow_mut().data_mut()
     }
 
     fn page_id(&self) -> PageId {
-        self.base.page_id()
+        self.base.borrow().page_id()
     }
 
     fn is_dirty(&self) -> bool {
-        self.base.is_dirty()
+        self.base.borrow().is_dirty()
     }
 
     fn pin_count(&self) -> usize {
-        self.base.pin_count()
+        self.base.borrow().pin_count()
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::common::config::INVALID_PAGE_ID;
-    use crate::storage::page::HeaderPage;
+    use crate::common::config::{INVALID_PAGE_ID, USABLE_PAGE_SIZE};
+    use crate::storage::page::header::HEADER_PAGE_MAX_NAME_SIZE;
+    use crate::storage::page::{HeaderPage, Page};
 
     #[test]
     fn header_page_crud() {
@@ -155,4 +239,30 @@ mod tests {
         assert_eq!(page.record_count(), 0);
         assert_eq!(page.root_id(key), INVALID_PAGE_ID);
     }
+
+    #[test]
+    fn long_names() {
+        let mut page = HeaderPage::new();
+        let names: Vec<String> = (0..3).map(|i| format!("{}", i).repeat(1200)).collect();
+        for (i, name) in names.iter().enumerate() {
+            assert!(page.insert_record(name, i as i32));
+        }
+        assert!(!page.fits(&"3".repeat(1200)));
+        assert!(page.delete_record(&names[0]));
+        assert!(page.update_record(&names[2], 7));
+        assert_eq!(page.root_id(&names[1]), 1);
+        assert_eq!(page.root_id(&names[2]), 7);
+        assert!(page.insert_record("rustub", 8));
+        let records = page.records();
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[2], ("rustub".to_string(), 8));
+
+        let mut page = HeaderPage::new();
+        let longest = "x".repeat(HEADER_PAGE_MAX_NAME_SIZE);
+        assert!(!page.insert_record(&(longest.clone() + "x"), 1));
+        assert!(page.insert_record(&longest, 1));
+        assert!(!page.fits(""));
+        // the end of the page is left to the disk manager
+        assert!(page.data()[USABLE_PAGE_SIZE..].iter().all(|b| *b == 0));
+    }
 }
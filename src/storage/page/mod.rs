@@ -1,16 +1,22 @@
-use crate::common::config::{PageId, INVALID_PAGE_ID, PAGE_SIZE};
+use crate::common::config::{Lsn, PageId, INVALID_PAGE_ID, PAGE_SIZE};
 
+mod free_space_map;
 mod header;
+mod overflow;
 mod table;
 mod tmp;
 
-pub use header::HeaderPage;
-pub use table::TablePage;
-pub use tmp::TmpTuplePage;
+pub use free_space_map::{FreeSpaceMapPage, FREE_SPACE_MAP_PAGE_CAPACITY};
+pub use header::{HeaderPage, HEADER_PAGE_MAX_NAME_SIZE};
+pub use overflow::{OverflowPage, OVERFLOW_PAGE_CAPACITY};
+pub use table::{TablePage, TableSlot, TABLE_PAGE_SLOT_SIZE};
+pub use tmp::{TmpTuplePage, TMP_TUPLE_MAX_SIZE};
 
 // Page
 //  |__ HeaderPage
 //  |__ TablePage
+//  |__ OverflowPage
+//  |__ FreeSpaceMapPage
 //  |__ TmpTuplePage
 
 /// Page is the basic unit of storage within the database system. Page provides a wrapper for actual
@@ -32,7 +38,7 @@ pub trait Page {
     fn pin_count(&self) -> usize;
 }
 
-struct BasePage {
+pub struct BasePage {
     /// The actual data that is stored within the page
     data: [u8; PAGE_SIZE],
     /// The ID of this page
@@ -41,6 +47,9 @@ struct BasePage {
     pin_count: usize,
     /// True if the page is dirty, i.e. it is different from its corresponding page on disk
     is_dirty: bool,
+    /// The end of the log record of the last modification of the page in memory. The page must not
+    /// be written to disk until the log is durable up to there.
+    lsn: Lsn,
 }
 
 impl BasePage {
@@ -50,8 +59,26 @@ impl BasePage {
             page_id: INVALID_PAGE_ID,
             pin_count: 0,
             is_dirty: false,
+            lsn: 0,
         }
     }
+
+    /// Set the id of the page, when the buffer pool loads another page in its place. The page
+    /// hasn't been modified by any log record yet.
+    pub(crate) fn set_page_id(&mut self, pid: PageId) {
+        self.page_id = pid;
+        self.lsn = 0;
+    }
+
+    /// Returns the end of the log record of the last modification of the page
+    pub fn lsn(&self) -> Lsn {
+        self.lsn
+    }
+
+    /// Set the end of the log record of the last modification of the page
+    pub fn set_lsn(&mut self, lsn: Lsn) {
+        self.lsn = lsn;
+    }
 }
 
 impl Page for BasePage {
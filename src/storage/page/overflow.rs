@@ -0,0 +1,103 @@
+use crate::common::config::{PageId, INVALID_PAGE_ID, USABLE_PAGE_SIZE};
+use crate::storage::page::{BasePage, Page};
+use bytes::{Buf, BufMut};
+use std::borrow::{Borrow, BorrowMut};
+
+const SIZE_OVERFLOW_PAGE_HEADER: usize = 16;
+const OFFSET_LSN: usize = 4;
+const OFFSET_NEXT_PAGE_ID: usize = 8;
+const OFFSET_DATA_SIZE: usize = 12;
+
+/// The number of bytes of a tuple an overflow page can hold, up to the end of its usable part
+pub const OVERFLOW_PAGE_CAPACITY: usize = USABLE_PAGE_SIZE - SIZE_OVERFLOW_PAGE_HEADER;
+
+/// Overflow pages hold a tuple too large to be stored in a table page. The tuple is split in a
+/// chain of overflow pages, and the table page only holds a pointer to the first one.
+///
+/// Format (size in byte):
+/// -------------------------------------------------------------------------------
+/// | page id (4) | LSN (4) | next page id (4) | data size (4) | data ... |
+/// -------------------------------------------------------------------------------
+///
+/// An overflow page either owns its page, or views a page held by the buffer pool.
+pub struct OverflowPage<P = BasePage> {
+    base: P,
+}
+
+impl<P: Borrow<BasePage>> OverflowPage<P> {
+    /// View the given page as an overflow page
+    pub fn from_page(base: P) -> Self {
+        OverflowPage { base }
+    }
+
+    /// Returns the page id of this overflow page
+    pub fn page_id(&self) -> PageId {
+        self.bytes().get_i32()
+    }
+
+    /// Returns the page id of the next overflow page of the chain, or INVALID_PAGE_ID for the last
+    /// one
+    pub fn next_page_id(&self) -> PageId {
+        (&self.bytes()[OFFSET_NEXT_PAGE_ID..]).get_i32()
+    }
+
+    /// Returns the part of the tuple held by the page
+    pub fn payload(&self) -> &[u8] {
+        let size = (&self.bytes()[OFFSET_DATA_SIZE..]).get_u32() as usize;
+        let end = SIZE_OVERFLOW_PAGE_HEADER + size.min(OVERFLOW_PAGE_CAPACITY);
+        &self.bytes()[SIZE_OVERFLOW_PAGE_HEADER..end]
+    }
+
+    fn bytes(&self) -> &[u8] {
+        self.base.borrow().data()
+    }
+}
+
+impl<P: BorrowMut<BasePage>> OverflowPage<P> {
+    /// Format the page as the last overflow page of a chain, without any data
+    pub fn init(&mut self, page_id: PageId) {
+        let data = self.data_mut();
+        (&mut data[..]).put_i32(page_id);
+        (&mut data[OFFSET_LSN..]).put_u32(0);
+        (&mut data[OFFSET_NEXT_PAGE_ID..]).put_i32(INVALID_PAGE_ID);
+        (&mut data[OFFSET_DATA_SIZE..]).put_u32(0);
+    }
+
+    /// Set the page id of the next overflow page of the chain
+    pub fn set_next_page_id(&mut self, pid: PageId) {
+        (&mut self.data_mut()[OFFSET_NEXT_PAGE_ID..]).put_i32(pid)
+    }
+
+    /// Store as much of the given data as fits in the page, in place of its payload, and return
+    /// the number of bytes stored
+    pub fn set_payload(&mut self, payload: &[u8]) -> usize {
+        let size = payload.len().min(OVERFLOW_PAGE_CAPACITY);
+        let data = self.data_mut();
+        (&mut data[OFFSET_DATA_SIZE..]).put_u32(size as u32);
+        data[SIZE_OVERFLOW_PAGE_HEADER..SIZE_OVERFLOW_PAGE_HEADER + size]
+            .copy_from_slice(&payload[..size]);
+        size
+    }
+}
+
+impl<P: BorrowMut<BasePage>> Page for OverflowPage<P> {
+    fn data(&self) -> &[u8] {
+        self.base.borrow().data()
+    }
+
+    fn data_mut(&mut self) -> &mut [u8] {
+        self.base.borrow_mut().data_mut()
+    }
+
+    fn page_id(&self) -> PageId {
+        self.base.borrow().page_id()
+    }
+
+    fn is_dirty(&self) -> bool {
+        self.base.borrow().is_dirty()
+    }
+
+    fn pin_count(&self) -> usize {
+        self.base.borrow().pin_count()
+    }
+}
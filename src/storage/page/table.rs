@@ -1,9 +1,16 @@
-use crate::common::config::PageId;
+use crate::common::config::{PageId, INVALID_PAGE_ID, PAGE_SIZE, USABLE_PAGE_SIZE};
+use crate::common::rid::RecordId;
 use crate::storage::page::{BasePage, Page};
+use crate::storage::table::Tuple;
 use bytes::{Buf, BufMut};
+use std::borrow::{Borrow, BorrowMut};
 
 const SIZE_TABLE_PAGE_HEADER: usize = 24;
 const SIZE_TUPLE: usize = 8;
+
+/// The space a tuple takes in a table page besides its data, i.e. its slot
+pub const TABLE_PAGE_SLOT_SIZE: usize = SIZE_TUPLE;
+const OFFSET_LSN: usize = 4;
 const OFFSET_PREV_PAGE_ID: usize = 8;
 const OFFSET_NEXT_PAGE_ID: usize = 12;
 const OFFSET_FREE_SPACE: usize = 16;
@@ -11,6 +18,27 @@ const OFFSET_TUPLE_COUNT: usize = 20;
 const OFFSET_TUPLE_OFFSET: usize = 24;
 const OFFSET_TUPLE_SIZE: usize = 28;
 
+/// Set in the size of a tuple which has been marked as deleted, until the deletion is applied or
+/// rolled back
+const DELETE_MASK: u32 = 1 << 31;
+/// Set in the size of a tuple which only holds a pointer to the overflow pages storing the actual
+/// tuple, see `storage::table::OverflowPointer`
+const OVERFLOW_MASK: u32 = 1 << 30;
+/// The bits of the size of a tuple which hold its size
+const SIZE_MASK: u32 = !(DELETE_MASK | OVERFLOW_MASK);
+
+/// A slot of a table page as it's stored, e.g. for inspecting a page
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TableSlot {
+    pub offset: usize,
+    /// The size of the tuple, or 0 if the slot is empty
+    pub size: usize,
+    /// True if the deletion of the tuple is pending
+    pub deleted: bool,
+    /// True if the slot holds a pointer to a tuple stored in overflow pages
+    pub overflow: bool,
+}
+
 /// Slotted page format:
 /// ----------------------------------------------------------
 /// |  header | ... free space ... | ... inserted tuples ... |
@@ -25,24 +53,134 @@ const OFFSET_TUPLE_SIZE: usize = 28;
 /// -----------------------------------------------------------------
 /// | tuple count (4) | tuple_1 offset (4) | tuple_1 size (4) | ... |
 /// -----------------------------------------------------------------
-pub struct TablePage {
-    base: BasePage,
+///
+/// The tuple count is the number of slots. The tuples are packed at the end of the usable part of
+/// the page, see `USABLE_PAGE_SIZE`, without any hole between them: deleting or resizing a tuple
+/// moves the tuples stored before it. A slot whose size is 0 is empty, and is reused by the next
+/// insert. The highest bit of the size of a tuple is set while its deletion is pending, and the
+/// next one if the tuple is stored out of line.
+///
+/// A table page either owns its page, or views a page held by the buffer pool.
+pub struct TablePage<P = BasePage> {
+    base: P,
 }
 
-impl TablePage {
+impl<P: Borrow<BasePage>> TablePage<P> {
+    /// View the given page as a table page
+    pub fn from_page(base: P) -> Self {
+        TablePage { base }
+    }
+
     /// Returns the page id of this table page
     pub fn page_id(&self) -> PageId {
-        self.data().get_i32()
+        self.bytes().get_i32()
     }
 
     /// Returns the page id of the previous table page
     pub fn prev_page_id(&self) -> PageId {
-        (&self.data()[OFFSET_PREV_PAGE_ID..]).get_i32()
+        (&self.bytes()[OFFSET_PREV_PAGE_ID..]).get_i32()
     }
 
     /// Returns the page id of the next table page
     pub fn next_page_id(&self) -> PageId {
-        (&self.data()[OFFSET_NEXT_PAGE_ID..]).get_i32()
+        (&self.bytes()[OFFSET_NEXT_PAGE_ID..]).get_i32()
+    }
+
+    /// Returns the number of slots, including the empty ones
+    pub fn tuple_count(&self) -> u32 {
+        (&self.bytes()[OFFSET_TUPLE_COUNT..]).get_u32()
+    }
+
+    /// Returns the number of free bytes between the slots and the tuples
+    pub fn free_space_remaining(&self) -> usize {
+        // saturating, so that a page which isn't a table page can't panic
+        self.free_space_pointer()
+            .saturating_sub(SIZE_TABLE_PAGE_HEADER + self.tuple_count() as usize * SIZE_TUPLE)
+    }
+
+    /// Returns the tuple of the given slot, or None if the slot is empty or its tuple is marked as
+    /// deleted
+    pub fn get_tuple(&self, rid: RecordId) -> Option<Tuple> {
+        let slot = rid.slot_num();
+        if slot >= self.tuple_count() || is_deleted(self.tuple_size(slot)) {
+            return None;
+        }
+        let offset = self.tuple_offset(slot);
+        let size = (self.tuple_size(slot) & SIZE_MASK) as usize;
+        let mut tuple = Tuple::new(self.bytes()[offset..offset + size].to_vec());
+        tuple.set_rid(rid);
+        Some(tuple)
+    }
+
+    /// Returns the first tuple of the page which isn't deleted
+    pub fn first_tuple_rid(&self) -> Option<RecordId> {
+        self.tuple_rid_from(0)
+    }
+
+    /// Returns the tuple after the given one in the page which isn't deleted
+    pub fn next_tuple_rid(&self, rid: RecordId) -> Option<RecordId> {
+        assert_eq!(rid.page_id(), self.page_id());
+        self.tuple_rid_from(rid.slot_num() + 1)
+    }
+
+    /// Returns the given slot as it's stored, whether it's empty or not, or None if there is no
+    /// such slot. The slot may point out of the page if the page isn't a table page.
+    pub fn slot(&self, slot: u32) -> Option<TableSlot> {
+        if slot >= self.tuple_count()
+            || SIZE_TABLE_PAGE_HEADER + (slot as usize + 1) * SIZE_TUPLE > PAGE_SIZE
+        {
+            return None;
+        }
+        let size = self.tuple_size(slot);
+        Some(TableSlot {
+            offset: self.tuple_offset(slot),
+            size: (size & SIZE_MASK) as usize,
+            deleted: size & DELETE_MASK != 0,
+            overflow: size & OVERFLOW_MASK != 0,
+        })
+    }
+
+    /// Returns true if the given slot holds a pointer to a tuple stored in overflow pages rather
+    /// than the tuple itself
+    pub fn is_overflow(&self, rid: RecordId) -> bool {
+        let slot = rid.slot_num();
+        slot < self.tuple_count() && self.tuple_size(slot) & OVERFLOW_MASK != 0
+    }
+
+    fn tuple_rid_from(&self, first_slot: u32) -> Option<RecordId> {
+        (first_slot..self.tuple_count())
+            .find(|slot| !is_deleted(self.tuple_size(*slot)))
+            .map(|slot| RecordId::new(self.page_id(), slot))
+    }
+
+    fn free_space_pointer(&self) -> usize {
+        (&self.bytes()[OFFSET_FREE_SPACE..]).get_u32() as usize
+    }
+
+    fn tuple_offset(&self, slot: u32) -> usize {
+        (&self.bytes()[OFFSET_TUPLE_OFFSET + slot as usize * SIZE_TUPLE..]).get_u32() as usize
+    }
+
+    /// Returns the size of the tuple of the given slot, with its flags
+    fn tuple_size(&self, slot: u32) -> u32 {
+        (&self.bytes()[OFFSET_TUPLE_SIZE + slot as usize * SIZE_TUPLE..]).get_u32()
+    }
+
+    fn bytes(&self) -> &[u8] {
+        self.base.borrow().data()
+    }
+}
+
+impl<P: BorrowMut<BasePage>> TablePage<P> {
+    /// Format the page as an empty table page, which comes after the given page in its table
+    pub fn init(&mut self, page_id: PageId, prev_page_id: PageId) {
+        let data = self.data_mut();
+        (&mut data[..]).put_i32(page_id);
+        (&mut data[OFFSET_LSN..]).put_u32(0);
+        (&mut data[OFFSET_PREV_PAGE_ID..]).put_i32(prev_page_id);
+        (&mut data[OFFSET_NEXT_PAGE_ID..]).put_i32(INVALID_PAGE_ID);
+        (&mut data[OFFSET_FREE_SPACE..]).put_u32(USABLE_PAGE_SIZE as u32);
+        (&mut data[OFFSET_TUPLE_COUNT..]).put_u32(0);
     }
 
     /// Set the page id of the next table page
@@ -54,26 +192,278 @@ impl TablePage {
     pub fn set_prev_page_id(&mut self, pid: PageId) {
         (&mut self.data_mut()[OFFSET_PREV_PAGE_ID..]).put_i32(pid)
     }
+
+    /// Insert the given tuple, in the first empty slot if any. Returns where the tuple has been
+    /// stored, or None if there isn't enough free space or if the tuple is empty, since an empty
+    /// slot is told apart by its size of 0.
+    pub fn insert_tuple(&mut self, tuple: &Tuple) -> Option<RecordId> {
+        self.insert(tuple, 0)
+    }
+
+    /// Insert the pointer to a tuple stored out of line in overflow pages. The pointer is read and
+    /// deleted like any other tuple, see `is_overflow`, but can't be updated.
+    pub fn insert_overflow_tuple(&mut self, pointer: &Tuple) -> Option<RecordId> {
+        self.insert(pointer, OVERFLOW_MASK)
+    }
+
+    fn insert(&mut self, tuple: &Tuple, flags: u32) -> Option<RecordId> {
+        if tuple.is_empty() {
+            return None;
+        }
+        let count = self.tuple_count();
+        let slot = (0..count)
+            .find(|slot| self.tuple_size(*slot) == 0)
+            .unwrap_or(count);
+        let needed = match slot == count {
+            true => tuple.len() + SIZE_TUPLE,
+            false => tuple.len(),
+        };
+        if needed > self.free_space_remaining() {
+            return None;
+        }
+        let offset = self.free_space_pointer() - tuple.len();
+        self.data_mut()[offset..offset + tuple.len()].copy_from_slice(tuple.data());
+        self.set_free_space_pointer(offset);
+        self.set_tuple_offset(slot, offset);
+        self.set_tuple_size(slot, tuple.len() as u32 | flags);
+        if slot == count {
+            self.set_tuple_count(count + 1);
+        }
+        Some(RecordId::new(self.page_id(), slot))
+    }
+
+    /// Mark the given tuple as deleted. Its space is only freed by `apply_delete`, until then the
+    /// deletion can be rolled back. Returns false if there is no such tuple.
+    pub fn mark_delete(&mut self, rid: RecordId) -> bool {
+        let slot = rid.slot_num();
+        if slot >= self.tuple_count() || is_deleted(self.tuple_size(slot)) {
+            return false;
+        }
+        let size = self.tuple_size(slot);
+        self.set_tuple_size(slot, size | DELETE_MASK);
+        true
+    }
+
+    /// Remove the given tuple, whether it has been marked as deleted or not, and free its space.
+    /// Its slot becomes empty. Returns the removed tuple, or None if the slot is already empty.
+    pub fn apply_delete(&mut self, rid: RecordId) -> Option<Tuple> {
+        let slot = rid.slot_num();
+        if slot >= self.tuple_count() || self.tuple_size(slot) & SIZE_MASK == 0 {
+            return None;
+        }
+        let offset = self.tuple_offset(slot);
+        let size = (self.tuple_size(slot) & SIZE_MASK) as usize;
+        let mut tuple = Tuple::new(self.bytes()[offset..offset + size].to_vec());
+        tuple.set_rid(rid);
+
+        // move the tuples stored before it in its place
+        self.resize_tuple_space(offset, size, 0);
+        self.set_tuple_offset(slot, 0);
+        self.set_tuple_size(slot, 0);
+        Some(tuple)
+    }
+
+    /// Undo the deletion of a tuple marked as deleted. Returns false if the tuple isn't marked.
+    pub fn rollback_delete(&mut self, rid: RecordId) -> bool {
+        let slot = rid.slot_num();
+        if slot >= self.tuple_count() || self.tuple_size(slot) & DELETE_MASK == 0 {
+            return false;
+        }
+        let size = self.tuple_size(slot);
+        self.set_tuple_size(slot, size & !DELETE_MASK);
+        true
+    }
+
+    /// Replace the given tuple with a new one, which keeps its record id and is stored inline.
+    /// Returns the old tuple, or None if there is no such tuple, not enough free space for the
+    /// new one, or if the slot holds a pointer to overflow pages, whose chain would be leaked.
+    pub fn update_tuple(&mut self, new_tuple: &Tuple, rid: RecordId) -> Option<Tuple> {
+        assert!(!new_tuple.is_empty());
+        if self.is_overflow(rid) {
+            return None;
+        }
+        let old_tuple = self.get_tuple(rid)?;
+        if new_tuple.len() > old_tuple.len() + self.free_space_remaining() {
+            return None;
+        }
+        let slot = rid.slot_num();
+        let offset = self.tuple_offset(slot);
+        let new_offset = self.resize_tuple_space(offset, old_tuple.len(), new_tuple.len());
+        self.data_mut()[new_offset..new_offset + new_tuple.len()].copy_from_slice(new_tuple.data());
+        self.set_tuple_offset(slot, new_offset);
+        self.set_tuple_size(slot, new_tuple.len() as u32);
+        Some(old_tuple)
+    }
+
+    /// Resize the space of the tuple at the given offset, which ends where it ends, by moving the
+    /// tuples stored before it, and return its new offset. The page stays compact.
+    fn resize_tuple_space(&mut self, offset: usize, old_size: usize, new_size: usize) -> usize {
+        let free_space_pointer = self.free_space_pointer();
+        let new_offset = offset + old_size - new_size;
+        let new_free_space_pointer = free_space_pointer + old_size - new_size;
+        self.data_mut()
+            .copy_within(free_space_pointer..offset, new_free_space_pointer);
+        self.set_free_space_pointer(new_free_space_pointer);
+        for slot in 0..self.tuple_count() {
+            let slot_offset = self.tuple_offset(slot);
+            if self.tuple_size(slot) & SIZE_MASK != 0 && slot_offset < offset {
+                self.set_tuple_offset(slot, slot_offset + old_size - new_size);
+            }
+        }
+        new_offset
+    }
+
+    fn set_free_space_pointer(&mut self, offset: usize) {
+        (&mut self.data_mut()[OFFSET_FREE_SPACE..]).put_u32(offset as u32)
+    }
+
+    fn set_tuple_count(&mut self, count: u32) {
+        (&mut self.data_mut()[OFFSET_TUPLE_COUNT..]).put_u32(count)
+    }
+
+    fn set_tuple_offset(&mut self, slot: u32, offset: usize) {
+        (&mut self.data_mut()[OFFSET_TUPLE_OFFSET + slot as usize * SIZE_TUPLE..])
+            .put_u32(offset as u32)
+    }
+
+    fn set_tuple_size(&mut self, slot: u32, size: u32) {
+        (&mut self.data_mut()[OFFSET_TUPLE_SIZE + slot as usize * SIZE_TUPLE..]).put_u32(size)
+    }
+}
+
+/// Returns true if the slot of a tuple of the given size is empty, or its tuple is marked as
+/// deleted
+fn is_deleted(size: u32) -> bool {
+    size & DELETE_MASK != 0 || size & SIZE_MASK == 0
 }
 
-impl Page for TablePage {
+impl<P: BorrowMut<BasePage>> Page for TablePage<P> {
     fn data(&self) -> &[u8] {
-        self.base.data()
+        self.base.borrow().data()
     }
 
     fn data_mut(&mut self) -> &mut [u8] {
-        self.base.data_mut()
+        self.base.borrow_mut().data_mut()
     }
 
     fn page_id(&self) -> PageId {
-        self.base.page_id()
+        self.base.borrow().page_id()
     }
 
     fn is_dirty(&self) -> bool {
-        self.base.is_dirty()
+        self.base.borrow().is_dirty()
     }
 
     fn pin_count(&self) -> usize {
-        self.base.pin_count()
+        self.base.borrow().pin_count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::common::config::{INVALID_PAGE_ID, USABLE_PAGE_SIZE};
+    use crate::common::rid::RecordId;
+    use crate::storage::page::{BasePage, TablePage};
+    use crate::storage::table::Tuple;
+
+    fn new_page() -> TablePage {
+        let mut page = TablePage::from_page(BasePage::new());
+        page.init(7, INVALID_PAGE_ID);
+        page
+    }
+
+    #[test]
+    fn insert_and_delete() {
+        let mut page = new_page();
+        assert_eq!(page.first_tuple_rid(), None);
+        let a = page.insert_tuple(&Tuple::new(b"alpha".to_vec())).unwrap();
+        let b = page.insert_tuple(&Tuple::new(b"beta".to_vec())).unwrap();
+        assert_eq!((a, b), (RecordId::new(7, 0), RecordId::new(7, 1)));
+        assert_eq!(page.get_tuple(b).unwrap().data(), b"beta");
+        assert_eq!(page.get_tuple(b).unwrap().rid(), Some(b));
+        assert_eq!(
+            page.free_space_remaining(),
+            USABLE_PAGE_SIZE - 24 - 2 * 8 - 9
+        );
+
+        // a tuple marked as deleted is hidden until the deletion is rolled back
+        assert!(page.mark_delete(a));
+        assert!(!page.mark_delete(a));
+        assert_eq!(page.get_tuple(a), None);
+        assert_eq!(page.first_tuple_rid(), Some(b));
+        assert!(page.rollback_delete(a));
+        assert!(!page.rollback_delete(a));
+        assert_eq!(page.get_tuple(a).unwrap().data(), b"alpha");
+
+        // applying the deletion frees its space, and moves the tuple stored after it
+        assert!(page.mark_delete(a));
+        assert_eq!(page.apply_delete(a).unwrap().data(), b"alpha");
+        assert_eq!(page.apply_delete(a), None);
+        assert_eq!(page.get_tuple(b).unwrap().data(), b"beta");
+        assert_eq!(
+            page.free_space_remaining(),
+            USABLE_PAGE_SIZE - 24 - 2 * 8 - 4
+        );
+
+        // the empty slot is reused
+        let c = page.insert_tuple(&Tuple::new(b"gamma".to_vec())).unwrap();
+        assert_eq!(c, a);
+        assert_eq!(page.tuple_count(), 2);
+        // an empty tuple would look like an empty slot
+        assert_eq!(page.insert_tuple(&Tuple::new(vec![])), None);
+        assert_eq!(page.tuple_count(), 2);
+        let rids: Vec<_> =
+            std::iter::successors(page.first_tuple_rid(), |rid| page.next_tuple_rid(*rid))
+                .collect();
+        assert_eq!(rids, vec![a, b]);
+    }
+
+    #[test]
+    fn update_and_compact() {
+        let mut page = new_page();
+        let rids: Vec<_> = (0..4u8)
+            .map(|i| page.insert_tuple(&Tuple::new(vec![i; 10])).unwrap())
+            .collect();
+        let free = page.free_space_remaining();
+        // growing and shrinking tuples moves the ones stored after them
+        let old = page
+            .update_tuple(&Tuple::new(vec![9; 30]), rids[1])
+            .unwrap();
+        assert_eq!(old.data(), [1u8; 10]);
+        assert_eq!(page.free_space_remaining(), free - 20);
+        page.update_tuple(&Tuple::new(vec![8; 2]), rids[2]).unwrap();
+        assert_eq!(page.free_space_remaining(), free - 12);
+        assert_eq!(page.get_tuple(rids[0]).unwrap().data(), [0u8; 10]);
+        assert_eq!(page.get_tuple(rids[1]).unwrap().data(), [9u8; 30]);
+        assert_eq!(page.get_tuple(rids[2]).unwrap().data(), [8u8; 2]);
+        assert_eq!(page.get_tuple(rids[3]).unwrap().data(), [3u8; 10]);
+
+        // a tuple can't grow beyond the free space, nor be updated once deleted
+        let too_large = Tuple::new(vec![0; 10 + free]);
+        assert_eq!(page.update_tuple(&too_large, rids[0]), None);
+        assert!(page.mark_delete(rids[3]));
+        assert_eq!(page.update_tuple(&Tuple::new(vec![1]), rids[3]), None);
+
+        // nor replace a pointer to overflow pages
+        let pointer = page.insert_overflow_tuple(&Tuple::new(vec![7; 8])).unwrap();
+        assert_eq!(page.update_tuple(&Tuple::new(vec![1]), pointer), None);
+        assert!(page.is_overflow(pointer));
+        assert_eq!(page.get_tuple(pointer).unwrap().data(), [7u8; 8]);
+        page.apply_delete(pointer).unwrap();
+
+        // fill the page, then empty it
+        let mut all = rids.clone();
+        while let Some(rid) = page.insert_tuple(&Tuple::new(vec![5; 100])) {
+            all.push(rid);
+        }
+        assert!(page.free_space_remaining() < 100 + 8);
+        for rid in &all {
+            page.apply_delete(*rid).unwrap();
+        }
+        assert_eq!(
+            page.free_space_remaining(),
+            USABLE_PAGE_SIZE - 24 - all.len() * 8
+        );
+        assert_eq!(page.first_tuple_rid(), None);
     }
 }
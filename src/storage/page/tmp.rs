@@ -1,4 +1,193 @@
-/// To pass the test cases for this class, you must follow the existing TmpTuplePage format and
-/// implement the existing functions exactly as they are! It may be helpful to look at TablePage.
-/// Remember that this task is optional, you get full credit if you finish the next task.
-pub struct TmpTuplePage {}
+use crate::common::config::{PageId, USABLE_PAGE_SIZE};
+use crate::storage::page::{BasePage, Page};
+use crate::storage::table::{TmpTuple, Tuple};
+use bytes::{Buf, BufMut};
+use std::borrow::{Borrow, BorrowMut};
+
+const SIZE_TMP_TUPLE_PAGE_HEADER: usize = 16;
+const SIZE_TUPLE_SIZE: usize = 4;
+const OFFSET_LSN: usize = 4;
+const OFFSET_FREE_SPACE: usize = 8;
+const OFFSET_TUPLE_COUNT: usize = 12;
+
+/// The size of the largest tuple a temporary page can hold
+pub const TMP_TUPLE_MAX_SIZE: usize =
+    USABLE_PAGE_SIZE - SIZE_TMP_TUPLE_PAGE_HEADER - SIZE_TUPLE_SIZE;
+
+/// TmpTuplePage holds intermediate results of operators, e.g. the sorted runs of a sort or the
+/// partitions of a hash join, written once and read back later. Tuples can't be deleted nor
+/// updated, so they are simply appended without any slot directory.
+///
+/// Format:
+/// ```text
+/// ---------------------------------------------------------------------------------
+/// |  header | ... free space ... | size_2 (4) | tuple_2 | size_1 (4) | tuple_1 |
+/// ---------------------------------------------------------------------------------
+///                                ^
+///                                free space pointer
+/// Header format (size in byte):
+/// ------------------------------------------------------------------------
+/// | page id (4) | LSN (4) | free space pointer (4) | tuple count (4) |
+/// ------------------------------------------------------------------------
+/// ```
+///
+/// The tuples end where the usable part of the page does, see `USABLE_PAGE_SIZE`. A tuple is
+/// located by the offset of its size, see `TmpTuple`.
+///
+/// A temporary page either owns its page, or views a page held by the buffer pool.
+pub struct TmpTuplePage<P = BasePage> {
+    base: P,
+}
+
+impl<P: Borrow<BasePage>> TmpTuplePage<P> {
+    /// View the given page as a temporary page
+    pub fn from_page(base: P) -> Self {
+        TmpTuplePage { base }
+    }
+
+    /// Returns the page id of this temporary page
+    pub fn page_id(&self) -> PageId {
+        self.bytes().get_i32()
+    }
+
+    /// Returns the number of tuples of the page
+    pub fn tuple_count(&self) -> u32 {
+        (&self.bytes()[OFFSET_TUPLE_COUNT..]).get_u32()
+    }
+
+    /// Returns the number of free bytes between the header and the tuples
+    pub fn free_space_remaining(&self) -> usize {
+        self.free_space_pointer() - SIZE_TMP_TUPLE_PAGE_HEADER
+    }
+
+    /// Returns the tuple stored at the given location, or None if there is no tuple there
+    pub fn get_tuple(&self, tmp_tuple: TmpTuple) -> Option<Tuple> {
+        let offset = tmp_tuple.offset();
+        if tmp_tuple.page_id() != self.page_id()
+            || offset < self.free_space_pointer()
+            || offset + SIZE_TUPLE_SIZE > USABLE_PAGE_SIZE
+        {
+            return None;
+        }
+        let size = (&self.bytes()[offset..]).get_u32() as usize;
+        let start = offset + SIZE_TUPLE_SIZE;
+        if size > USABLE_PAGE_SIZE - start {
+            return None;
+        }
+        Some(Tuple::new(self.bytes()[start..start + size].to_vec()))
+    }
+
+    /// Returns the locations of the tuples of the page, in the order they have been inserted
+    pub fn tuples(&self) -> Vec<TmpTuple> {
+        let mut tuples = Vec::with_capacity(self.tuple_count() as usize);
+        let mut offset = self.free_space_pointer();
+        // the tuples are walked from the last inserted one, whose size comes first
+        while offset + SIZE_TUPLE_SIZE <= USABLE_PAGE_SIZE
+            && tuples.len() < self.tuple_count() as usize
+        {
+            tuples.push(TmpTuple::new(self.page_id(), offset));
+            let size = (&self.bytes()[offset..]).get_u32() as usize;
+            offset += SIZE_TUPLE_SIZE + size;
+        }
+        tuples.reverse();
+        tuples
+    }
+
+    fn free_space_pointer(&self) -> usize {
+        let pointer = (&self.bytes()[OFFSET_FREE_SPACE..]).get_u32() as usize;
+        pointer.clamp(SIZE_TMP_TUPLE_PAGE_HEADER, USABLE_PAGE_SIZE)
+    }
+
+    fn bytes(&self) -> &[u8] {
+        self.base.borrow().data()
+    }
+}
+
+impl<P: BorrowMut<BasePage>> TmpTuplePage<P> {
+    /// Format the page as an empty temporary page
+    pub fn init(&mut self, page_id: PageId) {
+        let data = self.data_mut();
+        (&mut data[..]).put_i32(page_id);
+        (&mut data[OFFSET_LSN..]).put_u32(0);
+        (&mut data[OFFSET_FREE_SPACE..]).put_u32(USABLE_PAGE_SIZE as u32);
+        (&mut data[OFFSET_TUPLE_COUNT..]).put_u32(0);
+    }
+
+    /// Append the given tuple to the page. Returns where the tuple has been stored, or None if
+    /// there isn't enough free space.
+    pub fn insert_tuple(&mut self, tuple: &Tuple) -> Option<TmpTuple> {
+        if SIZE_TUPLE_SIZE + tuple.len() > self.free_space_remaining() {
+            return None;
+        }
+        let offset = self.free_space_pointer() - SIZE_TUPLE_SIZE - tuple.len();
+        let mut buf = &mut self.data_mut()[offset..];
+        buf.put_u32(tuple.len() as u32);
+        buf.put_slice(tuple.data());
+        (&mut self.data_mut()[OFFSET_FREE_SPACE..]).put_u32(offset as u32);
+        let count = self.tuple_count();
+        (&mut self.data_mut()[OFFSET_TUPLE_COUNT..]).put_u32(count + 1);
+        Some(TmpTuple::new(self.page_id(), offset))
+    }
+}
+
+impl<P: BorrowMut<BasePage>> Page for TmpTuplePage<P> {
+    fn data(&self) -> &[u8] {
+        self.base.borrow().data()
+    }
+
+    fn data_mut(&mut self) -> &mut [u8] {
+        self.base.borrow_mut().data_mut()
+    }
+
+    fn page_id(&self) -> PageId {
+        self.base.borrow().page_id()
+    }
+
+    fn is_dirty(&self) -> bool {
+        self.base.borrow().is_dirty()
+    }
+
+    fn pin_count(&self) -> usize {
+        self.base.borrow().pin_count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::common::config::USABLE_PAGE_SIZE;
+    use crate::storage::page::{BasePage, Page, TmpTuplePage, TMP_TUPLE_MAX_SIZE};
+    use crate::storage::table::{TmpTuple, Tuple};
+
+    #[test]
+    fn append_and_read_back() {
+        let mut page = TmpTuplePage::from_page(BasePage::new());
+        page.init(3);
+        let tuples: Vec<Tuple> = (1..=3)
+            .map(|i| Tuple::new(vec![i; i as usize * 10]))
+            .collect();
+        let locations: Vec<TmpTuple> = tuples
+            .iter()
+            .map(|tuple| page.insert_tuple(tuple).unwrap())
+            .collect();
+        assert_eq!(page.tuple_count(), 3);
+        assert_eq!(page.tuples(), locations);
+        assert_eq!(page.get_tuple(locations[1]).unwrap(), tuples[1]);
+        assert!(page
+            .get_tuple(TmpTuple::new(4, locations[1].offset()))
+            .is_none());
+        assert!(page.get_tuple(TmpTuple::new(3, 20)).is_none());
+        // an empty tuple takes some space too
+        assert!(page.insert_tuple(&Tuple::new(vec![])).is_some());
+
+        page.init(3);
+        let largest = Tuple::new(vec![7; TMP_TUPLE_MAX_SIZE]);
+        assert!(page
+            .insert_tuple(&Tuple::new(vec![7; TMP_TUPLE_MAX_SIZE + 1]))
+            .is_none());
+        let location = page.insert_tuple(&largest).unwrap();
+        assert_eq!(page.free_space_remaining(), 0);
+        assert_eq!(page.get_tuple(location).unwrap(), largest);
+        // the end of the page is left to the disk manager
+        assert!(page.data()[USABLE_PAGE_SIZE..].iter().all(|b| *b == 0));
+    }
+}
@@ -0,0 +1,67 @@
+use crate::common::config::{PageId, INVALID_PAGE_ID};
+use crate::common::error::Result;
+use crate::storage::buffer::{BufferPoolManager, WritePageGuard};
+
+/// A kind of page chained through its next page id, e.g. header pages or free space map pages.
+/// Modifications of a chain latch its first page for their whole duration, then the following
+/// pages in the order of the chain.
+pub(crate) trait ChainedPage {
+    /// Returns the id of the next page of the chain, or INVALID_PAGE_ID for the last one
+    fn next_page_id(page: &WritePageGuard) -> PageId;
+
+    /// Set the id of the next page of the chain
+    fn set_next_page_id(page: &mut WritePageGuard, pid: PageId);
+
+    /// Format the given new page as an empty page of the chain
+    fn init(page: &mut WritePageGuard);
+}
+
+/// Insert a record into the chain starting with the given page, which stays latched by the caller.
+/// Every page of the chain is given to `update` first, which returns Some if the page already holds
+/// the record, ending the walk. Otherwise the record is given by `insert` to the first page `fits`
+/// tells has room for it, or else to a new page appended to the chain.
+pub(crate) fn insert_into_chain<'a, C: ChainedPage, T>(
+    bpm: &'a BufferPoolManager,
+    first: &mut WritePageGuard<'a>,
+    mut update: impl FnMut(&mut WritePageGuard) -> Option<T>,
+    fits: impl Fn(&WritePageGuard) -> bool,
+    insert: impl FnOnce(&mut WritePageGuard) -> T,
+) -> Result<T> {
+    if let Some(result) = update(first) {
+        return Ok(result);
+    }
+    let first_page_id = first.page_id();
+    // only look for room once the record is known to be missing from every page
+    let mut room = fits(first).then_some(first_page_id);
+    let mut last: Option<WritePageGuard> = None;
+    let mut pid = C::next_page_id(first);
+    while pid != INVALID_PAGE_ID {
+        let mut page = bpm.fetch_page_write(pid)?;
+        if let Some(result) = update(&mut page) {
+            return Ok(result);
+        }
+        if room.is_none() && fits(&page) {
+            room = Some(pid);
+        }
+        pid = C::next_page_id(&page);
+        last = Some(page);
+    }
+
+    match room {
+        Some(pid) if pid == first_page_id => Ok(insert(first)),
+        Some(pid) => {
+            // the page is latched again, the first page keeping the chain latched meanwhile
+            drop(last);
+            let mut page = bpm.fetch_page_write(pid)?;
+            Ok(insert(&mut page))
+        }
+        None => {
+            let mut page = bpm.new_page_guarded()?;
+            C::init(&mut page);
+            let result = insert(&mut page);
+            let tail = last.as_mut().unwrap_or(first);
+            C::set_next_page_id(tail, page.page_id());
+            Ok(result)
+        }
+    }
+}
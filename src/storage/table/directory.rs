@@ -0,0 +1,179 @@
+use crate::common::config::{PageId, INVALID_PAGE_ID};
+use crate::common::error::Result;
+use crate::storage::buffer::{BufferPoolManager, WritePageGuard};
+use crate::storage::page::{HeaderPage, HEADER_PAGE_MAX_NAME_SIZE};
+use crate::storage::table::chain::{insert_into_chain, ChainedPage};
+use crate::RustubError;
+use std::sync::Arc;
+
+/// HeaderDirectory maps the names of tables and indexes to their root page, in a chain of header
+/// pages. A new header page is appended to the chain when none of them has room for a record, so
+/// the directory holds any number of names, each up to `HEADER_PAGE_MAX_NAME_SIZE` bytes long.
+/// Pages emptied by deletions stay in the chain, and are filled again by later insertions.
+///
+/// Every operation latches the first page of the chain for its whole duration, then the following
+/// pages in the order of the chain, so that the directory is consistent across pages.
+///
+/// THREAD SAFETY: YES
+pub struct HeaderDirectory {
+    bpm: Arc<BufferPoolManager>,
+    /// The page the chain starts with
+    first_page_id: PageId,
+}
+
+impl HeaderDirectory {
+    /// Create a directory made of a single empty header page
+    pub fn new(bpm: Arc<BufferPoolManager>) -> Result<Self> {
+        let mut guard = bpm.new_page_guarded()?;
+        let first_page_id = guard.page_id();
+        guard.as_header_page_mut().init();
+        drop(guard);
+        Ok(HeaderDirectory { bpm, first_page_id })
+    }
+
+    /// Open the directory starting with the given header page
+    pub fn open(bpm: Arc<BufferPoolManager>, first_page_id: PageId) -> Self {
+        HeaderDirectory { bpm, first_page_id }
+    }
+
+    /// Returns the id of the first header page of the directory
+    pub fn first_page_id(&self) -> PageId {
+        self.first_page_id
+    }
+
+    /// Returns the root page id of the given name, or None if it isn't in the directory
+    pub fn root_id(&self, name: &str) -> Result<Option<PageId>> {
+        let first = self.bpm.fetch_page_read(self.first_page_id)?;
+        let mut pid = first.as_header_page().next_page_id();
+        let mut root_id = first.as_header_page().root_id(name);
+        while root_id == INVALID_PAGE_ID && pid != INVALID_PAGE_ID {
+            let page = self.bpm.fetch_page_read(pid)?;
+            root_id = page.as_header_page().root_id(name);
+            pid = page.as_header_page().next_page_id();
+        }
+        Ok(Some(root_id).filter(|root_id| *root_id != INVALID_PAGE_ID))
+    }
+
+    /// Returns the names and root page ids of every record of the directory
+    pub fn records(&self) -> Result<Vec<(String, PageId)>> {
+        let first = self.bpm.fetch_page_read(self.first_page_id)?;
+        let mut records = first.as_header_page().records();
+        let mut pid = first.as_header_page().next_page_id();
+        while pid != INVALID_PAGE_ID {
+            let page = self.bpm.fetch_page_read(pid)?;
+            records.extend(page.as_header_page().records());
+            pid = page.as_header_page().next_page_id();
+        }
+        Ok(records)
+    }
+
+    /// Insert a record into the first header page with room for it, or into a new page appended to
+    /// the chain. Returns false if the name is already in the directory, and fails if the name is
+    /// longer than `HEADER_PAGE_MAX_NAME_SIZE`.
+    pub fn insert(&self, name: &str, root_id: PageId) -> Result<bool> {
+        if name.len() > HEADER_PAGE_MAX_NAME_SIZE {
+            return Err(RustubError::UntypedError(
+                "the name is too long to fit in a header page",
+            ));
+        }
+        let mut first = self.bpm.fetch_page_write(self.first_page_id)?;
+        insert_into_chain::<HeaderPage, _>(
+            &self.bpm,
+            &mut first,
+            |page| (page.as_header_page().root_id(name) != INVALID_PAGE_ID).then_some(false),
+            |page| page.as_header_page().fits(name),
+            |page| page.as_header_page_mut().insert_record(name, root_id),
+        )
+    }
+
+    /// Change the root page id of a record, returns false if the name isn't in the directory
+    pub fn update(&self, name: &str, root_id: PageId) -> Result<bool> {
+        self.modify(name, |page| {
+            page.as_header_page_mut().update_record(name, root_id)
+        })
+    }
+
+    /// Delete a record, returns false if the name isn't in the directory
+    pub fn delete(&self, name: &str) -> Result<bool> {
+        self.modify(name, |page| page.as_header_page_mut().delete_record(name))
+    }
+
+    /// Apply `f` to the header page holding the given name, if any
+    fn modify(&self, name: &str, f: impl FnOnce(&mut WritePageGuard) -> bool) -> Result<bool> {
+        let mut first = self.bpm.fetch_page_write(self.first_page_id)?;
+        if first.as_header_page().root_id(name) != INVALID_PAGE_ID {
+            return Ok(f(&mut first));
+        }
+        let mut pid = first.as_header_page().next_page_id();
+        while pid != INVALID_PAGE_ID {
+            let mut page = self.bpm.fetch_page_write(pid)?;
+            if page.as_header_page().root_id(name) != INVALID_PAGE_ID {
+                return Ok(f(&mut page));
+            }
+            pid = page.as_header_page().next_page_id();
+        }
+        Ok(false)
+    }
+}
+
+impl ChainedPage for HeaderPage {
+    fn next_page_id(page: &WritePageGuard) -> PageId {
+        page.as_header_page().next_page_id()
+    }
+
+    fn set_next_page_id(page: &mut WritePageGuard, pid: PageId) {
+        page.as_header_page_mut().set_next_page_id(pid)
+    }
+
+    fn init(page: &mut WritePageGuard) {
+        page.as_header_page_mut().init()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::common::config::INVALID_PAGE_ID;
+    use crate::storage::buffer::BufferPoolManager;
+    use crate::storage::disk::InMemDiskManager;
+    use crate::storage::page::HEADER_PAGE_MAX_NAME_SIZE;
+    use crate::storage::table::HeaderDirectory;
+    use crate::RustubError;
+    use std::sync::Arc;
+
+    #[test]
+    fn chained_header_pages() {
+        let disk = Arc::new(InMemDiskManager::new());
+        let bpm = Arc::new(BufferPoolManager::new(4, disk).unwrap());
+        let directory = HeaderDirectory::new(bpm.clone()).unwrap();
+        let name = |i: i32| format!("index_{:03}_{}", i, "x".repeat(200));
+        // about 18 records per page
+        for i in 0..100 {
+            assert!(directory.insert(&name(i), i + 1).unwrap());
+        }
+        assert!(!directory.insert(&name(42), 1).unwrap());
+        let longest = "x".repeat(HEADER_PAGE_MAX_NAME_SIZE + 1);
+        assert!(matches!(
+            directory.insert(&longest, 1),
+            Err(RustubError::UntypedError(_))
+        ));
+        assert_eq!(directory.records().unwrap().len(), 100);
+        let first = bpm.fetch_page_read(directory.first_page_id()).unwrap();
+        assert_ne!(first.as_header_page().next_page_id(), INVALID_PAGE_ID);
+        drop(first);
+
+        assert!(directory.update(&name(99), 1000).unwrap());
+        assert!(directory.delete(&name(3)).unwrap());
+        assert!(!directory.delete(&name(3)).unwrap());
+        assert_eq!(directory.root_id(&name(99)).unwrap(), Some(1000));
+        assert_eq!(directory.root_id(&name(3)).unwrap(), None);
+        assert_eq!(directory.root_id(&name(50)).unwrap(), Some(51));
+
+        // the space freed in the first page is reused
+        assert!(directory.insert("short", 7).unwrap());
+        let first = bpm.fetch_page_read(directory.first_page_id()).unwrap();
+        assert_eq!(first.as_header_page().root_id("short"), 7);
+        drop(first);
+        let reopened = HeaderDirectory::open(bpm, directory.first_page_id());
+        assert_eq!(reopened.records().unwrap().len(), 100);
+    }
+}
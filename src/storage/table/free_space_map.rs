@@ -0,0 +1,175 @@
+use crate::common::config::{PageId, INVALID_PAGE_ID};
+use crate::common::error::Result;
+use crate::storage::buffer::{BufferPoolManager, WritePageGuard};
+use crate::storage::page::{FreeSpaceMapPage, FREE_SPACE_MAP_PAGE_CAPACITY};
+use crate::storage::table::chain::{insert_into_chain, ChainedPage};
+use std::sync::Arc;
+
+/// FreeSpaceMap records the approximate free space of every page of a table in a chain of free
+/// space map pages, see `FreeSpaceMapPage`. It also records the last page of the table, where new
+/// pages are appended.
+///
+/// Modifications latch the first page of the chain for their whole duration, then the following
+/// pages in the order of the chain. Pages of the table may be latched while the map is latched,
+/// but not the other way around.
+///
+/// THREAD SAFETY: YES
+pub struct FreeSpaceMap {
+    bpm: Arc<BufferPoolManager>,
+    /// The page the chain starts with
+    first_page_id: PageId,
+}
+
+impl FreeSpaceMap {
+    /// Create the map of a table made of the given page, which has the given free space
+    pub fn new(
+        bpm: Arc<BufferPoolManager>,
+        table_page_id: PageId,
+        free_space: usize,
+    ) -> Result<Self> {
+        let mut guard = bpm.new_page_guarded()?;
+        let first_page_id = guard.page_id();
+        let mut page = guard.as_free_space_map_page_mut();
+        page.init(first_page_id);
+        page.set_last_page_id(table_page_id);
+        page.set_free_space(table_page_id, free_space);
+        drop(guard);
+        Ok(FreeSpaceMap { bpm, first_page_id })
+    }
+
+    /// Open the map starting with the given page
+    pub fn open(bpm: Arc<BufferPoolManager>, first_page_id: PageId) -> Self {
+        FreeSpaceMap { bpm, first_page_id }
+    }
+
+    /// Returns the id of the first page of the map
+    pub fn first_page_id(&self) -> PageId {
+        self.first_page_id
+    }
+
+    /// Returns a page of the table with at least the given free space, or None if there is none
+    pub fn find(&self, min_free_space: usize) -> Result<Option<PageId>> {
+        let mut pid = self.first_page_id;
+        while pid != INVALID_PAGE_ID {
+            let guard = self.bpm.fetch_page_read(pid)?;
+            let page = guard.as_free_space_map_page();
+            if let Some(table_page_id) = page.find(min_free_space) {
+                return Ok(Some(table_page_id));
+            }
+            pid = page.next_page_id();
+        }
+        Ok(None)
+    }
+
+    /// Returns the recorded free space of the given page of the table, or None if it isn't
+    /// recorded
+    pub fn free_space(&self, table_page_id: PageId) -> Result<Option<usize>> {
+        let mut pid = self.first_page_id;
+        while pid != INVALID_PAGE_ID {
+            let guard = self.bpm.fetch_page_read(pid)?;
+            let page = guard.as_free_space_map_page();
+            if let Some(free_space) = page.free_space(table_page_id) {
+                return Ok(Some(free_space));
+            }
+            pid = page.next_page_id();
+        }
+        Ok(None)
+    }
+
+    /// Record the free space of the given page of the table
+    pub fn update(&self, table_page_id: PageId, free_space: usize) -> Result<()> {
+        let mut first = self.bpm.fetch_page_write(self.first_page_id)?;
+        self.record(&mut first, table_page_id, free_space)
+    }
+
+    /// Append a page to the table, while the map is latched so that the last page doesn't change
+    /// meanwhile. `append` is given the last page of the table, and returns the page it appended
+    /// with its free space, along with anything else for the caller.
+    pub fn append_page<T>(
+        &self,
+        append: impl FnOnce(PageId) -> Result<(PageId, usize, T)>,
+    ) -> Result<T> {
+        let mut first = self.bpm.fetch_page_write(self.first_page_id)?;
+        let last_page_id = first.as_free_space_map_page().last_page_id();
+        let (pid, free_space, result) = append(last_page_id)?;
+        first.as_free_space_map_page_mut().set_last_page_id(pid);
+        self.record(&mut first, pid, free_space)?;
+        Ok(result)
+    }
+
+    /// Record the free space of a page of the table in the page of the map which already records
+    /// it, or else in the first one with room, a new page being appended to the chain if needed
+    fn record<'a>(
+        &'a self,
+        first: &mut WritePageGuard<'a>,
+        table_page_id: PageId,
+        free_space: usize,
+    ) -> Result<()> {
+        insert_into_chain::<FreeSpaceMapPage, _>(
+            &self.bpm,
+            first,
+            |page| {
+                let mut page = page.as_free_space_map_page_mut();
+                page.free_space(table_page_id)?;
+                page.set_free_space(table_page_id, free_space);
+                Some(())
+            },
+            |page| page.as_free_space_map_page().entry_count() < FREE_SPACE_MAP_PAGE_CAPACITY,
+            |page| {
+                page.as_free_space_map_page_mut()
+                    .set_free_space(table_page_id, free_space);
+            },
+        )
+    }
+}
+
+impl ChainedPage for FreeSpaceMapPage {
+    fn next_page_id(page: &WritePageGuard) -> PageId {
+        page.as_free_space_map_page().next_page_id()
+    }
+
+    fn set_next_page_id(page: &mut WritePageGuard, pid: PageId) {
+        page.as_free_space_map_page_mut().set_next_page_id(pid)
+    }
+
+    fn init(page: &mut WritePageGuard) {
+        let pid = page.page_id();
+        page.as_free_space_map_page_mut().init(pid)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::common::config::INVALID_PAGE_ID;
+    use crate::storage::buffer::BufferPoolManager;
+    use crate::storage::disk::InMemDiskManager;
+    use crate::storage::page::FREE_SPACE_MAP_PAGE_CAPACITY;
+    use crate::storage::table::FreeSpaceMap;
+    use std::sync::Arc;
+
+    #[test]
+    fn chained_map_pages() {
+        let disk = Arc::new(InMemDiskManager::new());
+        let bpm = Arc::new(BufferPoolManager::new(4, disk).unwrap());
+        let fsm = FreeSpaceMap::new(bpm.clone(), 10_000, 0).unwrap();
+        let last = 10_000 + FREE_SPACE_MAP_PAGE_CAPACITY as i32 + 10;
+        for pid in 10_001..last {
+            fsm.update(pid, 64).unwrap();
+        }
+        let first = bpm.fetch_page_read(fsm.first_page_id()).unwrap();
+        let second_page_id = first.as_free_space_map_page().next_page_id();
+        assert_ne!(second_page_id, INVALID_PAGE_ID);
+        drop(first);
+
+        // a page recorded in the second page of the map is updated in place
+        fsm.update(last - 1, 512).unwrap();
+        assert_eq!(fsm.free_space(last - 1).unwrap(), Some(512));
+        assert_eq!(fsm.find(512).unwrap(), Some(last - 1));
+        let second = bpm.fetch_page_read(second_page_id).unwrap();
+        assert_eq!(second.as_free_space_map_page().entry_count(), 10);
+        assert_eq!(
+            second.as_free_space_map_page().next_page_id(),
+            INVALID_PAGE_ID
+        );
+    }
+}
@@ -1,6 +1,16 @@
+mod chain;
+mod directory;
+mod free_space_map;
+mod overflow;
+mod scan;
+mod spill;
+mod table_heap;
 mod tuple;
 
-pub use tuple::Tuple;
-
-/// TableHeap represents a physical table on disk. This is just a doubly-linked list of pages
-pub struct TableHeap;
+pub use directory::HeaderDirectory;
+pub use free_space_map::FreeSpaceMap;
+pub use overflow::OverflowPointer;
+pub use scan::{ScanOptions, TablePageIter};
+pub use spill::{SpillFile, SpillIter};
+pub use table_heap::{LazyTuple, TableHeap, INLINE_TUPLE_MAX_SIZE};
+pub use tuple::{TmpTuple, Tuple};
@@ -0,0 +1,119 @@
+use crate::common::config::{PageId, INVALID_PAGE_ID};
+use crate::common::error::Result;
+use crate::storage::buffer::BufferPoolManager;
+use crate::storage::page::OVERFLOW_PAGE_CAPACITY;
+use crate::storage::table::Tuple;
+use crate::RustubError;
+use bytes::{Buf, BufMut};
+
+/// OverflowPointer is what a table page holds in place of a tuple stored out of line: the first
+/// page of the chain of overflow pages holding the tuple, and the size of the tuple.
+///
+/// Format (size in byte):
+/// ------------------------------------
+/// | first page id (4) | size (4) |
+/// ------------------------------------
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OverflowPointer {
+    first_page_id: PageId,
+    size: u32,
+}
+
+impl OverflowPointer {
+    /// The size of a pointer stored in a table page
+    pub const SIZE: usize = 8;
+
+    /// Returns the first overflow page of the chain
+    pub fn first_page_id(&self) -> PageId {
+        self.first_page_id
+    }
+
+    /// Returns the size of the tuple stored in the chain
+    pub fn size(&self) -> usize {
+        self.size as usize
+    }
+
+    /// Store the given tuple in a new chain of overflow pages
+    pub(crate) fn write(bpm: &BufferPoolManager, tuple: &Tuple) -> Result<Self> {
+        assert!(!tuple.is_empty());
+        // the chain is built from its end, so every page is complete once it's been created, and
+        // a failure leaves a valid chain to be freed
+        let mut next_page_id = INVALID_PAGE_ID;
+        for chunk in tuple.data().chunks(OVERFLOW_PAGE_CAPACITY).rev() {
+            let mut guard = match bpm.new_page_guarded() {
+                Ok(guard) => guard,
+                Err(e) => {
+                    OverflowPointer::free_chain(bpm, next_page_id)?;
+                    return Err(e);
+                }
+            };
+            let pid = guard.page_id();
+            let mut page = guard.as_overflow_page_mut();
+            page.init(pid);
+            page.set_next_page_id(next_page_id);
+            page.set_payload(chunk);
+            next_page_id = pid;
+        }
+        Ok(OverflowPointer {
+            first_page_id: next_page_id,
+            size: tuple.len() as u32,
+        })
+    }
+
+    /// Read the tuple stored in the chain
+    pub(crate) fn read(&self, bpm: &BufferPoolManager) -> Result<Tuple> {
+        let mut data = Vec::with_capacity(self.size());
+        let mut pid = self.first_page_id;
+        while data.len() < self.size() {
+            if pid == INVALID_PAGE_ID {
+                return Err(RustubError::PageCorrupted(
+                    self.first_page_id,
+                    "overflow chain shorter than its tuple",
+                ));
+            }
+            let guard = bpm.fetch_page_read(pid)?;
+            let page = guard.as_overflow_page();
+            data.extend_from_slice(page.payload());
+            pid = page.next_page_id();
+        }
+        data.truncate(self.size());
+        Ok(Tuple::new(data))
+    }
+
+    /// Delete the pages of the chain
+    pub(crate) fn free(&self, bpm: &BufferPoolManager) -> Result<()> {
+        OverflowPointer::free_chain(bpm, self.first_page_id)
+    }
+
+    fn free_chain(bpm: &BufferPoolManager, first_page_id: PageId) -> Result<()> {
+        let mut pid = first_page_id;
+        while pid != INVALID_PAGE_ID {
+            let next_page_id = bpm.fetch_page_read(pid)?.as_overflow_page().next_page_id();
+            if !bpm.delete_page(pid)? {
+                error!("overflow page {} is still pinned, it can't be freed", pid);
+            }
+            pid = next_page_id;
+        }
+        Ok(())
+    }
+
+    /// Returns the pointer as stored in a table page
+    pub(crate) fn to_tuple(self) -> Tuple {
+        let mut data = Vec::with_capacity(OverflowPointer::SIZE);
+        data.put_i32(self.first_page_id);
+        data.put_u32(self.size);
+        Tuple::new(data)
+    }
+
+    /// Read a pointer stored in a table page, or None if the tuple isn't a pointer
+    pub(crate) fn from_tuple(tuple: &Tuple) -> Option<Self> {
+        if tuple.len() != OverflowPointer::SIZE {
+            return None;
+        }
+        let mut data = tuple.data();
+        Some(OverflowPointer {
+            first_page_id: data.get_i32(),
+            size: data.get_u32(),
+        })
+    }
+}
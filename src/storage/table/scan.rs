@@ -0,0 +1,280 @@
+use crate::common::config::{PageId, INVALID_PAGE_ID};
+use crate::common::error::{IOContext, Result};
+use crate::storage::buffer::{BufferPoolManager, BufferRing, ReadPageGuard};
+use crate::storage::page::TablePage;
+use crate::RustubError;
+use std::collections::VecDeque;
+use std::sync::mpsc::{channel, Receiver, Sender, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// Options of a scan of the pages of a table
+#[derive(Debug, Clone, Copy)]
+pub struct ScanOptions {
+    /// Number of frames of the private ring the scan reads its pages into, or 0 to read them into
+    /// the pool like any other page. The ring should be larger than the read-ahead distance, or
+    /// prefetched pages may be evicted before the scan gets to them.
+    pub ring_size: usize,
+    /// Number of pages fetched ahead of the scan in the background, or 0 to disable read-ahead
+    pub read_ahead: usize,
+}
+
+impl Default for ScanOptions {
+    fn default() -> Self {
+        ScanOptions {
+            ring_size: 16,
+            read_ahead: 4,
+        }
+    }
+}
+
+/// TablePageIter goes through the pages of a table, following their `next_page_id` chain. Every
+/// page is returned latched for reading.
+pub struct TablePageIter<'a> {
+    bpm: &'a Arc<BufferPoolManager>,
+    /// The page to be returned next, or INVALID_PAGE_ID at the end of the table
+    next_page_id: PageId,
+    ring: Option<Arc<Mutex<BufferRing>>>,
+    read_ahead: Option<ReadAhead>,
+}
+
+impl<'a> TablePageIter<'a> {
+    pub(crate) fn new(
+        bpm: &'a Arc<BufferPoolManager>,
+        first_page_id: PageId,
+        options: ScanOptions,
+    ) -> Result<Self> {
+        let ring = match options.ring_size {
+            0 => None,
+            size => Some(Arc::new(Mutex::new(BufferRing::new(size)))),
+        };
+        let read_ahead = match options.read_ahead {
+            0 => None,
+            distance => Some(ReadAhead::start(
+                bpm.clone(),
+                ring.clone(),
+                first_page_id,
+                distance,
+            )?),
+        };
+        Ok(TablePageIter {
+            bpm,
+            next_page_id: first_page_id,
+            ring,
+            read_ahead,
+        })
+    }
+}
+
+impl<'a> Iterator for TablePageIter<'a> {
+    type Item = Result<ReadPageGuard<'a>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.next_page_id == INVALID_PAGE_ID {
+            return None;
+        }
+        let pid = self.next_page_id;
+        let bpm: &'a BufferPoolManager = self.bpm;
+        let result = match &self.ring {
+            Some(ring) => bpm.fetch_page_read_in_ring(pid, &mut ring.lock().unwrap()),
+            None => bpm.fetch_page_read(pid),
+        };
+        match result {
+            Ok(guard) => {
+                self.next_page_id = guard.as_table_page().next_page_id();
+                if let Some(read_ahead) = &self.read_ahead {
+                    read_ahead.advance(self.next_page_id);
+                }
+                Some(Ok(guard))
+            }
+            Err(e) => {
+                self.next_page_id = INVALID_PAGE_ID;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+/// ReadAhead fetches the pages of a scan ahead of it from a background thread, so that the scan
+/// rarely waits for a read. It follows the chain of pages on its own, staying at most `distance`
+/// pages ahead of the scan. It stops when the scan is dropped.
+struct ReadAhead {
+    /// Tells the worker the next page of the scan every time the scan moves on
+    progress: Option<Sender<PageId>>,
+    worker: Option<thread::JoinHandle<()>>,
+}
+
+impl ReadAhead {
+    fn start(
+        bpm: Arc<BufferPoolManager>,
+        ring: Option<Arc<Mutex<BufferRing>>>,
+        first_page_id: PageId,
+        distance: usize,
+    ) -> Result<Self> {
+        let (progress, scan) = channel();
+        let worker = thread::Builder::new()
+            .name("read-ahead".to_string())
+            .spawn(move || ReadAhead::work(bpm, ring, first_page_id, distance, scan))
+            .map_err(|e| RustubError::IOError(e, IOContext::Other("can't start read-ahead")))?;
+        Ok(ReadAhead {
+            progress: Some(progress),
+            worker: Some(worker),
+        })
+    }
+
+    fn advance(&self, next_page_id: PageId) {
+        if let Some(progress) = &self.progress {
+            // the worker may have stopped already
+            let _ = progress.send(next_page_id);
+        }
+    }
+
+    fn work(
+        bpm: Arc<BufferPoolManager>,
+        ring: Option<Arc<Mutex<BufferRing>>>,
+        first_page_id: PageId,
+        distance: usize,
+        scan: Receiver<PageId>,
+    ) {
+        let mut cursor = first_page_id;
+        // the pages fetched and not reached by the scan yet, in their order in the table
+        let mut ahead: VecDeque<PageId> = VecDeque::new();
+        loop {
+            // catch up with the scan, and wait for it once far enough ahead
+            let progress = match ahead.len() < distance && cursor != INVALID_PAGE_ID {
+                true => match scan.try_recv() {
+                    Ok(next_page_id) => Some(next_page_id),
+                    Err(TryRecvError::Empty) => None,
+                    Err(TryRecvError::Disconnected) => return,
+                },
+                false => match scan.recv() {
+                    Ok(next_page_id) => Some(next_page_id),
+                    Err(_) => return,
+                },
+            };
+            match progress {
+                Some(next_page_id) => match ahead.iter().position(|pid| *pid == next_page_id) {
+                    Some(reached) => {
+                        ahead.drain(..reached);
+                    }
+                    // the scan has reached the cursor, or overtaken it
+                    None => {
+                        ahead.clear();
+                        cursor = next_page_id;
+                    }
+                },
+                None => match ReadAhead::prefetch(&bpm, ring.as_deref(), cursor) {
+                    Ok(next_page_id) => {
+                        ahead.push_back(cursor);
+                        cursor = next_page_id;
+                    }
+                    // the scan reports the error once it gets to the page
+                    Err(_) => cursor = INVALID_PAGE_ID,
+                },
+            }
+        }
+    }
+
+    /// Bring the given page into the pool, and return the id of the page after it
+    fn prefetch(
+        bpm: &BufferPoolManager,
+        ring: Option<&Mutex<BufferRing>>,
+        pid: PageId,
+    ) -> Result<PageId> {
+        let page = match ring {
+            Some(ring) => bpm.fetch_page_in_ring(pid, &mut ring.lock().unwrap())?,
+            None => bpm.fetch_page(pid)?,
+        };
+        let next_page_id = TablePage::from_page(&*page.read().unwrap()).next_page_id();
+        bpm.unpin_page(pid, false);
+        Ok(next_page_id)
+    }
+}
+
+impl Drop for ReadAhead {
+    fn drop(&mut self) {
+        drop(self.progress.take());
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::common::config::PageId;
+    use crate::storage::buffer::BufferPoolManager;
+    use crate::storage::disk::{DiskManager, InMemDiskManager};
+    use crate::storage::page::Page;
+    use crate::storage::table::{ScanOptions, TableHeap};
+    use std::sync::Arc;
+
+    /// Create a table of `num_pages` pages, whose first byte is their position in the table, and
+    /// write them to disk
+    fn create_table(bpm: &Arc<BufferPoolManager>, num_pages: usize) -> TableHeap {
+        let table = TableHeap::new(bpm.clone()).unwrap();
+        let mut last = bpm.fetch_page_write(table.first_page_id()).unwrap();
+        for i in 1..num_pages {
+            let mut page = bpm.new_page_guarded().unwrap();
+            let pid = page.page_id();
+            page.as_table_page_mut().init(pid, last.page_id());
+            page.data_mut()[100] = i as u8;
+            last.as_table_page_mut().set_next_page_id(pid);
+            last = page;
+        }
+        drop(last);
+        bpm.flush_all().unwrap();
+        table
+    }
+
+    fn scan(table: &TableHeap, options: ScanOptions) -> Vec<u8> {
+        table
+            .scan_pages(options)
+            .unwrap()
+            .map(|page| page.unwrap().data()[100])
+            .collect()
+    }
+
+    #[test]
+    fn scan_through_ring() {
+        let disk = Arc::new(InMemDiskManager::new());
+        let bpm = Arc::new(BufferPoolManager::new(8, disk.clone()).unwrap());
+        let table = create_table(&bpm, 20);
+        let expected: Vec<u8> = (0..20).collect();
+
+        // the working set of the pool
+        let hot: Vec<PageId> = (0..4)
+            .map(|_| bpm.new_page_guarded().unwrap().page_id())
+            .collect();
+        let options = ScanOptions {
+            ring_size: 3,
+            read_ahead: 0,
+        };
+        assert_eq!(scan(&table, options), expected);
+        assert!(hot.iter().all(|pid| bpm.pin_count(*pid) == Some(0)));
+
+        // without a ring, the scan pushes the working set out of the pool
+        let options = ScanOptions {
+            ring_size: 0,
+            read_ahead: 0,
+        };
+        assert_eq!(scan(&table, options), expected);
+        assert!(hot.iter().all(|pid| bpm.pin_count(*pid).is_none()));
+    }
+
+    #[test]
+    fn read_ahead() {
+        let disk = Arc::new(InMemDiskManager::new());
+        let bpm = Arc::new(BufferPoolManager::new(8, disk.clone()).unwrap());
+        let table = create_table(&bpm, 30);
+        let reads = disk.metrics().page_reads.count;
+        let options = ScanOptions {
+            ring_size: 6,
+            read_ahead: 3,
+        };
+        assert_eq!(scan(&table, options), (0..30).collect::<Vec<u8>>());
+        // prefetched pages are still in the pool when the scan gets to them
+        assert!(disk.metrics().page_reads.count - reads <= 30);
+        assert_eq!(scan(&table, ScanOptions::default()).len(), 30);
+    }
+}
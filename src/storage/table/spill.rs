@@ -0,0 +1,181 @@
+use crate::common::config::PageId;
+use crate::common::error::Result;
+use crate::storage::buffer::BufferPoolManager;
+use crate::storage::page::TMP_TUPLE_MAX_SIZE;
+use crate::storage::table::{TmpTuple, Tuple};
+use crate::RustubError;
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+/// SpillFile holds the intermediate results of an operator which don't fit in memory, e.g. a
+/// sorted run or a partition of a hash join, in temporary pages of the buffer pool. Tuples are
+/// appended, then read back in order or by their location. Pages are unpinned between two calls,
+/// so the pool writes them to disk when it needs their frames.
+///
+/// The pages are deleted when the file is dropped.
+///
+/// THREAD SAFETY: NO
+pub struct SpillFile {
+    bpm: Arc<BufferPoolManager>,
+    /// The pages of the file, in the order they have been filled
+    pages: Vec<PageId>,
+    num_tuples: usize,
+}
+
+impl SpillFile {
+    /// Create an empty file, which doesn't take any page until a tuple is appended
+    pub fn new(bpm: Arc<BufferPoolManager>) -> Self {
+        SpillFile {
+            bpm,
+            pages: Vec::new(),
+            num_tuples: 0,
+        }
+    }
+
+    /// Returns the number of tuples of the file
+    pub fn len(&self) -> usize {
+        self.num_tuples
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.num_tuples == 0
+    }
+
+    /// Returns the number of pages of the file
+    pub fn num_pages(&self) -> usize {
+        self.pages.len()
+    }
+
+    /// Append a tuple to the file, in a new page if the last one is full, and return where it has
+    /// been stored. Fails if the tuple is larger than `TMP_TUPLE_MAX_SIZE`.
+    pub fn append(&mut self, tuple: &Tuple) -> Result<TmpTuple> {
+        if tuple.len() > TMP_TUPLE_MAX_SIZE {
+            return Err(RustubError::UntypedError(
+                "the tuple is too large to fit in a temporary page",
+            ));
+        }
+        if let Some(&pid) = self.pages.last() {
+            let mut page = self.bpm.fetch_page_write(pid)?;
+            if let Some(tmp_tuple) = page.as_tmp_tuple_page_mut().insert_tuple(tuple) {
+                self.num_tuples += 1;
+                return Ok(tmp_tuple);
+            }
+        }
+        let mut page = self.bpm.new_page_guarded()?;
+        let pid = page.page_id();
+        let mut tmp_page = page.as_tmp_tuple_page_mut();
+        tmp_page.init(pid);
+        let tmp_tuple = tmp_page.insert_tuple(tuple).unwrap();
+        self.pages.push(pid);
+        self.num_tuples += 1;
+        Ok(tmp_tuple)
+    }
+
+    /// Returns the tuple stored at the given location, or None if it isn't a tuple of the file
+    pub fn get(&self, tmp_tuple: TmpTuple) -> Result<Option<Tuple>> {
+        if !self.pages.contains(&tmp_tuple.page_id()) {
+            return Ok(None);
+        }
+        let page = self.bpm.fetch_page_read(tmp_tuple.page_id())?;
+        Ok(page.as_tmp_tuple_page().get_tuple(tmp_tuple))
+    }
+
+    /// Read the tuples of the file back, in the order they have been appended
+    pub fn iter(&self) -> SpillIter<'_> {
+        SpillIter {
+            file: self,
+            next_page: 0,
+            tuples: VecDeque::new(),
+        }
+    }
+}
+
+impl Drop for SpillFile {
+    fn drop(&mut self) {
+        for pid in self.pages.drain(..) {
+            match self.bpm.delete_page(pid) {
+                Ok(true) => {}
+                Ok(false) => error!("temporary page {} is still pinned", pid),
+                Err(e) => error!("IO error while deleting temporary page {}: {}", pid, e),
+            }
+        }
+    }
+}
+
+/// SpillIter reads the tuples of a `SpillFile` back a page at a time, so that a page is only
+/// pinned while its tuples are copied out of it
+pub struct SpillIter<'a> {
+    file: &'a SpillFile,
+    /// The position in the file of the page to be read next
+    next_page: usize,
+    /// The tuples of the last page read, not returned yet
+    tuples: VecDeque<Tuple>,
+}
+
+impl Iterator for SpillIter<'_> {
+    type Item = Result<Tuple>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.tuples.is_empty() {
+            let &pid = self.file.pages.get(self.next_page)?;
+            self.next_page += 1;
+            let page = match self.file.bpm.fetch_page_read(pid) {
+                Ok(page) => page,
+                Err(e) => {
+                    self.next_page = self.file.pages.len();
+                    return Some(Err(e));
+                }
+            };
+            let tmp_page = page.as_tmp_tuple_page();
+            self.tuples = tmp_page
+                .tuples()
+                .into_iter()
+                .filter_map(|tmp_tuple| tmp_page.get_tuple(tmp_tuple))
+                .collect();
+        }
+        self.tuples.pop_front().map(Ok)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::storage::buffer::BufferPoolManager;
+    use crate::storage::disk::{DiskManager, InMemDiskManager};
+    use crate::storage::page::TMP_TUPLE_MAX_SIZE;
+    use crate::storage::table::{SpillFile, Tuple};
+    use crate::RustubError;
+    use std::sync::Arc;
+
+    #[test]
+    fn spill_to_disk() {
+        let disk = Arc::new(InMemDiskManager::new());
+        let bpm = Arc::new(BufferPoolManager::new(3, disk.clone()).unwrap());
+        let mut file = SpillFile::new(bpm.clone());
+        let tuple = |i: usize| Tuple::new(format!("tuple {}", i).repeat(i % 50 + 1).into_bytes());
+        let locations: Vec<_> = (0..1000).map(|i| file.append(&tuple(i)).unwrap()).collect();
+        assert_eq!(file.len(), 1000);
+        // the file is much larger than the pool
+        assert!(file.num_pages() > 50);
+        assert!(disk.metrics().page_writes.count > 0);
+
+        let tuples: Vec<Tuple> = file.iter().map(|tuple| tuple.unwrap()).collect();
+        assert_eq!(tuples, (0..1000).map(tuple).collect::<Vec<_>>());
+        assert_eq!(file.get(locations[123]).unwrap(), Some(tuple(123)));
+
+        // a tuple larger than a page is refused
+        let too_large = Tuple::new(vec![0; TMP_TUPLE_MAX_SIZE + 1]);
+        assert!(matches!(
+            file.append(&too_large),
+            Err(RustubError::UntypedError(_))
+        ));
+        assert_eq!(file.len(), 1000);
+        let largest = Tuple::new(vec![1; TMP_TUPLE_MAX_SIZE]);
+        let location = file.append(&largest).unwrap();
+        assert_eq!(file.get(location).unwrap(), Some(largest));
+
+        // the pages are given back to the disk
+        let pages = file.pages.clone();
+        drop(file);
+        assert!(pages.contains(&disk.allocate_page().unwrap()));
+    }
+}
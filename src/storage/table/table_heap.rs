@@ -0,0 +1,399 @@
+use crate::common::config::{PageId, INVALID_PAGE_ID, PAGE_SIZE};
+use crate::common::error::Result;
+use crate::common::rid::RecordId;
+use crate::storage::buffer::{BufferPoolManager, WritePageGuard};
+use crate::storage::page::TABLE_PAGE_SLOT_SIZE;
+use crate::storage::table::{FreeSpaceMap, OverflowPointer, ScanOptions, TablePageIter, Tuple};
+use crate::RustubError;
+use std::sync::Arc;
+
+/// Tuples larger than this are stored out of line in overflow pages, so that a table page always
+/// holds several tuples
+pub const INLINE_TUPLE_MAX_SIZE: usize = PAGE_SIZE / 4;
+
+/// TableHeap represents a physical table on disk. This is just a doubly-linked list of pages.
+///
+/// The free space of every page is recorded in a `FreeSpaceMap`, so that an insert goes straight
+/// to a page with enough room, or else appends a page to the table. The map is updated after
+/// inserts and applied deletions.
+///
+/// A tuple larger than `INLINE_TUPLE_MAX_SIZE` is stored in a chain of overflow pages, the table
+/// page only holding an `OverflowPointer` to it. The chain is read when the tuple is fetched, and
+/// freed when its deletion is applied.
+pub struct TableHeap {
+    bpm: Arc<BufferPoolManager>,
+    /// The page the list starts with
+    first_page_id: PageId,
+    free_space_map: FreeSpaceMap,
+}
+
+impl TableHeap {
+    /// Create a table made of a single empty page
+    pub fn new(bpm: Arc<BufferPoolManager>) -> Result<Self> {
+        let mut guard = bpm.new_page_guarded()?;
+        let first_page_id = guard.page_id();
+        guard
+            .as_table_page_mut()
+            .init(first_page_id, INVALID_PAGE_ID);
+        let free_space = guard.as_table_page().free_space_remaining();
+        drop(guard);
+        let free_space_map = FreeSpaceMap::new(bpm.clone(), first_page_id, free_space)?;
+        Ok(TableHeap {
+            bpm,
+            first_page_id,
+            free_space_map,
+        })
+    }
+
+    /// Open the table starting with the given page, whose free space is recorded in the free space
+    /// map starting with the given page
+    pub fn open(
+        bpm: Arc<BufferPoolManager>,
+        first_page_id: PageId,
+        free_space_map_page_id: PageId,
+    ) -> Self {
+        let free_space_map = FreeSpaceMap::open(bpm.clone(), free_space_map_page_id);
+        TableHeap {
+            bpm,
+            first_page_id,
+            free_space_map,
+        }
+    }
+
+    /// Returns the id of the first page of the table
+    pub fn first_page_id(&self) -> PageId {
+        self.first_page_id
+    }
+
+    /// Returns the map of the free space of the pages of the table
+    pub fn free_space_map(&self) -> &FreeSpaceMap {
+        &self.free_space_map
+    }
+
+    /// Returns the buffer pool the pages of the table are read through
+    pub fn buffer_pool(&self) -> &Arc<BufferPoolManager> {
+        &self.bpm
+    }
+
+    /// Scan the pages of the table in the order of their list
+    pub fn scan_pages(&self, options: ScanOptions) -> Result<TablePageIter<'_>> {
+        TablePageIter::new(&self.bpm, self.first_page_id, options)
+    }
+
+    /// Insert a tuple into a page with enough free space according to the free space map, or into a
+    /// new page appended to the table, and return its record id. Fails if the tuple is empty.
+    pub fn insert_tuple(&self, tuple: &Tuple) -> Result<RecordId> {
+        if tuple.is_empty() {
+            return Err(RustubError::UntypedError(
+                "an empty tuple can't be stored in a table page",
+            ));
+        }
+        if tuple.len() <= INLINE_TUPLE_MAX_SIZE {
+            return self.insert_stored_tuple(tuple, false);
+        }
+        let pointer = OverflowPointer::write(&self.bpm, tuple)?;
+        self.insert_stored_tuple(&pointer.to_tuple(), true)
+            .or_else(|e| {
+                pointer.free(&self.bpm)?;
+                Err(e)
+            })
+    }
+
+    /// Insert what a table page stores for a tuple, i.e. either the tuple or a pointer to it
+    fn insert_stored_tuple(&self, stored: &Tuple, overflow: bool) -> Result<RecordId> {
+        let insert = |guard: &mut WritePageGuard| {
+            let mut page = guard.as_table_page_mut();
+            match overflow {
+                true => page.insert_overflow_tuple(stored),
+                false => page.insert_tuple(stored),
+            }
+        };
+        let needed = stored.len() + TABLE_PAGE_SLOT_SIZE;
+        // the map may be out of date, but a page without enough room is recorded with its actual
+        // free space once tried, so it isn't tried again
+        while let Some(pid) = self.free_space_map.find(needed)? {
+            let mut guard = self.bpm.fetch_page_write(pid)?;
+            let rid = insert(&mut guard);
+            let free_space = guard.as_table_page().free_space_remaining();
+            drop(guard);
+            self.free_space_map.update(pid, free_space)?;
+            if let Some(rid) = rid {
+                return Ok(rid);
+            }
+        }
+        self.free_space_map.append_page(|last_page_id| {
+            let mut guard = self.bpm.new_page_guarded()?;
+            let pid = guard.page_id();
+            guard.as_table_page_mut().init(pid, last_page_id);
+            let mut last = self.bpm.fetch_page_write(last_page_id)?;
+            last.as_table_page_mut().set_next_page_id(pid);
+            drop(last);
+            // an inline tuple always fits in an empty page
+            let rid = insert(&mut guard).unwrap();
+            Ok((pid, guard.as_table_page().free_space_remaining(), rid))
+        })
+    }
+
+    /// Returns the given tuple, reading it from its overflow pages if it's stored out of line, or
+    /// None if there is no such tuple
+    pub fn get_tuple(&self, rid: RecordId) -> Result<Option<Tuple>> {
+        match self.get_lazy(rid)? {
+            Some(tuple) => tuple.fetch().map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the given tuple without reading its overflow pages, or None if there is no such
+    /// tuple
+    pub fn get_lazy(&self, rid: RecordId) -> Result<Option<LazyTuple<'_>>> {
+        let guard = self.bpm.fetch_page_read(rid.page_id())?;
+        let page = guard.as_table_page();
+        let stored = match page.get_tuple(rid) {
+            Some(stored) => stored,
+            None => return Ok(None),
+        };
+        let stored = match page.is_overflow(rid) {
+            true => StoredTuple::Overflow(OverflowPointer::from_tuple(&stored).ok_or(
+                RustubError::PageCorrupted(rid.page_id(), "invalid overflow pointer"),
+            )?),
+            false => StoredTuple::Inline(stored),
+        };
+        Ok(Some(LazyTuple {
+            heap: self,
+            rid,
+            stored,
+        }))
+    }
+
+    /// Mark the given tuple as deleted, see `TablePage::mark_delete`
+    pub fn mark_delete(&self, rid: RecordId) -> Result<bool> {
+        let mut guard = self.bpm.fetch_page_write(rid.page_id())?;
+        Ok(guard.as_table_page_mut().mark_delete(rid))
+    }
+
+    /// Undo the deletion of a tuple marked as deleted, see `TablePage::rollback_delete`
+    pub fn rollback_delete(&self, rid: RecordId) -> Result<bool> {
+        let mut guard = self.bpm.fetch_page_write(rid.page_id())?;
+        Ok(guard.as_table_page_mut().rollback_delete(rid))
+    }
+
+    /// Remove the given tuple and free its space, including its overflow pages, which is recorded
+    /// in the free space map. Returns false if there is no such tuple.
+    pub fn apply_delete(&self, rid: RecordId) -> Result<bool> {
+        let mut guard = self.bpm.fetch_page_write(rid.page_id())?;
+        let overflow = guard.as_table_page().is_overflow(rid);
+        let stored = match guard.as_table_page_mut().apply_delete(rid) {
+            Some(stored) => stored,
+            None => return Ok(false),
+        };
+        let free_space = guard.as_table_page().free_space_remaining();
+        drop(guard);
+        self.free_space_map.update(rid.page_id(), free_space)?;
+        if overflow {
+            if let Some(pointer) = OverflowPointer::from_tuple(&stored) {
+                pointer.free(&self.bpm)?;
+            }
+        }
+        Ok(true)
+    }
+}
+
+/// What a table page stores for a tuple
+enum StoredTuple {
+    Inline(Tuple),
+    Overflow(OverflowPointer),
+}
+
+/// LazyTuple is a tuple of a table whose content is only read from its overflow pages, if it's
+/// stored out of line, once it's fetched
+pub struct LazyTuple<'a> {
+    heap: &'a TableHeap,
+    rid: RecordId,
+    stored: StoredTuple,
+}
+
+impl LazyTuple<'_> {
+    /// Returns the record id of the tuple
+    pub fn rid(&self) -> RecordId {
+        self.rid
+    }
+
+    /// Returns the size of the tuple in bytes
+    pub fn len(&self) -> usize {
+        match &self.stored {
+            StoredTuple::Inline(tuple) => tuple.len(),
+            StoredTuple::Overflow(pointer) => pointer.size(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns true if the tuple is stored in overflow pages
+    pub fn is_overflow(&self) -> bool {
+        matches!(self.stored, StoredTuple::Overflow(_))
+    }
+
+    /// Returns the content of the tuple, reading its overflow pages if needed
+    pub fn fetch(self) -> Result<Tuple> {
+        let mut tuple = match self.stored {
+            StoredTuple::Inline(tuple) => tuple,
+            StoredTuple::Overflow(pointer) => pointer.read(&self.heap.bpm)?,
+        };
+        tuple.set_rid(self.rid);
+        Ok(tuple)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::storage::buffer::BufferPoolManager;
+    use crate::storage::disk::test::run_test;
+    use crate::storage::disk::{
+        DiskManager, DiskOptions, EncryptionKey, FileBasedDiskManager, InMemDiskManager,
+    };
+    use crate::storage::table::table_heap::StoredTuple;
+    use crate::storage::table::{TableHeap, Tuple};
+    use crate::RustubError;
+    use std::sync::Arc;
+
+    #[test]
+    fn overflow_tuples() {
+        let disk = Arc::new(InMemDiskManager::new());
+        let bpm = Arc::new(BufferPoolManager::new(4, disk.clone()).unwrap());
+        let table = TableHeap::new(bpm).unwrap();
+        let small = Tuple::new(vec![1; 100]);
+        let document: Vec<u8> = (0..20000).map(|i| (i % 251) as u8).collect();
+        let large = Tuple::new(document);
+        let a = table.insert_tuple(&small).unwrap();
+        let b = table.insert_tuple(&large).unwrap();
+        assert_eq!(a.page_id(), b.page_id());
+
+        // the overflow pages are only read once the tuple is fetched
+        let lazy = table.get_lazy(b).unwrap().unwrap();
+        assert!(lazy.is_overflow());
+        assert_eq!(lazy.len(), 20000);
+        let first_page_id = match lazy.stored {
+            StoredTuple::Overflow(pointer) => pointer.first_page_id(),
+            StoredTuple::Inline(_) => unreachable!(),
+        };
+        assert_eq!(lazy.fetch().unwrap().data(), large.data());
+        assert!(!table.get_lazy(a).unwrap().unwrap().is_overflow());
+        assert_eq!(table.get_tuple(a).unwrap().unwrap().data(), small.data());
+
+        // marking the tuple as deleted keeps its overflow pages, applying the deletion frees them
+        assert!(table.mark_delete(b).unwrap());
+        assert!(table.get_tuple(b).unwrap().is_none());
+        assert!(table.rollback_delete(b).unwrap());
+        assert_eq!(table.get_tuple(b).unwrap().unwrap().data(), large.data());
+        assert!(table.apply_delete(b).unwrap());
+        assert!(!table.apply_delete(b).unwrap());
+        assert!(table.get_tuple(b).unwrap().is_none());
+        // the chain was the last thing allocated, its pages are reused first
+        let reused = disk.allocate_page().unwrap();
+        assert!(reused > a.page_id() && reused <= first_page_id);
+    }
+
+    #[test]
+    fn insert_appends_pages() {
+        let disk = Arc::new(InMemDiskManager::new());
+        let bpm = Arc::new(BufferPoolManager::new(4, disk).unwrap());
+        let table = TableHeap::new(bpm).unwrap();
+        let rids: Vec<_> = (0..100u8)
+            .map(|i| table.insert_tuple(&Tuple::new(vec![i; 500])).unwrap())
+            .collect();
+        assert_ne!(rids[0].page_id(), rids[99].page_id());
+        for (i, rid) in rids.iter().enumerate() {
+            let tuple = table.get_tuple(*rid).unwrap().unwrap();
+            assert_eq!(tuple.data(), [i as u8; 500]);
+            assert_eq!(tuple.rid(), Some(*rid));
+        }
+        assert_eq!(table.scan_pages(Default::default()).unwrap().count(), 15);
+
+        // an empty tuple can't be stored
+        assert!(matches!(
+            table.insert_tuple(&Tuple::new(vec![])),
+            Err(RustubError::UntypedError(_))
+        ));
+        assert_eq!(table.scan_pages(Default::default()).unwrap().count(), 15);
+    }
+
+    #[test]
+    fn free_space_map() {
+        let disk = Arc::new(InMemDiskManager::new());
+        let bpm = Arc::new(BufferPoolManager::new(4, disk.clone()).unwrap());
+        let table = TableHeap::new(bpm.clone()).unwrap();
+        let rids: Vec<_> = (0..37u8)
+            .map(|i| table.insert_tuple(&Tuple::new(vec![i; 500])).unwrap())
+            .collect();
+        let first_page_id = table.first_page_id();
+        let fsm = table.free_space_map();
+        assert!(fsm.free_space(first_page_id).unwrap().unwrap() < 508);
+        assert_eq!(fsm.find(508).unwrap(), Some(rids[36].page_id()));
+
+        // the space freed by deletions is reused by the next insert
+        assert!(table.apply_delete(rids[3]).unwrap());
+        assert!(table.apply_delete(rids[4]).unwrap());
+        assert_eq!(fsm.find(508).unwrap(), Some(first_page_id));
+        let rid = table.insert_tuple(&Tuple::new(vec![9; 500])).unwrap();
+        assert_eq!(rid, rids[3]);
+
+        // the map is persistent
+        let fsm_page_id = fsm.first_page_id();
+        drop(table);
+        bpm.flush_all().unwrap();
+        let bpm = Arc::new(BufferPoolManager::new(4, disk).unwrap());
+        let table = TableHeap::open(bpm, first_page_id, fsm_page_id);
+        let free_space = table.free_space_map().free_space(rids[36].page_id());
+        assert!(free_space.unwrap().unwrap() > 1000);
+        // the first page still has room for the other tuple deleted
+        let rid = table.insert_tuple(&Tuple::new(vec![8; 500])).unwrap();
+        assert_eq!(rid, rids[4]);
+        assert_eq!(table.scan_pages(Default::default()).unwrap().count(), 6);
+    }
+
+    #[test]
+    fn round_trip_through_disk() {
+        for (name, encrypted) in [("table_heap_plain", false), ("table_heap_encrypted", true)] {
+            run_test(name, |db_file| {
+                let key_file = format!("{}.key", name);
+                if encrypted {
+                    EncryptionKey::generate().store(&key_file).unwrap();
+                }
+                let options = DiskOptions {
+                    key_file: encrypted.then(|| key_file.into()),
+                    ..DiskOptions::default()
+                };
+                let disk = Arc::new(FileBasedDiskManager::with_options(db_file, options).unwrap());
+                let bpm = Arc::new(BufferPoolManager::new(4, disk).unwrap());
+                let table = TableHeap::new(bpm.clone()).unwrap();
+                // the last tuple of every page ends where the disk manager's reserved end starts
+                let rids: Vec<_> = (0..40u8)
+                    .map(|i| table.insert_tuple(&Tuple::new(vec![i; 500])).unwrap())
+                    .collect();
+                bpm.flush_all().unwrap();
+                // the pool is smaller than the table, so most pages are read back from disk
+                for (i, rid) in rids.iter().enumerate() {
+                    let tuple = table.get_tuple(*rid).unwrap().unwrap();
+                    assert_eq!(tuple.data(), [i as u8; 500]);
+                }
+            });
+        }
+    }
+
+    #[test]
+    fn overflow_through_disk() {
+        run_test("overflow_through_disk", |db_file| {
+            let disk = Arc::new(FileBasedDiskManager::new(db_file).unwrap());
+            let bpm = Arc::new(BufferPoolManager::new(4, disk).unwrap());
+            let table = TableHeap::new(bpm.clone()).unwrap();
+            // the chain of overflow pages is longer than the pool, so its pages are read back from
+            // disk, every one of them full up to the reserved end
+            let large = Tuple::new((0..20 * 1024).map(|i| (i % 251) as u8).collect());
+            let rid = table.insert_tuple(&large).unwrap();
+            bpm.flush_all().unwrap();
+            assert_eq!(table.get_tuple(rid).unwrap().unwrap().data(), large.data());
+        });
+    }
+}
@@ -1 +1,66 @@
-pub struct Tuple {}
+use crate::common::config::PageId;
+use crate::common::rid::RecordId;
+
+/// Tuple is a row of a table, in the format it's stored in a table page
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Tuple {
+    /// Where the tuple is stored, once it has been inserted into or read from a table
+    rid: Option<RecordId>,
+    data: Vec<u8>,
+}
+
+impl Tuple {
+    pub fn new(data: Vec<u8>) -> Self {
+        Tuple { rid: None, data }
+    }
+
+    /// Returns the content of the tuple
+    #[inline]
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Returns the size of the tuple in bytes
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// Returns where the tuple is stored, if it has been inserted into or read from a table
+    #[inline]
+    pub fn rid(&self) -> Option<RecordId> {
+        self.rid
+    }
+
+    pub fn set_rid(&mut self, rid: RecordId) {
+        self.rid = Some(rid);
+    }
+}
+
+/// TmpTuple locates a tuple in a temporary page: the page, and the offset of the tuple in it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TmpTuple {
+    page_id: PageId,
+    offset: usize,
+}
+
+impl TmpTuple {
+    pub fn new(page_id: PageId, offset: usize) -> Self {
+        TmpTuple { page_id, offset }
+    }
+
+    #[inline]
+    pub fn page_id(&self) -> PageId {
+        self.page_id
+    }
+
+    #[inline]
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+}